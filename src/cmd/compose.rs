@@ -7,6 +7,7 @@ use command_runner::{Command, CommandRunner};
 #[cfg(test)]
 use command_runner::TestCommandRunner;
 use errors::*;
+use plugins::LifecycleEvent;
 use pod::Pod;
 use project::{PodOrService, Project};
 
@@ -35,6 +36,11 @@ impl CommandCompose for Project {
         where CR: CommandRunner,
               F: Fn(&Pod) -> bool
     {
+        // Let any lifecycle plugins know what we're doing.
+        let event = LifecycleEvent::from_compose_command(command);
+        if let Some(event) = event {
+            try!(self.plugins().before(event, self, act_on, runner));
+        }
 
         let names = match *act_on {
             args::ActOn::Named(ref names) => names.to_owned(),
@@ -72,6 +78,9 @@ impl CommandCompose for Project {
             }
         }
 
+        if let Some(event) = event {
+            try!(self.plugins().after(event, self, act_on, runner));
+        }
         Ok(())
     }
 }
@@ -139,3 +148,61 @@ fn runs_docker_compose_on_named_pods_and_services() {
 
     proj.remove_test_output().unwrap();
 }
+
+#[test]
+fn notifies_lifecycle_plugins_before_and_after_compose() {
+    use command_runner::DynCommandRunner;
+    use plugins::{Plugin, PluginLifecycle};
+    use std::ffi::OsStr;
+
+    /// A lifecycle plugin which runs `echo` for each callback.
+    struct EchoPlugin;
+
+    impl Plugin for EchoPlugin {
+        fn name(&self) -> &str {
+            "echo"
+        }
+    }
+
+    impl PluginLifecycle for EchoPlugin {
+        fn before(&self,
+                  event: LifecycleEvent,
+                  _project: &Project,
+                  _act_on: &args::ActOn,
+                  runner: &DynCommandRunner)
+                  -> Result<()> {
+            runner.build_dyn(OsStr::new("echo")).arg("before").arg(event.to_string()).exec()
+        }
+
+        fn after(&self,
+                 event: LifecycleEvent,
+                 _project: &Project,
+                 _act_on: &args::ActOn,
+                 runner: &DynCommandRunner)
+                 -> Result<()> {
+            runner.build_dyn(OsStr::new("echo")).arg("after").arg(event.to_string()).exec()
+        }
+    }
+
+    use env_logger;
+    let _ = env_logger::init();
+    let mut proj = Project::from_example("hello").unwrap();
+    proj.plugins_mut().register_lifecycle(Box::new(EchoPlugin));
+    let runner = TestCommandRunner::new();
+    proj.output().unwrap();
+
+    let opts = args::opts::Empty;
+    proj.compose(&runner, "stop", &args::ActOn::All, |_| true, &opts).unwrap();
+    assert_ran!(runner, {
+        ["echo", "before", "stop"],
+        ["docker-compose",
+         "-p",
+         "hello",
+         "-f",
+         proj.output_dir().join("pods").join("frontend.yml"),
+         "stop"],
+        ["echo", "after", "stop"]
+    });
+
+    proj.remove_test_output().unwrap();
+}
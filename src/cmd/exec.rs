@@ -6,6 +6,7 @@ use command_runner::{Command, CommandRunner};
 use command_runner::TestCommandRunner;
 use errors::*;
 use ext::service::ServiceExt;
+use plugins::LifecycleEvent;
 use project::Project;
 use util::err;
 
@@ -41,14 +42,17 @@ impl CommandExec for Project {
                 -> Result<()>
         where CR: CommandRunner
     {
+        let act_on = args::ActOn::Named(vec![service_name.to_owned()]);
         let (pod, service_name) = try!(self.service_or_err(service_name));
-        runner.build("docker-compose")
+        try!(self.plugins().before(LifecycleEvent::Exec, self, &act_on, runner));
+        try!(runner.build("docker-compose")
             .args(&try!(pod.compose_args(self, self.current_target())))
             .arg("exec")
             .args(&opts.to_args())
             .arg(service_name)
             .args(&command.to_args())
-            .exec()
+            .exec());
+        self.plugins().after(LifecycleEvent::Exec, self, &act_on, runner)
     }
 
     fn shell<CR>(&self,
@@ -66,9 +70,8 @@ impl CommandExec for Project {
             return Err(err("Can't run shell without a TTY"));
         }
 
-        let target = self.current_target();
         let (pod, service_name) = try!(self.service_or_err(service_name));
-        let service = try!(pod.service_or_err(target, service_name));
+        let service = try!(self.interpolated_service_or_err(pod, service_name));
         let shell = try!(service.shell());
         self.exec(runner, service_name, &args::Command::new(shell), opts)
     }
@@ -4,12 +4,12 @@ use rustc_serialize::json::{Json, ToJson};
 use std::collections::BTreeMap;
 #[cfg(test)]
 use std::env;
-#[cfg(test)]
 use std::fs;
 use std::io;
 use std::path::{PathBuf, Path};
 
 use errors::*;
+use plugins::{Plugin, PluginGenerate};
 use project::Project;
 use template::Template;
 use version;
@@ -18,6 +18,50 @@ use version;
 /// manually.
 const DEFAULT_TARGETS: &'static [&'static str] = &["development", "test"];
 
+/// The built-in template we use for new projects by default.
+const DEFAULT_NEW_TEMPLATE: &'static str = "new";
+
+/// Options for `generate_new`.
+#[derive(Debug, Clone)]
+pub struct NewOptions {
+    /// The template to use for our project, either the name of a built-in
+    /// template or the path to a directory.  Templates may contain a
+    /// `pods/targets/_default` child template, which is used to generate
+    /// each target in `targets`.
+    pub template: String,
+
+    /// Targets to create in addition to any created by the template.
+    pub targets: Vec<String>,
+
+    /// Should we generate `config/secrets.yml`?
+    pub secrets: bool,
+
+    /// Should we generate `config/vault.yml`?
+    pub vault: bool,
+
+    /// May we generate files into a directory which already exists,
+    /// overwriting any files with the same names?
+    pub allow_existing: bool,
+
+    /// PRIVATE: This field is a stand-in for future options.
+    /// See http://stackoverflow.com/q/39277157/12089
+    #[doc(hidden)]
+    pub _nonexhaustive: (),
+}
+
+impl Default for NewOptions {
+    fn default() -> NewOptions {
+        NewOptions {
+            template: DEFAULT_NEW_TEMPLATE.to_owned(),
+            targets: DEFAULT_TARGETS.iter().map(|t| (*t).to_owned()).collect(),
+            secrets: true,
+            vault: false,
+            allow_existing: false,
+            _nonexhaustive: (),
+        }
+    }
+}
+
 /// Interface to various file-generation commands.
 pub trait CommandGenerate {
     /// Create a new project skeleton, returning the path of the generated
@@ -36,7 +80,7 @@ pub trait CommandGenerate {
     ///       └── test
     ///           └── common.env
     /// ```
-    fn generate_new(parent_dir: &Path, name: &str) -> Result<PathBuf>;
+    fn generate_new(parent_dir: &Path, name: &str, opts: &NewOptions) -> Result<PathBuf>;
 
     /// Print our all available generators (excluding the `generate_new`
     /// generator).
@@ -46,12 +90,43 @@ pub trait CommandGenerate {
     fn generate(&self, name: &str) -> Result<()>;
 }
 
+/// Load the project template specified by `template`, which may be either
+/// the name of a built-in template or a directory, plus the child template
+/// used to generate each target, if there is one.
+fn new_templates(template: &str) -> Result<(Template, Option<Template>)> {
+    let dir = Path::new(template);
+    if dir.is_dir() {
+        let proj_tmpl = try!(Template::from_dir(template, dir));
+        let target_dir = dir.join("pods").join("targets").join("_default");
+        let target_tmpl = if target_dir.is_dir() {
+            Some(try!(Template::from_dir(format!("{}/pods/targets/_default", template),
+                                         &target_dir)))
+        } else {
+            None
+        };
+        Ok((proj_tmpl, target_tmpl))
+    } else if Template::is_builtin(template) {
+        let target_name = format!("{}/pods/targets/_default", template);
+        let target_tmpl = if Template::is_builtin(&target_name) {
+            Some(try!(Template::new(target_name)))
+        } else {
+            None
+        };
+        Ok((try!(Template::new(template)), target_tmpl))
+    } else {
+        Err(err!("no built-in template or directory named '{}'", template))
+    }
+}
+
 impl CommandGenerate for Project {
-    fn generate_new(parent_dir: &Path, name: &str) -> Result<PathBuf> {
+    fn generate_new(parent_dir: &Path, name: &str, opts: &NewOptions) -> Result<PathBuf> {
         let proj_dir = parent_dir.join(name);
+        if proj_dir.exists() && !opts.allow_existing {
+            return Err(err!("{} already exists", proj_dir.display()));
+        }
 
         // Generate our top-level files.
-        let mut proj_tmpl = try!(Template::new("new"));
+        let (mut proj_tmpl, mut target_tmpl) = try!(new_templates(&opts.template));
         let proj_info = ProjectInfo {
             name: name,
             cage_version: &version().to_string(),
@@ -59,19 +134,31 @@ impl CommandGenerate for Project {
         try!(proj_tmpl.generate(&proj_dir, &proj_info, &mut io::stdout()));
 
         // Generate a sample secrets.yml file.
-        let mut secrets_tmpl = try!(Template::new("secrets"));
-        try!(secrets_tmpl.generate(&proj_dir, &proj_info, &mut io::stdout()));
+        if opts.secrets {
+            let mut secrets_tmpl = try!(Template::new("secrets"));
+            try!(secrets_tmpl.generate(&proj_dir, &proj_info, &mut io::stdout()));
+        }
+
+        // Generate a sample vault.yml file.
+        if opts.vault {
+            let mut vault_tmpl = try!(Template::new("vault"));
+            try!(vault_tmpl.generate(&proj_dir, &proj_info, &mut io::stdout()));
+        }
 
         // Generate files for each target that uses our defaults.
-        let mut target_tmpl = try!(Template::new("new/pods/targets/_default"));
         let targets_dir = proj_dir.join("pods").join("targets");
-        for target in DEFAULT_TARGETS {
-            let target_info = TargetInfo {
-                project: &proj_info,
-                name: target,
-            };
+        for target in &opts.targets {
             let dir = targets_dir.join(target);
-            try!(target_tmpl.generate(&dir, &target_info, &mut io::stdout()));
+            if let Some(ref mut target_tmpl) = target_tmpl {
+                let target_info = TargetInfo {
+                    project: &proj_info,
+                    name: target,
+                };
+                try!(target_tmpl.generate(&dir, &target_info, &mut io::stdout()));
+            } else {
+                let mkerr = || ErrorKind::CouldNotWriteFile(dir.clone());
+                try!(fs::create_dir_all(&dir).chain_err(&mkerr));
+            }
         }
 
         Ok(proj_dir)
@@ -83,6 +170,11 @@ impl CommandGenerate for Project {
                      generator.name(),
                      generator.generator_description());
         }
+        for template in try!(self.plugins().template_generators(self)) {
+            println!("{:19} {}",
+                     template.name(),
+                     template.generator_description());
+        }
         Ok(())
     }
 
@@ -94,7 +186,7 @@ impl CommandGenerate for Project {
 #[test]
 fn generate_new_creates_a_project() {
     let cwd = env::current_dir().unwrap();
-    Project::generate_new(&cwd, "test_project").unwrap();
+    Project::generate_new(&cwd, "test_project", &NewOptions::default()).unwrap();
     let proj_dir = env::current_dir().unwrap().join("test_project");
 
     assert!(proj_dir.exists());
@@ -110,6 +202,51 @@ fn generate_new_creates_a_project() {
     fs::remove_dir_all(&proj_dir.as_path()).unwrap();
 }
 
+#[test]
+fn generate_new_honors_options() {
+    let cwd = env::current_dir().unwrap();
+    let opts = NewOptions {
+        targets: vec!["development".to_owned(), "staging".to_owned()],
+        secrets: false,
+        vault: true,
+        ..NewOptions::default()
+    };
+    let proj_dir = Project::generate_new(&cwd, "test_project_opts", &opts).unwrap();
+
+    assert!(!proj_dir.join("config").join("secrets.yml").exists());
+    assert!(proj_dir.join("config").join("vault.yml").exists());
+    let targets = proj_dir.join("pods").join("targets");
+    assert!(targets.join("development").join("common.env").exists());
+    assert!(targets.join("staging").join("common.env").exists());
+    assert!(targets.join("production").join("common.env").exists());
+    assert!(!targets.join("test").exists());
+
+    // We refuse to overwrite an existing project unless asked to.
+    assert!(Project::generate_new(&cwd, "test_project_opts", &opts).is_err());
+    let overwrite = NewOptions { allow_existing: true, ..opts };
+    Project::generate_new(&cwd, "test_project_opts", &overwrite).unwrap();
+
+    fs::remove_dir_all(&proj_dir).unwrap();
+}
+
+#[test]
+fn generate_new_can_use_a_template_directory() {
+    let cwd = env::current_dir().unwrap();
+    let opts = NewOptions {
+        template: "data/templates/test_tmpl".to_owned(),
+        ..NewOptions::default()
+    };
+    let proj_dir = Project::generate_new(&cwd, "test_project_dir", &opts).unwrap();
+
+    assert!(proj_dir.join("test.txt").exists());
+    assert!(proj_dir.join("pods").join("targets").join("test").is_dir());
+
+    fs::remove_dir_all(&proj_dir).unwrap();
+
+    let opts = NewOptions { template: "no_such_template".to_owned(), ..NewOptions::default() };
+    assert!(Project::generate_new(&cwd, "test_project_missing", &opts).is_err());
+}
+
 
 /// Information about the project we're generating.  This will be passed to
 /// our templates.
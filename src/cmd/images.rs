@@ -0,0 +1,200 @@
+//! The `images` command.
+
+use colored::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use command_runner::{Command, CommandRunner};
+#[cfg(test)]
+use command_runner::TestCommandRunner;
+use errors::*;
+use ext::service::ServiceExt;
+use image_lock::{DigestSource, ImageLock};
+use plugins::{self, Operation, PluginNew, PluginTransform};
+use plugins::transform::default_tags;
+use project::Project;
+use util;
+
+/// We implement `images` with a trait so we can put it in its own
+/// module.
+pub trait CommandImages {
+    /// Tag each image built from a locally cloned source tree with
+    /// `version`, or with the git commit ID of its source tree if no
+    /// version is given.  Then write a file for `--default-tags` to
+    /// `path`, listing tags for every image in the current target which
+    /// isn't already tagged explicitly.
+    fn images_tag<CR>(&self, runner: &CR, version: Option<&str>, path: &Path) -> Result<()>
+        where CR: CommandRunner;
+
+    /// Check that every image in the lock file at `path` still has the
+    /// digest recorded there, according to `source`.
+    fn images_verify(&self, path: &Path, source: &DigestSource) -> Result<()>;
+}
+
+impl CommandImages for Project {
+    fn images_tag<CR>(&self, runner: &CR, version: Option<&str>, path: &Path) -> Result<()>
+        where CR: CommandRunner
+    {
+        let target = self.current_target();
+        let mut tagged = BTreeMap::new();
+
+        // We only need to know which images we'd export, so apply our
+        // default tags by hand instead of running every plugin, some of
+        // which have side effects (like issuing Vault tokens).
+        let tag_plugin = try!(default_tags::Plugin::new(self));
+        for pod in self.pods().filter(|pod| pod.enabled_in(target)) {
+            let mut file = try!(self.interpolated_file(pod));
+            let ctx = plugins::Context::new(self, pod);
+            try!(tag_plugin.transform(Operation::Export, &ctx, &mut file));
+            for (name, service) in &file.services {
+                let image = match service.image {
+                    Some(ref image) => try!(image.value()).to_owned(),
+                    None => continue,
+                };
+                let untagged = image.without_tag().to_string();
+                if tagged.contains_key(&untagged) {
+                    continue;
+                }
+
+                // Default tags only apply to untagged images, so there's
+                // no point in listing images which the pod file tags
+                // explicitly.
+                let has_default_tag = self.default_tags().map_or(false, |default_tags| {
+                    default_tags.default_for(&image.without_tag()) == image
+                });
+                if image.tag.is_some() && !has_default_tag {
+                    debug!("{}/{}: {} has an explicit tag", pod.name(), name, &image);
+                    continue;
+                }
+
+                // Is this image built from a source tree we've cloned?
+                let source = try!(service.context())
+                    .and_then(|ctx| self.sources().find_by_context(ctx));
+                let tag = match source {
+                    Some(source) if source.is_available_locally(self) => {
+                        let tag = match version {
+                            Some(version) => version.to_owned(),
+                            None => try!(source.git_revision(self)),
+                        };
+                        let new_image = format!("{}:{}", &untagged, &tag);
+                        println!("Tagging {} as {}", &image, &new_image);
+                        try!(runner.build("docker")
+                            .arg("tag")
+                            .arg(image.to_string())
+                            .arg(&new_image)
+                            .exec());
+                        tag
+                    }
+                    _ => {
+                        // Otherwise, use whatever tag we'd export it with,
+                        // which we've already applied above.
+                        match image.tag {
+                            Some(tag) => tag,
+                            None => {
+                                warn!("{}/{}: {} has no tag and isn't built locally",
+                                      pod.name(),
+                                      name,
+                                      &untagged);
+                                continue;
+                            }
+                        }
+                    }
+                };
+                tagged.insert(untagged, tag);
+            }
+        }
+
+        let mut tags = format!("# Generated by `cage images tag` for target {}.\n",
+                               target.name());
+        for (image, tag) in &tagged {
+            tags.push_str(&format!("{}:{}\n", image, tag));
+        }
+        try!(util::write_if_changed(path, tags.as_bytes()));
+        Ok(())
+    }
+
+    fn images_verify(&self, path: &Path, source: &DigestSource) -> Result<()> {
+        let lock = try!(ImageLock::read_from_path(path));
+        let problems = lock.verify(source);
+        for problem in &problems {
+            println!("{}", problem.red());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(err!("{} images no longer match {}", problems.len(), path.display()))
+        }
+    }
+}
+
+#[test]
+fn tags_locally_built_images_and_writes_default_tags() {
+    use compose_yml::v2 as dc;
+    use default_tags::DefaultTags;
+    use env_logger;
+    use std::io;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    let cursor = io::Cursor::new("postgres:9.6\n");
+    proj.set_default_tags(DefaultTags::read(cursor).unwrap());
+    proj.sources().find_by_alias("rails_hello").unwrap().fake_clone_source(&proj).unwrap();
+    let runner = TestCommandRunner::new();
+    let path = proj.output_dir().join("default-tags.txt");
+    proj.images_tag(&runner, Some("build-42"), &path).unwrap();
+
+    // `faraday/rails_hello` is used by two pods, but we only tag it once.
+    assert_ran!(runner, {
+        ["docker", "tag", "faraday/rails_hello", "faraday/rails_hello:build-42"]
+    });
+
+    let written = DefaultTags::read_from_paths(vec![path.as_path()]).unwrap();
+    assert_eq!(written.default_for(&dc::Image::new("faraday/rails_hello").unwrap()),
+               dc::Image::new("faraday/rails_hello:build-42").unwrap());
+    assert_eq!(written.default_for(&dc::Image::new("postgres").unwrap()),
+               dc::Image::new("postgres:9.6").unwrap());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn tags_images_with_git_revision_by_default() {
+    use env_logger;
+    use std::fs;
+    use std::io::Read;
+    use std::process;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let source = proj.sources().find_by_alias("rails_hello").unwrap();
+    source.fake_clone_source(&proj).unwrap();
+    let git = |args: &[&str]| {
+        let status = process::Command::new("git")
+            .args(&["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(source.path(&proj))
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    git(&["init", "-q"]);
+    git(&["commit", "-q", "--allow-empty", "-m", "Initial commit"]);
+    let revision = source.git_revision(&proj).unwrap();
+    assert_eq!(revision.len(), 12);
+
+    let runner = TestCommandRunner::new();
+    let path = proj.output_dir().join("default-tags.txt");
+    proj.images_tag(&runner, None, &path).unwrap();
+    assert_ran!(runner, {
+        ["docker",
+         "tag",
+         "faraday/rails_hello",
+         format!("faraday/rails_hello:{}", &revision)]
+    });
+
+    let mut written = String::new();
+    fs::File::open(&path).unwrap().read_to_string(&mut written).unwrap();
+    assert!(written.contains(&format!("faraday/rails_hello:{}\n", &revision)));
+
+    proj.remove_test_output().unwrap();
+}
@@ -12,20 +12,32 @@
 
 pub use self::compose::CommandCompose;
 pub use self::exec::CommandExec;
-pub use self::generate::CommandGenerate;
+pub use self::generate::{CommandGenerate, NewOptions};
+pub use self::images::CommandImages;
 pub use self::logs::CommandLogs;
+pub use self::plugins::CommandPlugins;
+pub use self::port::CommandPort;
 pub use self::pull::CommandPull;
 pub use self::run::CommandRun;
+pub use self::secrets::CommandSecrets;
 pub use self::source::CommandSource;
 pub use self::status::CommandStatus;
 pub use self::up::CommandUp;
+#[cfg(feature="vault")]
+pub use self::vault::CommandVault;
 
 mod compose;
 mod exec;
 mod generate;
+mod images;
 mod logs;
+mod plugins;
+mod port;
 mod pull;
 mod run;
+mod secrets;
 mod source;
 mod status;
 mod up;
+#[cfg(feature="vault")]
+mod vault;
@@ -0,0 +1,29 @@
+//! The `plugins` command.
+
+use colored::*;
+
+use errors::*;
+use project::Project;
+
+/// We implement `plugins` with a trait so we can put it in its own
+/// module.
+pub trait CommandPlugins {
+    /// List the transform plugins which will be applied to pods in the
+    /// current target, in the order in which they will run.
+    fn plugins_list(&self) -> Result<()>;
+}
+
+impl CommandPlugins for Project {
+    fn plugins_list(&self) -> Result<()> {
+        let target = self.current_target();
+        println!("{} {}", "target".blue().bold(), target.name());
+        for transform in self.plugins().transforms() {
+            if transform.is_enabled_in(target) {
+                println!("  {}", transform.name());
+            } else {
+                println!("  {} {}", transform.name(), "(inactive)".red());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! The `port` command.
+
+use errors::*;
+use plugins::transform::ports::{PortSpec, normalize_container_port, published_ports};
+use project::Project;
+
+/// We implement `port` with a trait so we can put it in its own module.
+pub trait CommandPort {
+    /// Print the host port to which `container_port` on `service` is
+    /// published, including ports which were assigned by cage.
+    fn port(&self, service: &str, container_port: &str) -> Result<()>;
+}
+
+impl CommandPort for Project {
+    fn port(&self, service: &str, container_port: &str) -> Result<()> {
+        println!("{}", try!(host_port(self, service, container_port)));
+        Ok(())
+    }
+}
+
+/// Look up the host port to which `container_port` on `service_name` in
+/// `project` is published.  We read this from our generated output,
+/// because it has all our variables and port assignments applied.
+fn host_port(project: &Project, service_name: &str, container_port: &str) -> Result<String> {
+    let (pod, service_name) = try!(project.service_or_err(service_name));
+    let service = try!(try!(project.output_service(pod, service_name)).ok_or_else(|| {
+        err!("{} is not enabled in {}",
+             pod.name(),
+             project.current_target().name())
+    }));
+
+    let wanted = normalize_container_port(container_port);
+    for spec in published_ports(&service) {
+        let spec = PortSpec::parse(&spec);
+        if normalize_container_port(spec.container_port) == wanted {
+            return spec.host_port
+                .map(|p| p.to_owned())
+                .ok_or_else(|| {
+                    err!("{} publishes port {}, but not to a fixed host port",
+                         service_name,
+                         container_port)
+                });
+        }
+    }
+    Err(err!("{} does not publish port {}", service_name, container_port))
+}
+
+#[test]
+fn looks_up_host_ports() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    proj.output().unwrap();
+    assert_eq!(host_port(&proj, "web", "3000").unwrap(), "3000");
+    assert_eq!(host_port(&proj, "frontend/web", "3000/tcp").unwrap(), "3000");
+    assert!(host_port(&proj, "web", "4000").is_err());
+    assert!(host_port(&proj, "db", "5432").is_err());
+    proj.remove_test_output().unwrap();
+}
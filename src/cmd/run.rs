@@ -6,6 +6,7 @@ use command_runner::{Command, CommandRunner};
 use command_runner::TestCommandRunner;
 use errors::*;
 use ext::service::ServiceExt;
+use plugins::LifecycleEvent;
 use project::Project;
 
 /// We implement `run` with a trait so we put it in its own module.
@@ -37,11 +38,12 @@ impl CommandRun for Project {
                -> Result<()>
         where CR: CommandRunner
     {
+        let act_on = args::ActOn::Named(vec![pod.to_owned()]);
         let pod = try!(self.pod(pod)
             .ok_or_else(|| err!("Cannot find pod {}", pod)));
 
         // Get the single service in our pod.
-        let file = try!(pod.merged_file(self.current_target()));
+        let file = try!(self.interpolated_file(pod));
         if file.services.len() != 1 {
             return Err(err!("Can only `run` pods with 1 service, {} has {}",
                             pod.name(),
@@ -55,13 +57,15 @@ impl CommandRun for Project {
         } else {
             vec![]
         };
-        runner.build("docker-compose")
+        try!(self.plugins().before(LifecycleEvent::Run, self, &act_on, runner));
+        try!(runner.build("docker-compose")
             .args(&try!(pod.compose_args(self, self.current_target())))
             .arg("run")
             .args(&opts.to_args())
             .arg(service)
             .args(&command_args)
-            .exec()
+            .exec());
+        self.plugins().after(LifecycleEvent::Run, self, &act_on, runner)
     }
 
     fn test<CR>(&self,
@@ -72,22 +76,25 @@ impl CommandRun for Project {
         where CR: CommandRunner
     {
         let target = self.current_target();
+        let act_on = args::ActOn::Named(vec![service_name.to_owned()]);
         let (pod, service_name) = try!(self.service_or_err(service_name));
 
         let command_args = if let Some(c) = command {
             c.to_args()
         } else {
-            let service = try!(pod.service_or_err(target, service_name));
+            let service = try!(self.interpolated_service_or_err(pod, service_name));
             try!(service.test_command()).iter().map(|s| s.into()).collect()
         };
-        runner.build("docker-compose")
+        try!(self.plugins().before(LifecycleEvent::Test, self, &act_on, runner));
+        try!(runner.build("docker-compose")
             .args(&try!(pod.compose_args(self, target)))
             .arg("run")
             .arg("--rm")
             .arg("--no-deps")
             .arg(service_name)
             .args(&command_args)
-            .exec()
+            .exec());
+        self.plugins().after(LifecycleEvent::Test, self, &act_on, runner)
     }
 }
 
@@ -114,6 +121,7 @@ fn runs_a_single_service_pod() {
     opts.allocate_tty = false;
     proj.run(&runner, "rake", Some(&cmd), &opts).unwrap();
     assert_ran!(runner, {
+        ["docker", "network", "inspect", "--format", "{{.Name}}", "rails_hello_shared"],
         ["docker-compose",
          "-p",
          "rails_hello",
@@ -139,6 +147,7 @@ fn runs_tests() {
     proj.test(&runner, "frontend/proxy", None).unwrap();
 
     assert_ran!(runner, {
+        ["docker", "network", "inspect", "--format", "{{.Name}}", "hellotest_shared"],
         ["docker-compose",
          "-p",
          "hellotest",
@@ -168,6 +177,7 @@ fn runs_tests_with_custom_command() {
     proj.test(&runner, "proxy", Some(&cmd)).unwrap();
 
     assert_ran!(runner, {
+        ["docker", "network", "inspect", "--format", "{{.Name}}", "hellotest_shared"],
         ["docker-compose",
          "-p",
          "hellotest",
@@ -0,0 +1,248 @@
+//! The `secrets` subcommand.
+
+use rand::random;
+use shlex;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use command_runner::{Command, CommandRunner};
+use encryption::Key;
+use errors::*;
+use plugins::transform::secrets::Plugin;
+use project::Project;
+use util::{write_new_private_file, write_private_file};
+
+/// Commands for working with an encrypted `config/secrets.yml.enc` file.
+pub trait CommandSecrets {
+    /// Encrypt `config/secrets.yml` as `config/secrets.yml.enc`,
+    /// generating a new key if we can't find one, and delete the
+    /// original.  Refuses to overwrite an existing
+    /// `config/secrets.yml.enc`.
+    fn secrets_encrypt(&self) -> Result<()>;
+
+    /// Decrypt `config/secrets.yml.enc` as `config/secrets.yml` and
+    /// delete the encrypted version.  Refuses to overwrite an existing
+    /// `config/secrets.yml`.
+    fn secrets_decrypt(&self) -> Result<()>;
+
+    /// Edit `config/secrets.yml.enc` using `$VISUAL` or `$EDITOR`,
+    /// without leaving a decrypted copy in the project directory.
+    fn secrets_edit<CR>(&self, runner: &CR) -> Result<()> where CR: CommandRunner;
+}
+
+impl CommandSecrets for Project {
+    fn secrets_encrypt(&self) -> Result<()> {
+        try!(check_can_encrypt(self));
+        let (key, generated) = try!(Key::find_or_generate(self));
+        if generated {
+            println!("Generated a new key in {}.  Share it with your team using a \
+                      secure channel.",
+                     try!(Key::path_for(self)).display());
+        }
+        encrypt_with_key(self, &key)
+    }
+
+    fn secrets_decrypt(&self) -> Result<()> {
+        try!(check_can_decrypt(self));
+        decrypt_with_key(self, &try!(Key::find(self)))
+    }
+
+    fn secrets_edit<CR>(&self, runner: &CR) -> Result<()>
+        where CR: CommandRunner
+    {
+        let encrypted_path = Plugin::encrypted_config_path(self);
+        if !encrypted_path.exists() {
+            return Err(err!("Cannot find {} (try `cage secrets encrypt`)",
+                            encrypted_path.display()));
+        }
+        edit_with_key(self, runner, &try!(Key::find(self)))
+    }
+}
+
+/// Make sure we have a `config/secrets.yml` to encrypt, and that we
+/// won't overwrite an existing `config/secrets.yml.enc`.
+fn check_can_encrypt(project: &Project) -> Result<()> {
+    let path = Plugin::config_path(project);
+    let encrypted_path = Plugin::encrypted_config_path(project);
+    if !path.exists() {
+        return Err(err!("Cannot find {} to encrypt", path.display()));
+    }
+    if encrypted_path.exists() {
+        return Err(err!("{} already exists, so not encrypting {} over it (use `cage \
+                         secrets edit` to change it)",
+                        encrypted_path.display(),
+                        path.display()));
+    }
+    Ok(())
+}
+
+/// Make sure we won't overwrite an existing `config/secrets.yml` when
+/// decrypting.
+fn check_can_decrypt(project: &Project) -> Result<()> {
+    let path = Plugin::config_path(project);
+    if path.exists() {
+        return Err(err!("{} already exists, so not decrypting {} over it",
+                        path.display(),
+                        Plugin::encrypted_config_path(project).display()));
+    }
+    Ok(())
+}
+
+/// Encrypt `config/secrets.yml` using `key`.  See `secrets_encrypt`.
+fn encrypt_with_key(project: &Project, key: &Key) -> Result<()> {
+    try!(check_can_encrypt(project));
+    let path = Plugin::config_path(project);
+    let encrypted_path = Plugin::encrypted_config_path(project);
+    let yaml = try!(read_file(&path));
+    try!(Plugin::validate_config(&yaml)
+        .chain_err(|| ErrorKind::CouldNotReadFile(path.clone())));
+    try!(write_file(&encrypted_path, &try!(key.encrypt(yaml.as_bytes()))));
+    try!(fs::remove_file(&path)
+        .chain_err(|| format!("could not remove {}", path.display())));
+    println!("Encrypted {} as {}", path.display(), encrypted_path.display());
+    Ok(())
+}
+
+/// Decrypt `config/secrets.yml.enc` using `key`.  See `secrets_decrypt`.
+fn decrypt_with_key(project: &Project, key: &Key) -> Result<()> {
+    try!(check_can_decrypt(project));
+    let path = Plugin::config_path(project);
+    let encrypted_path = Plugin::encrypted_config_path(project);
+    let yaml = try!(Plugin::decrypt_config_with_key(project, key));
+    try!(write_private_file(&path, yaml.as_bytes()));
+    try!(fs::remove_file(&encrypted_path)
+        .chain_err(|| format!("could not remove {}", encrypted_path.display())));
+    println!("Decrypted {} as {}.  Do not check it into version control!",
+             encrypted_path.display(),
+             path.display());
+    Ok(())
+}
+
+/// Edit `config/secrets.yml.enc` using `key`.  See `secrets_edit`.
+fn edit_with_key<CR>(project: &Project, runner: &CR, key: &Key) -> Result<()>
+    where CR: CommandRunner
+{
+    // Decrypt our secrets into a private temporary file.  We refuse to
+    // reuse an existing path, because anybody can create files in the
+    // temporary directory.
+    let encrypted_path = Plugin::encrypted_config_path(project);
+    let original = try!(Plugin::decrypt_config_with_key(project, key));
+    let tmp_path = env::temp_dir().join(format!("cage-secrets-{:016x}.yml", random::<u64>()));
+    try!(write_new_private_file(&tmp_path, original.as_bytes()));
+
+    // Run the user's editor and read back the result.
+    let edited = run_editor(runner, &tmp_path).and_then(|()| read_file(&tmp_path));
+    let edited = match edited {
+        Ok(edited) => edited,
+        Err(e) => {
+            try!(fs::remove_file(&tmp_path));
+            return Err(e);
+        }
+    };
+
+    // Re-encrypt our secrets if they changed.  If they're invalid, we
+    // keep the temporary file around so the user doesn't lose their
+    // work.
+    if edited == original {
+        println!("No changes to {}", encrypted_path.display());
+    } else {
+        try!(Plugin::validate_config(&edited).chain_err(|| {
+            format!("invalid secrets, leaving your changes in {}",
+                    tmp_path.display())
+        }));
+        try!(write_file(&encrypted_path, &try!(key.encrypt(edited.as_bytes()))));
+        println!("Updated {}", encrypted_path.display());
+    }
+    try!(fs::remove_file(&tmp_path));
+    Ok(())
+}
+
+/// Run the user's preferred editor on `path`.
+fn run_editor<CR>(runner: &CR, path: &Path) -> Result<()>
+    where CR: CommandRunner
+{
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+    let mut lexer = shlex::Shlex::new(&editor);
+    let words: Vec<String> = lexer.by_ref().collect();
+    if lexer.had_error || words.is_empty() {
+        return Err(err!("cannot parse editor <{}> into shell words", editor));
+    }
+    runner.build(&words[0])
+        .args(&words[1..])
+        .arg(path)
+        .exec()
+}
+
+/// Read the contents of `path` as a string.
+fn read_file(path: &Path) -> Result<String> {
+    let mkerr = || ErrorKind::CouldNotReadFile(path.to_owned());
+    let mut f = try!(fs::File::open(path).chain_err(&mkerr));
+    let mut contents = String::new();
+    try!(f.read_to_string(&mut contents).chain_err(&mkerr));
+    Ok(contents)
+}
+
+/// Write `contents` to `path`.
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    let mut f = try!(fs::File::create(path).chain_err(&mkerr));
+    try!(f.write_all(contents.as_bytes()).chain_err(&mkerr));
+    Ok(())
+}
+
+#[test]
+fn secrets_round_trip_through_encrypted_file() {
+    use cmd::{CommandGenerate, NewOptions};
+    use command_runner::TestCommandRunner;
+    use env_logger;
+    let _ = env_logger::init();
+
+    // Generate a throwaway project, because we modify its config files.
+    let parent = env::current_dir().unwrap().join("target").join("test_output");
+    let name = format!("secrets-{}", random::<u16>());
+    let root = Project::generate_new(&parent, &name, &NewOptions::default()).unwrap();
+    let proj = Project::from_test_dir(&root).unwrap();
+    let path = Plugin::config_path(&proj);
+    let encrypted_path = Plugin::encrypted_config_path(&proj);
+    let original = read_file(&path).unwrap();
+
+    // Pass our key explicitly, so we don't touch the user's real keys.
+    let key = Key::generate().unwrap();
+
+    encrypt_with_key(&proj, &key).unwrap();
+    assert!(!path.exists());
+    assert_eq!(Plugin::decrypt_config_with_key(&proj, &key).unwrap(), original);
+
+    // We refuse to overwrite an existing encrypted file.
+    write_file(&path, "# Newer secrets.\n").unwrap();
+    assert!(encrypt_with_key(&proj, &key).is_err());
+    assert!(path.exists());
+    assert_eq!(Plugin::decrypt_config_with_key(&proj, &key).unwrap(), original);
+
+    // Our test runner doesn't actually run the editor, so nothing changes.
+    let runner = TestCommandRunner::new();
+    edit_with_key(&proj, &runner, &key).unwrap();
+    let tmp_path = {
+        let cmds = runner.cmds();
+        assert_eq!(cmds.len(), 1);
+        Path::new(cmds[0].last().unwrap()).to_owned()
+    };
+    assert!(!tmp_path.exists());
+    assert_eq!(Plugin::decrypt_config_with_key(&proj, &key).unwrap(), original);
+
+    // We refuse to overwrite an existing plaintext file.
+    assert!(decrypt_with_key(&proj, &key).is_err());
+    assert!(encrypted_path.exists());
+    assert_eq!(read_file(&path).unwrap(), "# Newer secrets.\n");
+    fs::remove_file(&path).unwrap();
+
+    decrypt_with_key(&proj, &key).unwrap();
+    assert!(!encrypted_path.exists());
+    assert_eq!(read_file(&path).unwrap(), original);
+
+    fs::remove_dir_all(&root).unwrap();
+}
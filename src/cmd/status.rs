@@ -30,8 +30,7 @@ impl CommandStatus for Project {
                 PodOrService::Pod(pod) => try!(self.pod_status(pod)),
                 PodOrService::Service(pod, service_name) => {
                     try!(self.pod_header(pod));
-                    let service = try!(pod.service_or_err(self.current_target(),
-                                                          service_name));
+                    let service = try!(self.interpolated_service_or_err(pod, service_name));
                     try!(self.service_status(pod, service_name, &service, true));
                 }
             }
@@ -58,7 +57,7 @@ impl Project {
     /// Display information about a pod and its services.
     fn pod_status(&self, pod: &Pod) -> Result<()> {
         try!(self.pod_header(pod));
-        let file = try!(pod.merged_file(self.current_target()));
+        let file = try!(self.interpolated_file(pod));
         for (i, (service_name, service)) in file.services.iter().enumerate() {
             try!(self.service_status(pod,
                                      service_name,
@@ -70,7 +69,7 @@ impl Project {
 
     /// Display information about a service.
     fn service_status(&self,
-                      _pod: &Pod,
+                      pod: &Pod,
                       service_name: &str,
                       service: &dc::Service,
                       last: bool)
@@ -81,8 +80,13 @@ impl Project {
             print!("├─ {}", service_name.blue());
         }
 
-        // Print out ports with known host bindings.
-        let ports: Vec<String> = try!(service.ports
+        // Print out ports with known host bindings.  Our generated output
+        // includes any host ports which we assigned ourselves, so prefer it
+        // when we have it.
+        let output_service = try!(self.output_service(pod, service_name));
+        let ports: Vec<String> = try!(output_service.as_ref()
+            .unwrap_or(service)
+            .ports
             .iter()
             .map(|port| Ok(try!(port.value()).host_string()))
             .filter_map(|result| {
@@ -116,3 +120,17 @@ impl Project {
         Ok(())
     }
 }
+
+#[test]
+fn status_interpolates_vars() {
+    use command_runner::TestCommandRunner;
+    use env_logger;
+    let _ = env_logger::init();
+
+    // `frontend` publishes `${RAILS_PORT}:3000`, which we can only parse
+    // after interpolating `config/vars.yml`.
+    let proj = Project::from_example("rails_hello").unwrap();
+    let runner = TestCommandRunner::new();
+    proj.status(&runner, &args::ActOn::All).unwrap();
+    proj.status(&runner, &args::ActOn::Named(vec!["web".to_owned()])).unwrap();
+}
@@ -45,6 +45,7 @@ fn runs_docker_compose_up_honors_enable_in_targets() {
     let opts = args::opts::Up::default();
     proj.up(&runner, &args::ActOn::All, &opts).unwrap();
     assert_ran!(runner, {
+        ["docker", "network", "inspect", "--format", "{{.Name}}", "rails_hello_shared"],
         ["docker-compose",
          "-p",
          "rails_hello",
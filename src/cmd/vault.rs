@@ -0,0 +1,101 @@
+//! The `vault` subcommand.
+
+use colored::*;
+
+use errors::*;
+use fingerprint;
+use plugins::generate::vault_policies;
+use plugins::transform::vault::{CachedToken, Plugin};
+use project::Project;
+
+/// Commands for working with the Vault tokens we issue to services, and
+/// the policies attached to them.
+pub trait CommandVault {
+    /// List the Vault tokens which we've issued to services and cached
+    /// for reuse.
+    fn vault_tokens_list(&self) -> Result<()>;
+
+    /// Revoke all the Vault tokens which we've cached, and clear our
+    /// cache.
+    fn vault_tokens_revoke(&self) -> Result<()>;
+
+    /// Report any policies referenced by `config/vault.yml` which are
+    /// missing from `config/vault_policies`, and fail if there are any.
+    fn vault_check_policies(&self) -> Result<()>;
+}
+
+impl CommandVault for Project {
+    fn vault_tokens_list(&self) -> Result<()> {
+        let tokens = try!(Plugin::cached_tokens(self));
+        if tokens.is_empty() {
+            println!("No cached Vault tokens");
+        }
+        for (name, token) in &tokens {
+            println!("{} {}", name, describe_expiration(token));
+            println!("  policies: {}", token.policies.join(", "));
+        }
+        Ok(())
+    }
+
+    fn vault_tokens_revoke(&self) -> Result<()> {
+        let revoked = try!(Plugin::revoke_cached_tokens(self));
+        for name in revoked.keys() {
+            println!("Revoked {}", name);
+        }
+        // Make sure we replace the revoked tokens in our output the next
+        // time we run.
+        try!(fingerprint::forget(self));
+        let remaining = try!(Plugin::cached_tokens(self)).len();
+        if remaining == 0 {
+            Ok(())
+        } else {
+            Err(err!("Could not revoke {} cached tokens (see the warnings above)",
+                     remaining))
+        }
+    }
+
+    fn vault_check_policies(&self) -> Result<()> {
+        let missing = try!(vault_policies::Plugin::missing_policies(self));
+        for (name, users) in &missing {
+            let users: Vec<_> = users.iter()
+                .map(|u| format!("{}/{} ({})", u.pod, u.service, u.target))
+                .collect();
+            println!("{} {}", name.red(), users.join(", "));
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(err!("{} policies are missing from {} (try `cage generate \
+                      vault_policies`)",
+                     missing.len(),
+                     try!(vault_policies::Plugin::policies_dir(self)).display()))
+        }
+    }
+}
+
+/// Describe when `token` will expire.
+fn describe_expiration(token: &CachedToken) -> String {
+    let expires_in = token.expires_in();
+    if expires_in == 0 {
+        "(expired)".red().to_string()
+    } else {
+        format!("(expires in {})", describe_duration(expires_in))
+    }
+}
+
+/// Describe a duration of `secs` seconds in hours and minutes.
+fn describe_duration(secs: u64) -> String {
+    let (hours, minutes) = (secs / 3600, secs % 3600 / 60);
+    if hours == 0 {
+        format!("{}m", minutes)
+    } else {
+        format!("{}h{:02}m", hours, minutes)
+    }
+}
+
+#[test]
+fn describes_durations_in_hours_and_minutes() {
+    assert_eq!(describe_duration(59 * 60 + 30), "59m");
+    assert_eq!(describe_duration(3600), "1h00m");
+    assert_eq!(describe_duration(25 * 3600 + 5 * 60), "25h05m");
+}
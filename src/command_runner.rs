@@ -42,6 +42,10 @@ pub trait Command {
     /// Run our command.
     fn status(&mut self) -> Result<process::ExitStatus>;
 
+    /// Run our command, capturing its standard output and standard error
+    /// instead of passing them through to the user.
+    fn output(&mut self) -> Result<process::Output>;
+
     /// Run our command as per `status`, returning an error if the command
     /// fails.
     fn exec(&mut self) -> Result<()> {
@@ -57,6 +61,93 @@ pub trait Command {
     fn command_failed_error(&self) -> ErrorKind;
 }
 
+/// An object-safe version of `CommandRunner`.  `CommandRunner` has
+/// generic methods and an associated type, so it can't be used as a
+/// trait object, which is a problem for plugins (which are trait objects
+/// themselves).  Every `CommandRunner` automatically implements this
+/// trait.
+pub trait DynCommandRunner {
+    /// Build a new command, returning it as a trait object.
+    fn build_dyn<'a>(&'a self, program: &OsStr) -> Box<DynCommand + 'a>;
+}
+
+impl<CR: CommandRunner> DynCommandRunner for CR {
+    fn build_dyn<'a>(&'a self, program: &OsStr) -> Box<DynCommand + 'a> {
+        Box::new(self.build(program))
+    }
+}
+
+/// An object-safe version of `Command`, returned by `DynCommandRunner`.
+/// You won't normally need to call these methods directly, because
+/// `Box<DynCommand>` implements `Command`.  (Note that `Box<DynCommand>`
+/// also implements this trait via `Command`, so we need to be careful to
+/// dereference the box before calling these methods on it.)
+pub trait DynCommand {
+    /// Add an argument to our command.
+    fn arg_dyn(&mut self, arg: &OsStr);
+
+    /// Set an environment variable for the process we're about to run.
+    fn env_dyn(&mut self, key: &OsStr, val: &OsStr);
+
+    /// Run our command.
+    fn status_dyn(&mut self) -> Result<process::ExitStatus>;
+
+    /// Run our command, capturing its output.
+    fn output_dyn(&mut self) -> Result<process::Output>;
+
+    /// Make an error representing a failure of this command.
+    fn command_failed_error_dyn(&self) -> ErrorKind;
+}
+
+impl<C: Command> DynCommand for C {
+    fn arg_dyn(&mut self, arg: &OsStr) {
+        self.arg(arg);
+    }
+
+    fn env_dyn(&mut self, key: &OsStr, val: &OsStr) {
+        self.env(key, val);
+    }
+
+    fn status_dyn(&mut self) -> Result<process::ExitStatus> {
+        self.status()
+    }
+
+    fn output_dyn(&mut self) -> Result<process::Output> {
+        self.output()
+    }
+
+    fn command_failed_error_dyn(&self) -> ErrorKind {
+        self.command_failed_error()
+    }
+}
+
+impl<'a> Command for Box<DynCommand + 'a> {
+    fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        (**self).arg_dyn(arg.as_ref());
+        self
+    }
+
+    fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+        where K: AsRef<OsStr>,
+              V: AsRef<OsStr>
+    {
+        (**self).env_dyn(key.as_ref(), val.as_ref());
+        self
+    }
+
+    fn status(&mut self) -> Result<process::ExitStatus> {
+        (**self).status_dyn()
+    }
+
+    fn output(&mut self) -> Result<process::Output> {
+        (**self).output_dyn()
+    }
+
+    fn command_failed_error(&self) -> ErrorKind {
+        (**self).command_failed_error_dyn()
+    }
+}
+
 /// Support for running operating system commands.
 #[derive(Debug, Default)]
 #[allow(missing_copy_implementations)]
@@ -124,6 +215,11 @@ impl Command for OsCommand {
         self.command.status().chain_err(|| self.command_failed_error())
     }
 
+    fn output(&mut self) -> Result<process::Output> {
+        debug!("Running {:?}", &self.arg_log);
+        self.command.output().chain_err(|| self.command_failed_error())
+    }
+
     fn command_failed_error(&self) -> ErrorKind {
         ErrorKind::CommandFailed(self.arg_log.clone())
     }
@@ -134,6 +230,7 @@ fn os_command_runner_runs_commands() {
     let runner = OsCommandRunner::new();
     assert!(runner.build("true").status().unwrap().success());
     assert!(!runner.build("false").status().unwrap().success());
+    assert_eq!(runner.build("echo").arg("hi").output().unwrap().stdout, b"hi\n");
 }
 
 /// Support for running commands in test mode.
@@ -219,6 +316,13 @@ impl Command for TestCommand {
             .chain_err(|| self.command_failed_error())
     }
 
+    fn output(&mut self) -> Result<process::Output> {
+        self.record_execution();
+        process::Command::new("true")
+            .output()
+            .chain_err(|| self.command_failed_error())
+    }
+
     fn command_failed_error(&self) -> ErrorKind {
         ErrorKind::CommandFailed(self.cmd.clone())
     }
@@ -266,3 +370,18 @@ pub fn test_command_runner_logs_commands() {
         ["echo", "a", "b"]
     });
 }
+
+#[test]
+pub fn dyn_command_runner_delegates_to_command_runner() {
+    let runner = TestCommandRunner::new();
+    {
+        let dyn_runner: &DynCommandRunner = &runner;
+        dyn_runner.build_dyn(OsStr::new("git"))
+            .args(&["clone", "https://github.com/torvalds/linux"])
+            .exec()
+            .unwrap();
+    }
+    assert_ran!(runner, {
+        ["git", "clone", "https://github.com/torvalds/linux"]
+    });
+}
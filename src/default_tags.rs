@@ -4,32 +4,77 @@
 use compose_yml::v2 as dc;
 use std::collections::BTreeMap;
 use std::collections::btree_map;
-use std::io::{self, BufRead};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
 use std::str::FromStr;
+use yaml_rust::{Yaml, YamlLoader};
 
 use errors::*;
 
 /// This is typically used to incorporate image tags for specific builds
 /// generated by a continuous integration system (such as [Go][GoCD]).
 ///
-/// The on-disk format is a text file with one tagged image name per line:
+/// The simplest on-disk format is a text file with one tagged image name
+/// per line.  Blank lines and comments starting with `#` are ignored:
 ///
 /// ```txt
+/// # Built by CI run 1234.
 /// example.com/app1:30
 /// example.com/app2:57
 /// alpine:4.3
+/// registry.example.com/team/*:build-1234
 /// ```
 ///
 /// The tags from this file will be used as default tags for these images.
 /// So for example, `example.com/app1` would default to
-/// `example.com/app1:30`, and `alpine` would default to `alpine:4.3`.
+/// `example.com/app1:30`, and `alpine` would default to `alpine:4.3`.  A
+/// name ending in `/*` applies to every image in that namespace which
+/// doesn't have a tag of its own, so `registry.example.com/team/api`
+/// would default to `registry.example.com/team/api:build-1234`.  If
+/// several namespaces match, the longest one wins.
+///
+/// We also accept a YAML or JSON map from image names to tags:
+///
+/// ```yaml
+/// example.com/app1: 30
+/// "registry.example.com/team/*": "build-1234"
+/// ```
 ///
 /// [GoCD]: https://www.go.cd/
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DefaultTags {
-    /// Our default tags. All the `Image` keys should have a tag of `None`,
-    /// and the values should have a tag of `Some(...)`.
-    tags: BTreeMap<dc::Image, dc::Image>,
+    /// Default tags for specific images.  All the `Image` keys should
+    /// have a tag of `None`.
+    tags: BTreeMap<dc::Image, Rule>,
+    /// Default tags for every image whose name starts with a prefix.  Each
+    /// prefix ends with `/`.
+    prefixes: BTreeMap<String, Rule>,
+}
+
+/// A default tag, and where we found it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// The tag to use.
+    tag: String,
+    /// Where this rule was defined.
+    location: Location,
+}
+
+/// A line in a tag file, for use in error messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    /// The name of the file, or some other description of our input.
+    source: String,
+    /// The line number, starting from 1.
+    line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", &self.source, self.line)
+    }
 }
 
 impl DefaultTags {
@@ -37,27 +82,143 @@ impl DefaultTags {
     pub fn read<R>(r: R) -> Result<Self>
         where R: io::Read
     {
-        let mut tags = BTreeMap::new();
-        let reader = io::BufReader::new(r);
-        for line_result in reader.lines() {
-            let line = try!(line_result);
-            let image = try!(dc::Image::from_str(&line));
-            if let (key, Some(_)) = (image.without_tag(), image.tag.as_ref()) {
-                match tags.entry(key.to_owned()) {
-                    btree_map::Entry::Vacant(vacant) => {
-                        vacant.insert(image.to_owned());
-                    }
-                    btree_map::Entry::Occupied(occupied) => {
-                        if occupied.get() != &image {
-                            return Err(err!("Conflicting versions for {}", &key));
-                        }
-                    }
+        DefaultTags::read_named("<input>", r)
+    }
+
+    /// Read in tag defaults from the file at `path`.
+    pub fn read_from_path(path: &Path) -> Result<Self> {
+        let mkerr = || ErrorKind::CouldNotReadFile(path.to_owned());
+        let f = try!(fs::File::open(path).chain_err(&mkerr));
+        DefaultTags::read_named(&path.display().to_string(), f).chain_err(&mkerr)
+    }
+
+    /// Read in tag defaults from each file in `paths`.  When several
+    /// files have a default for the same image or namespace, the last
+    /// one wins.
+    pub fn read_from_paths<'a, I>(paths: I) -> Result<Self>
+        where I: IntoIterator<Item = &'a Path>
+    {
+        let mut tags = DefaultTags::default();
+        for path in paths {
+            tags.override_with(try!(DefaultTags::read_from_path(path)));
+        }
+        Ok(tags)
+    }
+
+    /// Read in tag defaults from a stream, using `source` to describe it
+    /// in error messages.
+    fn read_named<R>(source: &str, mut r: R) -> Result<Self>
+        where R: io::Read
+    {
+        let mut text = String::new();
+        try!(r.read_to_string(&mut text));
+        let mut tags = DefaultTags::default();
+        if is_map_format(&text) {
+            try!(tags.parse_map(source, &text));
+        } else {
+            try!(tags.parse_lines(source, &text));
+        }
+        Ok(tags)
+    }
+
+    /// Parse our text format, with one tagged image per line.
+    fn parse_lines(&mut self, source: &str, text: &str) -> Result<()> {
+        for (i, line) in text.lines().enumerate() {
+            let location = Location {
+                source: source.to_owned(),
+                line: i + 1,
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, tag) = match line.rfind(':') {
+                Some(colon) if !line[colon..].contains('/') => {
+                    (&line[..colon], &line[colon + 1..])
                 }
-            } else {
-                return Err(err!("Default image must have tag: {}", &image));
+                _ => return Err(err!("Default image must have tag at {}: {}", location, line)),
+            };
+            try!(self.add(name, tag, location));
+        }
+        Ok(())
+    }
+
+    /// Parse a YAML or JSON map from image names to tags.
+    fn parse_map(&mut self, source: &str, text: &str) -> Result<()> {
+        let docs = try!(YamlLoader::load_from_str(text)
+            .map_err(|e| err!("Could not parse default tags in {}: {}", source, e)));
+        let map = match docs.into_iter().next() {
+            Some(Yaml::Hash(map)) => map,
+            None | Some(Yaml::Null) => return Ok(()),
+            Some(_) => return Err(err!("Expected a map of default tags in {}", source)),
+        };
+        // yaml_rust silently keeps the last of any duplicate keys, so look
+        // for them ourselves.
+        let keys = map_keys(text);
+        for (i, &(ref key, line)) in keys.iter().enumerate() {
+            if let Some(&(_, first)) = keys[..i].iter().find(|&&(ref k, _)| k == key) {
+                return Err(err!("Duplicate default tags for {} at {}:{} and {}:{}",
+                                key,
+                                source,
+                                first,
+                                source,
+                                line));
+            }
+        }
+        for (key, value) in map {
+            let name = try!(key.as_str()
+                .ok_or_else(|| err!("Expected image names in {} to be strings", source)));
+            let location = Location {
+                source: source.to_owned(),
+                line: keys.iter()
+                    .find(|&&(ref k, _)| k == name)
+                    .map_or(1, |&(_, line)| line),
+            };
+            let tag = match value {
+                Yaml::String(tag) => tag,
+                Yaml::Integer(tag) => tag.to_string(),
+                Yaml::Real(tag) => tag,
+                _ => return Err(err!("Expected a tag for {} at {}", name, location)),
+            };
+            try!(self.add(name, &tag, location));
+        }
+        Ok(())
+    }
+
+    /// Add a default `tag` for `name`, which may be either an untagged
+    /// image or a namespace ending in `/*`.  Conflicting defaults are an
+    /// error.
+    fn add(&mut self, name: &str, tag: &str, location: Location) -> Result<()> {
+        if tag.is_empty() || tag.contains('/') {
+            return Err(err!("Invalid tag for {} at {}: {}", name, location, tag));
+        }
+        let rule = Rule {
+            tag: tag.to_owned(),
+            location: location,
+        };
+        if name.ends_with("/*") {
+            let prefix = name[..name.len() - 1].to_owned();
+            insert_rule(&mut self.prefixes, prefix, name, rule)
+        } else {
+            let image = try!(dc::Image::from_str(name));
+            if image.tag.is_some() {
+                return Err(err!("Image name already has a tag at {}: {}", &rule.location, name));
             }
+            insert_rule(&mut self.tags, image, name, rule)
+        }
+    }
+
+    /// Replace any of our defaults which are also specified by `other`,
+    /// and add the rest.
+    fn override_with(&mut self, other: DefaultTags) {
+        for (image, rule) in other.tags {
+            debug!("Default tag for {} from {}", &image, &rule.location);
+            self.tags.insert(image, rule);
+        }
+        for (prefix, rule) in other.prefixes {
+            debug!("Default tag for {}* from {}", &prefix, &rule.location);
+            self.prefixes.insert(prefix, rule);
         }
-        Ok(DefaultTags { tags: tags })
     }
 
     /// Default the `tag` field of `image` if necessary, returning the old
@@ -65,10 +226,24 @@ impl DefaultTags {
     pub fn default_for(&self, image: &dc::Image) -> dc::Image {
         if image.tag.is_some() {
             // Already tagged, so assume the user knows what they're doing.
-            image.to_owned()
-        } else if let Some(default) = self.tags.get(image) {
-            debug!("Defaulting {} to {}", image, &default);
-            default.to_owned()
+            return image.to_owned();
+        }
+
+        // Specific images take precedence over namespaces, and longer
+        // namespaces take precedence over shorter ones.
+        let name = image.to_string();
+        let rule = self.tags.get(image).or_else(|| {
+            self.prefixes
+                .iter()
+                .filter(|&(prefix, _)| name.starts_with(&prefix[..]))
+                .max_by_key(|&(prefix, _)| prefix.len())
+                .map(|(_, rule)| rule)
+        });
+        if let Some(rule) = rule {
+            let mut default = image.to_owned();
+            default.tag = Some(rule.tag.clone());
+            debug!("Defaulting {} to {} (from {})", image, &default, &rule.location);
+            default
         } else {
             // If we have a list of default tags, but it doesn't
             // include all the images we use, then we consider that
@@ -80,6 +255,114 @@ impl DefaultTags {
     }
 }
 
+/// Insert `rule` for `key` into `rules`, reporting an error if we already
+/// have a different tag for `key`.  We use `name` in error messages.
+fn insert_rule<K: Ord>(rules: &mut BTreeMap<K, Rule>,
+                       key: K,
+                       name: &str,
+                       rule: Rule)
+                       -> Result<()> {
+    match rules.entry(key) {
+        btree_map::Entry::Vacant(vacant) => {
+            vacant.insert(rule);
+        }
+        btree_map::Entry::Occupied(occupied) => {
+            let existing = occupied.get();
+            if existing.tag != rule.tag {
+                return Err(err!("Conflicting versions for {}: {} at {} and {} at {}",
+                                name,
+                                &existing.tag,
+                                &existing.location,
+                                &rule.tag,
+                                &rule.location));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove any `#` comment from `line`.  Comments must start a line or
+/// follow whitespace.
+fn strip_comment(line: &str) -> &str {
+    if line.trim_left().starts_with('#') {
+        return "";
+    }
+    match line.find(" #").or_else(|| line.find("\t#")) {
+        Some(pos) => &line[..pos],
+        None => line,
+    }
+}
+
+/// Does `text` look like a YAML or JSON map, rather than our simple text
+/// format?  Our text format never contains `": "`, because image names
+/// can't contain spaces.
+fn is_map_format(text: &str) -> bool {
+    text.lines()
+        .map(|line| strip_comment(line).trim())
+        .find(|line| !line.is_empty())
+        .map_or(false, |line| {
+            line.starts_with('{') || line.starts_with("---") || line.ends_with(':') ||
+            line.contains(": ")
+        })
+}
+
+/// Find the keys of the top-level YAML or JSON map in `text`, along with
+/// the line number where each appears.  This isn't a full parser, but it
+/// handles block-style YAML maps and flow-style YAML or JSON maps, which
+/// is all we need to report line numbers and spot duplicate keys.
+fn map_keys(text: &str) -> Vec<(String, usize)> {
+    let mut keys = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        let mut rest = line;
+        loop {
+            rest = rest.trim_left_matches(|c: char| c == '{' || c == ',' || c.is_whitespace());
+            if rest.starts_with('"') || rest.starts_with('\'') {
+                // A quoted key, possibly one of several on this line.
+                let (key, after) = match split_quoted(rest) {
+                    Some(split) => split,
+                    None => break,
+                };
+                let after = after.trim_left();
+                if !after.starts_with(':') {
+                    break;
+                }
+                keys.push((key.to_owned(), i + 1));
+                rest = after[1..].trim_left();
+                if rest.starts_with('"') || rest.starts_with('\'') {
+                    rest = split_quoted(rest).map_or("", |(_, after)| after);
+                } else {
+                    rest = rest.find(',').map_or("", |pos| &rest[pos..]);
+                }
+            } else if rest.len() == line.len() && !rest.starts_with("---") {
+                // A bare key at the start of a block-style YAML line.
+                let trimmed = rest.trim_right();
+                let end = rest.find(": ").or_else(|| {
+                    if trimmed.ends_with(':') {
+                        Some(trimmed.len() - 1)
+                    } else {
+                        None
+                    }
+                });
+                if let Some(end) = end {
+                    keys.push((rest[..end].trim_right().to_owned(), i + 1));
+                }
+                break;
+            } else {
+                break;
+            }
+        }
+    }
+    keys
+}
+
+/// Split a string starting with a quote character into the quoted text
+/// and whatever follows the closing quote.
+fn split_quoted(s: &str) -> Option<(&str, &str)> {
+    let quote = &s[..1];
+    s[1..].find(quote).map(|end| (&s[1..end + 1], &s[end + 2..]))
+}
+
 #[test]
 fn defaults_tags_using_data_from_file() {
     let file = "example.com/app1:30
@@ -91,8 +374,111 @@ alpine:4.3
                dc::Image::new("alpine:4.3").unwrap());
     assert_eq!(default_tags.default_for(&dc::Image::new("alpine:4.2").unwrap()),
                dc::Image::new("alpine:4.2").unwrap());
-    // TODO LOW: I'm not sure how we should actually handle `latest`.
-    // Should it default?
+    // We leave an explicit `latest` alone, because somebody asked for it,
+    // but `strict_tags` will refuse to export it.
     assert_eq!(default_tags.default_for(&dc::Image::new("alpine:latest").unwrap()),
                dc::Image::new("alpine:latest").unwrap());
 }
+
+#[test]
+fn defaults_tags_using_comments_and_namespaces() {
+    let file = "# Built by CI.
+registry.example.com/team/*:build-1234   # Everything from our team.
+registry.example.com/team/api/*:build-1300
+
+registry.example.com/team/web:build-1200
+";
+    let default_tags = DefaultTags::read(io::Cursor::new(file)).unwrap();
+    let default_for = |name: &str| {
+        default_tags.default_for(&dc::Image::new(name).unwrap()).to_string()
+    };
+    assert_eq!(default_for("registry.example.com/team/worker"),
+               "registry.example.com/team/worker:build-1234");
+    assert_eq!(default_for("registry.example.com/team/api/v2"),
+               "registry.example.com/team/api/v2:build-1300");
+    assert_eq!(default_for("registry.example.com/team/web"),
+               "registry.example.com/team/web:build-1200");
+    assert_eq!(default_for("registry.example.com/other/web"),
+               "registry.example.com/other/web");
+}
+
+#[test]
+fn defaults_tags_using_yaml_and_json_maps() {
+    let yaml = "# Built by CI.
+example.com/app1: 30
+\"registry.example.com/team/*\": \"build-1234\"
+";
+    let json = r#"{"example.com/app1": "30", "registry.example.com/team/*": "build-1234"}"#;
+    for file in &[yaml, json] {
+        let default_tags = DefaultTags::read(io::Cursor::new(*file)).unwrap();
+        assert_eq!(default_tags.default_for(&dc::Image::new("example.com/app1").unwrap()),
+                   dc::Image::new("example.com/app1:30").unwrap());
+        assert_eq!(default_tags.default_for(&dc::Image::new("registry.example.com/team/x")
+                       .unwrap()),
+                   dc::Image::new("registry.example.com/team/x:build-1234").unwrap());
+    }
+}
+
+#[test]
+fn reports_conflicting_tags_with_line_numbers() {
+    let file = "alpine:4.3
+# Oops.
+alpine:4.4
+";
+    let err = DefaultTags::read(io::Cursor::new(file)).unwrap_err();
+    assert!(err.to_string().contains("alpine: 4.3 at <input>:1 and 4.4 at <input>:3"));
+    assert!(DefaultTags::read(io::Cursor::new("alpine\n")).is_err());
+}
+
+#[test]
+fn reports_duplicate_map_keys_with_line_numbers() {
+    let yaml = "alpine: 3.4
+# Oops.
+alpine: 3.5
+";
+    let err = DefaultTags::read(io::Cursor::new(yaml)).unwrap_err();
+    assert!(err.to_string().contains("alpine at <input>:1 and <input>:3"));
+
+    let json = r#"{"alpine": "3.4", "example.com/app1": "30",
+ "alpine": "3.5"}"#;
+    let err = DefaultTags::read(io::Cursor::new(json)).unwrap_err();
+    assert!(err.to_string().contains("alpine at <input>:1 and <input>:2"));
+}
+
+#[test]
+fn reports_exact_line_numbers_for_map_keys() {
+    let yaml = "example.com/app1-extra: 1
+example.com/app1: a/b
+";
+    let err = DefaultTags::read(io::Cursor::new(yaml)).unwrap_err();
+    assert!(err.to_string().contains("example.com/app1 at <input>:2"));
+
+    let json = r#"{
+  "example.com/app1-extra": "1",
+  "example.com/app1": "a/b"
+}"#;
+    let err = DefaultTags::read(io::Cursor::new(json)).unwrap_err();
+    assert!(err.to_string().contains("example.com/app1 at <input>:3"));
+}
+
+#[test]
+fn later_files_take_precedence() {
+    use project::Project;
+    use std::io::Write;
+
+    let proj = Project::from_example("hello").unwrap();
+    let ci = proj.output_dir().join("ci.txt");
+    let overrides = proj.output_dir().join("overrides.yml");
+    fs::create_dir_all(proj.output_dir()).unwrap();
+    fs::File::create(&ci).unwrap().write_all(b"example.com/app1:30\nalpine:3.4\n").unwrap();
+    fs::File::create(&overrides).unwrap().write_all(b"example.com/app1: 31\n").unwrap();
+
+    let default_tags = DefaultTags::read_from_paths(vec![ci.as_path(), overrides.as_path()])
+        .unwrap();
+    assert_eq!(default_tags.default_for(&dc::Image::new("example.com/app1").unwrap()),
+               dc::Image::new("example.com/app1:31").unwrap());
+    assert_eq!(default_tags.default_for(&dc::Image::new("alpine").unwrap()),
+               dc::Image::new("alpine:3.4").unwrap());
+
+    proj.remove_test_output().unwrap();
+}
@@ -0,0 +1,195 @@
+//! Symmetric encryption for files which we want to check into version
+//! control, such as `config/secrets.yml.enc`.
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use rand::{OsRng, Rng};
+use rustc_serialize::base64::{self, FromBase64, ToBase64};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+
+use errors::*;
+use project::Project;
+use util::{err, write_private_file};
+
+/// The environment variable which may be used to supply a key.
+const KEY_VAR: &'static str = "CAGE_SECRETS_KEY";
+
+/// The first line of every file we encrypt.  This is also authenticated
+/// as "additional data", so it can't be altered.
+const HEADER: &'static str = "cage-encrypted-v1";
+
+/// The length of our keys, in bytes.
+const KEY_LEN: usize = 32;
+
+/// The length of our nonces, in bytes.  This is the only length which
+/// `ChaCha20Poly1305` supports.
+const NONCE_LEN: usize = 8;
+
+/// The length of our authentication tags, in bytes.
+const TAG_LEN: usize = 16;
+
+/// How we encode binary data in our files.
+const BASE64: base64::Config = base64::Config {
+    char_set: base64::CharacterSet::Standard,
+    newline: base64::Newline::LF,
+    pad: true,
+    line_length: Some(76),
+};
+
+/// A key used to encrypt and decrypt files, using ChaCha20-Poly1305.
+pub struct Key {
+    /// The raw key.
+    bytes: [u8; KEY_LEN],
+}
+
+// Don't include our key in any debugging output.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key {{ .. }}")
+    }
+}
+
+impl Key {
+    /// Generate a new random key.
+    pub fn generate() -> Result<Key> {
+        let mut rng = try!(OsRng::new());
+        let mut bytes = [0; KEY_LEN];
+        rng.fill_bytes(&mut bytes);
+        Ok(Key { bytes: bytes })
+    }
+
+    /// Parse a base64-encoded key.
+    pub fn from_base64(encoded: &str) -> Result<Key> {
+        let decoded = try!(encoded.trim()
+            .from_base64()
+            .map_err(|e| err!("could not decode key: {}", e)));
+        if decoded.len() != KEY_LEN {
+            return Err(err!("key should be {} bytes long, not {}",
+                            KEY_LEN,
+                            decoded.len()));
+        }
+        let mut bytes = [0; KEY_LEN];
+        bytes.copy_from_slice(&decoded);
+        Ok(Key { bytes: bytes })
+    }
+
+    /// Encode this key as base64.
+    pub fn to_base64(&self) -> String {
+        self.bytes.to_base64(base64::STANDARD)
+    }
+
+    /// The path to the key file for `project`, which lives in the user's
+    /// home directory so that it doesn't get checked in along with the
+    /// data it protects.
+    pub fn path_for(project: &Project) -> Result<PathBuf> {
+        let home = try!(env::home_dir()
+            .ok_or_else(|| err("You do not appear to have a home directory")));
+        Ok(home.join(".cage").join("keys").join(format!("{}.key", project.name())))
+    }
+
+    /// Find the key for `project`, either in the environment variable
+    /// `CAGE_SECRETS_KEY` or in the file returned by `path_for`.
+    pub fn find(project: &Project) -> Result<Key> {
+        if let Ok(encoded) = env::var(KEY_VAR) {
+            return Key::from_base64(&encoded)
+                .chain_err(|| format!("invalid key in ${}", KEY_VAR));
+        }
+        let path = try!(Key::path_for(project));
+        if !path.exists() {
+            return Err(err!("Cannot find a key for {}.  Ask a teammate for the key and \
+                             put it in {} or set ${}",
+                            project.name(),
+                            path.display(),
+                            KEY_VAR));
+        }
+        let mkerr = || ErrorKind::CouldNotReadFile(path.clone());
+        let mut encoded = String::new();
+        let mut f = try!(fs::File::open(&path).chain_err(&mkerr));
+        try!(f.read_to_string(&mut encoded).chain_err(&mkerr));
+        Key::from_base64(&encoded).chain_err(&mkerr)
+    }
+
+    /// Find the key for `project`, or generate one and store it in the
+    /// file returned by `path_for`.  Returns `true` along with the key
+    /// if we generated it.
+    pub fn find_or_generate(project: &Project) -> Result<(Key, bool)> {
+        let path = try!(Key::path_for(project));
+        if env::var_os(KEY_VAR).is_some() || path.exists() {
+            Ok((try!(Key::find(project)), false))
+        } else {
+            let key = try!(Key::generate());
+            try!(write_private_file(&path, format!("{}\n", key.to_base64()).as_bytes()));
+            Ok((key, true))
+        }
+    }
+
+    /// Encrypt `plaintext`, returning the contents of an encrypted file.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String> {
+        let mut rng = try!(OsRng::new());
+        let mut nonce = [0; NONCE_LEN];
+        rng.fill_bytes(&mut nonce);
+
+        let mut cipher = ChaCha20Poly1305::new(&self.bytes, &nonce, HEADER.as_bytes());
+        let mut ciphertext = vec![0; plaintext.len()];
+        let mut tag = [0; TAG_LEN];
+        cipher.encrypt(plaintext, &mut ciphertext, &mut tag);
+
+        let mut data = Vec::with_capacity(NONCE_LEN + TAG_LEN + ciphertext.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&tag);
+        data.extend_from_slice(&ciphertext);
+        Ok(format!("{}\n{}\n", HEADER, data.to_base64(BASE64)))
+    }
+
+    /// Decrypt the contents of a file created by `encrypt`.
+    pub fn decrypt(&self, encrypted: &str) -> Result<Vec<u8>> {
+        let mut lines = encrypted.splitn(2, '\n');
+        if lines.next().map(|l| l.trim()) != Some(HEADER) {
+            return Err(err!("encrypted data does not start with {:?}", HEADER));
+        }
+        let data = try!(lines.next()
+            .unwrap_or("")
+            .from_base64()
+            .map_err(|e| err!("could not decode encrypted data: {}", e)));
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return Err(err("encrypted data is truncated"));
+        }
+        let (nonce, rest) = data.split_at(NONCE_LEN);
+        let (tag, ciphertext) = rest.split_at(TAG_LEN);
+
+        let mut cipher = ChaCha20Poly1305::new(&self.bytes, nonce, HEADER.as_bytes());
+        let mut plaintext = vec![0; ciphertext.len()];
+        if !cipher.decrypt(ciphertext, &mut plaintext, tag) {
+            return Err(err("could not decrypt data (do you have the right key?)"));
+        }
+        Ok(plaintext)
+    }
+}
+
+#[test]
+fn encrypt_and_decrypt_round_trip() {
+    let key = Key::generate().unwrap();
+    let encrypted = key.encrypt(b"PASSWORD: secret\n").unwrap();
+    assert!(encrypted.starts_with("cage-encrypted-v1\n"));
+    assert!(!encrypted.contains("secret"));
+    assert_eq!(key.decrypt(&encrypted).unwrap(), b"PASSWORD: secret\n");
+
+    // Each encryption uses a fresh random nonce.
+    let data = encrypted.splitn(2, '\n').nth(1).unwrap().from_base64().unwrap();
+    assert_eq!(data.len(), NONCE_LEN + TAG_LEN + 17);
+    let again = key.encrypt(b"PASSWORD: secret\n").unwrap();
+    assert!(again.splitn(2, '\n').nth(1).unwrap().from_base64().unwrap()[..NONCE_LEN] !=
+            data[..NONCE_LEN]);
+
+    // Make sure we can round-trip our key.
+    let key2 = Key::from_base64(&key.to_base64()).unwrap();
+    assert_eq!(key2.decrypt(&encrypted).unwrap(), b"PASSWORD: secret\n");
+
+    // The wrong key should fail.
+    let wrong_key = Key::generate().unwrap();
+    assert!(wrong_key.decrypt(&encrypted).is_err());
+}
@@ -11,6 +11,7 @@
 use compose_yml::v2 as dc;
 use glob;
 use semver;
+use serde_yaml;
 use std::ffi::OsString;
 use std::io;
 use std::path::{PathBuf, StripPrefixError};
@@ -34,6 +35,7 @@ error_chain! {
         glob::PatternError, GlobPattern;
         io::Error, Io;
         StripPrefixError, StripPrefix;
+        serde_yaml::Error, Yaml;
     }
 
     errors {
@@ -68,6 +70,21 @@ error_chain! {
                      compiled (you may want to rebuild from source)")
         }
 
+        /// The project configuration placed a plugin relative to a
+        /// built-in plugin which isn't active in this project.
+        InactivePlugin(plugin: String) {
+            description("plugin not active in this project")
+            display("plugin '{}' in {} is not active in this project",
+                    &plugin, PROJECT_CONFIG_PATH.display())
+        }
+
+        /// We could not parse a variable reference in a pod file.
+        InvalidInterpolation(text: String) {
+            description("invalid variable interpolation")
+            display("invalid variable interpolation in {:?} (use `$$` for a literal `$`)",
+                    &text)
+        }
+
         /// This project specified that it required a different version of
         /// this tool.
         MismatchedVersion(required: semver::VersionReq) {
@@ -90,6 +107,18 @@ error_chain! {
                     &names, base.display(), target.display())
         }
 
+        /// A pod file referred to a variable which wasn't defined.
+        UndefinedVariable(name: String) {
+            description("undefined variable")
+            display("undefined variable '{}' (define it in `config/vars.yml`)", &name)
+        }
+
+        /// The project configuration referred to a plugin we don't have.
+        UnknownPlugin(plugin: String) {
+            description("unknown plugin")
+            display("unknown plugin '{}' in {}", &plugin, PROJECT_CONFIG_PATH.display())
+        }
+
         /// The user tried to access an undefined library.
         ///
         /// TODO LOW: This will be merged with `UnknownSource` when library
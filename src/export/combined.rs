@@ -0,0 +1,248 @@
+//! Merge all our pods into a single `docker-compose.yml` file, for use by
+//! people and tools which run `docker-compose` without `cage`.
+
+use compose_yml::v2 as dc;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use errors::*;
+use export::{ExportedPod, merge_definitions, secret_vars, yaml_hash, yaml_str};
+use plugins::transform::shared_network;
+use pod::PodType;
+use project::Project;
+use util::{self, ConductorPathExt};
+
+/// Merge the service pods in `pods` into a single file, which will be
+/// written to `dir`.  Volumes and networks which appear in several pods
+/// are only declared once, and services which appear in more than one pod
+/// are reported as an error.  We leave out task pods, so that
+/// `docker-compose up` doesn't run them.
+///
+/// Since this file is likely to be checked in, secrets are replaced by
+/// `${VAR}` references for `docker-compose` to fill in from its own
+/// environment, and paths inside our project are made relative to `dir`.
+pub fn combine(project: &Project, pods: &[ExportedPod], dir: &Path) -> Result<dc::File> {
+    let dir = try!(dir.to_absolute());
+    let mut services = Hash::new();
+    let mut volumes = Hash::new();
+    let mut networks = Hash::new();
+    let mut service_pods: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for exported in pods {
+        if exported.pod.pod_type() == PodType::Task {
+            warn!("Not including task pod {} in combined file",
+                  exported.pod.name());
+            continue;
+        }
+        let mut file = exported.file.clone();
+        for (name, service) in &mut file.services {
+            try!(hide_secrets(project, exported.pod.name(), name, service));
+            try!(relativize_paths(project, &dir, service));
+        }
+        let yaml = serde_yaml::to_value(&file);
+        try!(merge_definitions(&mut volumes, &yaml["volumes"], "volume"));
+        try!(merge_definitions(&mut networks, &yaml["networks"], "network"));
+        if let Yaml::Hash(ref pod_services) = yaml["services"] {
+            for (name, service) in pod_services {
+                let name_str = try!(name.as_str()
+                    .ok_or_else(|| err!("expected service name to be a string")));
+                service_pods.entry(name_str.to_owned())
+                    .or_insert_with(Vec::new)
+                    .push(exported.pod.name());
+                services.insert(name.clone(), service.clone());
+            }
+        }
+    }
+
+    // Report every clash at once, so they can all be fixed together.
+    let clashes: Vec<String> = service_pods.iter()
+        .filter(|&(_, pods)| pods.len() > 1)
+        .map(|(service, pods)| format!("{} (in {})", service, pods.join(", ")))
+        .collect();
+    if !clashes.is_empty() {
+        return Err(err!("cannot combine pods because these services are defined in more \
+                         than one pod: {}",
+                        clashes.join("; ")));
+    }
+
+    // Everything is in one `docker-compose` project now, so we can let
+    // `docker-compose` create our shared network instead of expecting it
+    // to exist already.
+    let shared = shared_network::Plugin::network_name(project, project.current_target());
+    if let Some(network) = networks.get_mut(&yaml_str(&shared[..])) {
+        *network = yaml_hash(vec![]);
+    }
+
+    let mut top = vec![("version", yaml_str("2")), ("services", Yaml::Hash(services))];
+    if !volumes.is_empty() {
+        top.push(("volumes", Yaml::Hash(volumes)));
+    }
+    if !networks.is_empty() {
+        top.push(("networks", Yaml::Hash(networks)));
+    }
+    Ok(try!(serde_yaml::from_value(yaml_hash(top))))
+}
+
+/// Replace the value of each secret environment variable in
+/// `service_name` with a `${VAR}` reference.
+fn hide_secrets(project: &Project,
+                pod_name: &str,
+                service_name: &str,
+                service: &mut dc::Service)
+                -> Result<()> {
+    let secret_vars = try!(secret_vars(project, pod_name, service_name));
+    for (var, value) in &mut service.environment {
+        if secret_vars.contains(var) {
+            *value = format!("${{{}}}", var);
+        }
+    }
+    Ok(())
+}
+
+/// Make the paths in `service` which point into `project` relative to
+/// `dir`, so that the combined file works wherever the project is
+/// checked out.
+fn relativize_paths(project: &Project, dir: &Path, service: &mut dc::Service) -> Result<()> {
+    let project_dirs = vec![try!(project.root_dir().to_absolute()),
+                            try!(project.src_dir().to_absolute()),
+                            try!(project.output_dir().to_absolute())];
+    let relativize = |path: &mut PathBuf| {
+        let normalized = normalize(path);
+        if project_dirs.iter().any(|project_dir| normalized.starts_with(project_dir)) {
+            *path = relative_to(&normalized, dir);
+        }
+    };
+
+    if let Some(ref mut build) = service.build {
+        if let dc::Context::Dir(ref mut path) = *try!(build.context.value_mut()) {
+            relativize(path);
+        }
+    }
+    for volume in &mut service.volumes {
+        if let Some(dc::HostVolume::Path(ref mut path)) = try!(volume.value_mut()).host {
+            relativize(path);
+        }
+    }
+    Ok(())
+}
+
+/// Remove any `.` and `..` components from the absolute path `path`.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+    normalized
+}
+
+/// Express the absolute path `path` relative to the absolute path `dir`.
+fn relative_to(path: &Path, dir: &Path) -> PathBuf {
+    let common = path.components()
+        .zip(dir.components())
+        .take_while(|&(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in dir.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component.as_os_str());
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+/// Merge `pods` and write them to `path`, unless `path` is already up to
+/// date.
+pub fn write(project: &Project, pods: &[ExportedPod], path: &Path) -> Result<()> {
+    let dir = try!(path.parent()
+        .ok_or_else(|| err!("cannot find directory containing {}", path.display())));
+    let file = try!(combine(project, pods, dir));
+    let mut rendered = vec![];
+    try!(file.write(&mut rendered));
+    try!(util::write_if_changed(path, &rendered));
+    Ok(())
+}
+
+#[test]
+fn combines_pods_into_one_file() {
+    use env_logger;
+    use export::ExportFormat;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let export_dir = proj.output_dir().join("combined");
+    proj.export_as(ExportFormat::Combined, &export_dir).unwrap();
+    let file = dc::File::read_from_path(export_dir.join("docker-compose.yml")).unwrap();
+    let names: Vec<&str> = file.services.keys().map(|k| &k[..]).collect();
+    assert_eq!(names, &["db", "web"]);
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn replaces_secrets_and_relativizes_paths() {
+    use env_logger;
+    use plugins::Operation;
+    let _ = env_logger::init();
+
+    // `rails_hello` has a `config/secrets.yml` file.
+    let proj = Project::from_example("rails_hello").unwrap();
+    let source = proj.sources().find_by_alias("rails_hello").unwrap();
+    source.fake_clone_source(&proj).unwrap();
+    let pods = proj.processed_pods(Operation::Output).unwrap();
+    let file = combine(&proj, &pods, proj.output_dir()).unwrap();
+    let web = file.services.get("web").unwrap();
+    assert_eq!(web.environment.get("GLOBAL_PASSWORD").expect("has GLOBAL_PASSWORD"),
+               "${GLOBAL_PASSWORD}");
+
+    // Our source tree is mounted using a path relative to the file.
+    let src_path = source.path(&proj).strip_prefix(proj.output_dir()).unwrap().to_owned();
+    let mount = web.volumes
+        .last()
+        .expect("expected web service to have volumes")
+        .value()
+        .unwrap();
+    assert_eq!(mount.host, Some(dc::HostVolume::Path(src_path)));
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn computes_relative_paths() {
+    assert_eq!(relative_to(Path::new("/a/b/c"), Path::new("/a")), Path::new("b/c"));
+    assert_eq!(relative_to(Path::new("/a/b"), Path::new("/a/c/d")), Path::new("../../b"));
+    assert_eq!(relative_to(Path::new("/a"), Path::new("/a")), Path::new("."));
+    assert_eq!(normalize(Path::new("/a/b/../c/./d")), Path::new("/a/c/d"));
+}
+
+#[test]
+fn reports_services_defined_in_several_pods() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let frontend = proj.pod("frontend").unwrap();
+    let pods = vec![ExportedPod {
+                        pod: frontend,
+                        file: frontend.merged_file(proj.current_target()).unwrap(),
+                        original_labels: BTreeMap::new(),
+                    },
+                    ExportedPod {
+                        pod: frontend,
+                        file: frontend.merged_file(proj.current_target()).unwrap(),
+                        original_labels: BTreeMap::new(),
+                    }];
+    let err = combine(&proj, &pods, proj.root_dir()).unwrap_err();
+    assert!(err.to_string().contains("web (in frontend, frontend)"));
+}
@@ -0,0 +1,383 @@
+//! Export a project as Kubernetes manifests.
+//!
+//! Each service pod becomes a `Deployment` (plus a `Service` if it
+//! publishes any ports), and each task pod becomes a `Job`.  The services
+//! in a pod become containers in a single Kubernetes pod, which matches
+//! how `cage` itself thinks about pods.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use yaml_rust::Yaml;
+
+use errors::*;
+use export::{ExportedPod, ServiceInfo, VolumeSpec, secret_vars, write_yaml_docs, yaml_hash,
+             yaml_str, yaml_string_map};
+use plugins::transform::ports::PortSpec;
+use pod::PodType;
+use project::Project;
+
+/// Write a Kubernetes manifest for each pod in `pods` to `export_dir`.
+pub fn export(project: &Project, pods: &[ExportedPod], export_dir: &Path) -> Result<()> {
+    for exported in pods {
+        let docs = try!(pod_manifests(project, exported)
+            .chain_err(|| format!("could not convert {} to Kubernetes", exported.pod.name())));
+        let path = export_dir.join(format!("{}.yml", exported.pod.name()));
+        try!(write_yaml_docs(&path, &docs));
+    }
+    Ok(())
+}
+
+/// Convert `name` into something which Kubernetes will accept as an
+/// object name.
+fn k8s_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    name.trim_matches('-').to_lowercase()
+}
+
+/// The Kubernetes objects we're building for a single pod.
+#[derive(Debug, Default)]
+struct PodManifests {
+    /// The containers in our Kubernetes pod.
+    containers: Vec<Yaml>,
+    /// Volumes used by our containers, indexed by name.
+    volumes: BTreeMap<String, Yaml>,
+    /// Ports exposed by our Kubernetes `Service`, indexed by name.
+    service_ports: BTreeMap<String, Yaml>,
+    /// Annotations for our Kubernetes pod.
+    annotations: BTreeMap<String, String>,
+    /// `ConfigMap`, `Secret` and `PersistentVolumeClaim` objects needed
+    /// by our containers.
+    supporting: Vec<Yaml>,
+}
+
+/// Build all the Kubernetes objects for `exported`.
+fn pod_manifests(project: &Project, exported: &ExportedPod) -> Result<Vec<Yaml>> {
+    let pod = exported.pod;
+    let name = k8s_name(pod.name());
+    let mut manifests = PodManifests::default();
+    let mut claims = BTreeSet::new();
+    for (service_name, service) in &exported.file.services {
+        let info = try!(ServiceInfo::new(service));
+        let secret_vars = try!(secret_vars(project, pod.name(), service_name));
+
+        // Use the labels we had before the `labels` plugin removed our
+        // metadata, so that we can keep it as annotations.
+        let mut labels = exported.original_labels
+            .get(service_name)
+            .cloned()
+            .unwrap_or_else(BTreeMap::new);
+        labels.extend(info.labels.clone());
+
+        try!(add_container(&mut manifests,
+                           &mut claims,
+                           &name,
+                           service_name,
+                           &info,
+                           &labels,
+                           &secret_vars));
+    }
+
+    // Kubernetes uses labels to find the pods belonging to a `Deployment`
+    // or `Service`, so we always use the ones we'd use with `docker ps`.
+    let mut labels = BTreeMap::new();
+    labels.insert("io.fdy.cage.target".to_owned(),
+                  project.current_target().name().to_owned());
+    labels.insert("io.fdy.cage.pod".to_owned(), pod.name().to_owned());
+    let metadata = yaml_hash(vec![("name", yaml_str(&name[..])),
+                                  ("labels", yaml_string_map(&labels))]);
+
+    let mut pod_spec = vec![("containers", Yaml::Array(manifests.containers))];
+    if !manifests.volumes.is_empty() {
+        let volumes = manifests.volumes.into_iter().map(|(_, v)| v).collect();
+        pod_spec.push(("volumes", Yaml::Array(volumes)));
+    }
+    if pod.pod_type() == PodType::Task {
+        pod_spec.push(("restartPolicy", yaml_str("Never")));
+    }
+    let template =
+        yaml_hash(vec![("metadata",
+                        yaml_hash(vec![("labels", yaml_string_map(&labels)),
+                                       ("annotations",
+                                        yaml_string_map(&manifests.annotations))])),
+                       ("spec", yaml_hash(pod_spec))]);
+
+    let mut docs = manifests.supporting;
+    for claim in claims {
+        docs.push(persistent_volume_claim(&claim));
+    }
+    if pod.pod_type() == PodType::Task {
+        docs.push(yaml_hash(vec![("apiVersion", yaml_str("batch/v1")),
+                                 ("kind", yaml_str("Job")),
+                                 ("metadata", metadata),
+                                 ("spec", yaml_hash(vec![("template", template)]))]));
+    } else {
+        let selector = yaml_hash(vec![("matchLabels", yaml_string_map(&labels))]);
+        docs.push(yaml_hash(vec![("apiVersion", yaml_str("apps/v1")),
+                                 ("kind", yaml_str("Deployment")),
+                                 ("metadata", metadata.clone()),
+                                 ("spec",
+                                  yaml_hash(vec![("replicas", Yaml::Integer(1)),
+                                                 ("selector", selector),
+                                                 ("template", template)]))]));
+        if !manifests.service_ports.is_empty() {
+            let ports = manifests.service_ports.into_iter().map(|(_, p)| p).collect();
+            docs.push(yaml_hash(vec![("apiVersion", yaml_str("v1")),
+                                     ("kind", yaml_str("Service")),
+                                     ("metadata", metadata),
+                                     ("spec",
+                                      yaml_hash(vec![("selector", yaml_string_map(&labels)),
+                                                     ("ports", Yaml::Array(ports))]))]));
+        }
+    }
+    Ok(docs)
+}
+
+/// Add a container for `service_name` to `manifests`, along with any
+/// supporting objects it needs.  Named volumes are added to `claims`, and
+/// `labels` become annotations.
+fn add_container(manifests: &mut PodManifests,
+                 claims: &mut BTreeSet<String>,
+                 pod_name: &str,
+                 service_name: &str,
+                 info: &ServiceInfo,
+                 labels: &BTreeMap<String, String>,
+                 secret_vars: &BTreeSet<String>)
+                 -> Result<()> {
+    let name = k8s_name(service_name);
+    let mut container = vec![("name", yaml_str(&name[..]))];
+    if let Some(ref image) = info.image {
+        container.push(("image", yaml_str(&image[..])));
+    } else {
+        return Err(err!("{} has no image", service_name));
+    }
+    if let Some(ref entrypoint) = info.entrypoint {
+        container.push(("command", yaml_strings(entrypoint)));
+    }
+    if let Some(ref command) = info.command {
+        container.push(("args", yaml_strings(command)));
+    }
+    if let Some(ref working_dir) = info.working_dir {
+        container.push(("workingDir", yaml_str(&working_dir[..])));
+    }
+
+    // Put our environment variables into a `ConfigMap` and (if we have
+    // any secrets) a `Secret`.
+    let (secret_env, plain_env): (BTreeMap<String, String>, BTreeMap<String, String>) =
+        info.environment
+            .clone()
+            .into_iter()
+            .partition(|&(ref var, _)| secret_vars.contains(var));
+    let mut env_from = vec![];
+    if !plain_env.is_empty() {
+        let env_name = format!("{}-{}-env", pod_name, name);
+        manifests.supporting.push(yaml_hash(vec![("apiVersion", yaml_str("v1")),
+                                                 ("kind", yaml_str("ConfigMap")),
+                                                 ("metadata", name_metadata(&env_name)),
+                                                 ("data", yaml_string_map(&plain_env))]));
+        env_from.push(yaml_hash(vec![("configMapRef", name_metadata(&env_name))]));
+    }
+    if !secret_env.is_empty() {
+        let secret_name = format!("{}-{}-secrets", pod_name, name);
+        manifests.supporting.push(yaml_hash(vec![("apiVersion", yaml_str("v1")),
+                                                 ("kind", yaml_str("Secret")),
+                                                 ("metadata", name_metadata(&secret_name)),
+                                                 ("type", yaml_str("Opaque")),
+                                                 ("stringData", yaml_string_map(&secret_env))]));
+        env_from.push(yaml_hash(vec![("secretRef", name_metadata(&secret_name))]));
+    }
+    if !env_from.is_empty() {
+        container.push(("envFrom", Yaml::Array(env_from)));
+    }
+
+    // Expose our published ports.
+    let mut ports = vec![];
+    for spec in &info.ports {
+        let spec = PortSpec::parse(spec);
+        let mut parts = spec.container_port.splitn(2, '/');
+        let port_str = parts.next().unwrap_or("");
+        let container_port: i64 = try!(port_str.parse()
+            .map_err(|_| err!("cannot convert port range {} to Kubernetes", spec.container_port)));
+        let protocol = match parts.next() {
+            Some("udp") => "UDP",
+            _ => "TCP",
+        };
+        let port = spec.host_port
+            .and_then(|p| p.parse::<i64>().ok())
+            .unwrap_or(container_port);
+        ports.push(yaml_hash(vec![("containerPort", Yaml::Integer(container_port)),
+                                  ("protocol", yaml_str(protocol))]));
+        let port_name = format!("{}-{}", protocol.to_lowercase(), port);
+        manifests.service_ports.insert(port_name.clone(),
+                                       yaml_hash(vec![("name", yaml_str(port_name)),
+                                                      ("port", Yaml::Integer(port)),
+                                                      ("targetPort",
+                                                       Yaml::Integer(container_port)),
+                                                      ("protocol", yaml_str(protocol))]));
+    }
+    if !ports.is_empty() {
+        container.push(("ports", Yaml::Array(ports)));
+    }
+
+    // Mount our volumes, using claims for named volumes.
+    let mut mounts = vec![];
+    for (i, spec) in info.volumes.iter().enumerate() {
+        let spec = VolumeSpec::parse(spec);
+        let (volume_name, source) = match spec.source {
+            Some(source) if spec.is_named() => {
+                let claim = k8s_name(source);
+                claims.insert(claim.clone());
+                (claim.clone(),
+                 ("persistentVolumeClaim",
+                  yaml_hash(vec![("claimName", yaml_str(claim))])))
+            }
+            Some(source) => {
+                warn!("{} mounts host path {}, which may not exist on Kubernetes nodes",
+                      service_name,
+                      source);
+                (format!("{}-{}", name, i),
+                 ("hostPath", yaml_hash(vec![("path", yaml_str(source))])))
+            }
+            None => (format!("{}-{}", name, i), ("emptyDir", yaml_hash(vec![]))),
+        };
+        manifests.volumes.insert(volume_name.clone(),
+                                 yaml_hash(vec![("name", yaml_str(&volume_name[..])), source]));
+        let mut mount = vec![("name", yaml_str(volume_name)),
+                             ("mountPath", yaml_str(spec.target))];
+        if spec.read_only {
+            mount.push(("readOnly", Yaml::Boolean(true)));
+        }
+        mounts.push(yaml_hash(mount));
+    }
+    if !mounts.is_empty() {
+        container.push(("volumeMounts", Yaml::Array(mounts)));
+    }
+
+    // Labels become annotations, since Kubernetes restricts label values
+    // and we only use a few labels for selecting pods.
+    for (label, value) in labels {
+        if label != "io.fdy.cage.target" && label != "io.fdy.cage.pod" {
+            manifests.annotations.insert(format!("{}.{}", name, label), value.to_owned());
+        }
+    }
+
+    for field in info.other.keys() {
+        warn!("{}: cannot convert `{}` to Kubernetes", service_name, field);
+    }
+
+    manifests.containers.push(yaml_hash(container));
+    Ok(())
+}
+
+/// Metadata containing only a name.
+fn name_metadata(name: &str) -> Yaml {
+    yaml_hash(vec![("name", yaml_str(name))])
+}
+
+/// Convert a list of strings to YAML.
+fn yaml_strings(strs: &[String]) -> Yaml {
+    Yaml::Array(strs.iter().map(|s| yaml_str(&s[..])).collect())
+}
+
+/// A default claim for the named volume `name`.
+fn persistent_volume_claim(name: &str) -> Yaml {
+    let requests = yaml_hash(vec![("storage", yaml_str("1Gi"))]);
+    yaml_hash(vec![("apiVersion", yaml_str("v1")),
+                   ("kind", yaml_str("PersistentVolumeClaim")),
+                   ("metadata", name_metadata(name)),
+                   ("spec",
+                    yaml_hash(vec![("accessModes",
+                                    Yaml::Array(vec![yaml_str("ReadWriteOnce")])),
+                                   ("resources",
+                                    yaml_hash(vec![("requests", requests)]))]))])
+}
+
+#[test]
+fn exports_pods_as_kubernetes_manifests() {
+    use env_logger;
+    use export::ExportFormat;
+    use std::fs;
+    use std::io::Read;
+    use yaml_rust::YamlLoader;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let export_dir = proj.output_dir().join("kubernetes");
+    proj.export_as(ExportFormat::Kubernetes, &export_dir).unwrap();
+
+    let load = |name: &str| -> Vec<Yaml> {
+        let mut yaml = String::new();
+        fs::File::open(export_dir.join(name)).unwrap().read_to_string(&mut yaml).unwrap();
+        YamlLoader::load_from_str(&yaml).unwrap()
+    };
+    let kinds = |docs: &[Yaml]| -> Vec<String> {
+        docs.iter().map(|d| d["kind"].as_str().unwrap().to_owned()).collect()
+    };
+
+    // Service pods become deployments, with services for their ports.
+    let frontend = load("frontend.yml");
+    assert_eq!(kinds(&frontend),
+               &["ConfigMap", "Secret", "Deployment", "Service"]);
+    let env = &frontend[0]["data"];
+    assert!(env["GLOBAL_PASSWORD"].is_badvalue());
+    assert_eq!(frontend[1]["stringData"]["GLOBAL_PASSWORD"].as_str(),
+               Some("more magic"));
+    let deployment = &frontend[2];
+    assert_eq!(deployment["spec"]["selector"]["matchLabels"]["io.fdy.cage.pod"].as_str(),
+               Some("frontend"));
+    let annotations = &deployment["spec"]["template"]["metadata"]["annotations"];
+    assert_eq!(annotations["web.io.fdy.cage.shell"].as_str(), Some("bash"));
+    assert_eq!(annotations["web.io.fdy.cage.test"].as_str(),
+               Some("bundle exec rake"));
+    assert!(annotations["web.io.fdy.cage.pod"].is_badvalue());
+    let web = &deployment["spec"]["template"]["spec"]["containers"][0];
+    assert_eq!(web["name"].as_str(), Some("web"));
+    assert_eq!(web["ports"][0]["containerPort"].as_i64(), Some(3000));
+    assert_eq!(frontend[3]["spec"]["ports"][0]["port"].as_i64(), Some(80));
+
+    // Task pods become jobs.
+    let rake = load("rake.yml");
+    assert_eq!(kinds(&rake).last().unwrap(), "Job");
+    assert_eq!(rake.last().unwrap()["spec"]["template"]["spec"]["restartPolicy"].as_str(),
+               Some("Never"));
+
+    // Disabled pods aren't exported.
+    assert!(!export_dir.join("db.yml").exists());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+#[cfg(feature="vault")]
+fn puts_vault_vars_in_secrets() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let vars = secret_vars(&proj, "frontend", "web").unwrap();
+    let mut info = ServiceInfo::default();
+    info.image = Some("example/web".to_owned());
+    info.environment.insert("DATABASE_PASSWORD".to_owned(), "secret".to_owned());
+    info.environment.insert("VAULT_ENV".to_owned(), "production".to_owned());
+    info.environment.insert("RAILS_ENV".to_owned(), "production".to_owned());
+
+    let mut manifests = PodManifests::default();
+    let mut claims = BTreeSet::new();
+    add_container(&mut manifests,
+                  &mut claims,
+                  "frontend",
+                  "web",
+                  &info,
+                  &BTreeMap::new(),
+                  &vars)
+        .unwrap();
+    let config_map = &manifests.supporting[0];
+    let secret = &manifests.supporting[1];
+    assert_eq!(config_map["data"]["RAILS_ENV"].as_str(), Some("production"));
+    assert!(config_map["data"]["DATABASE_PASSWORD"].is_badvalue());
+    assert_eq!(secret["stringData"]["DATABASE_PASSWORD"].as_str(), Some("secret"));
+    assert_eq!(secret["stringData"]["VAULT_ENV"].as_str(), Some("production"));
+}
@@ -0,0 +1,420 @@
+//! Export formats for deployment tools other than `docker-compose`.
+//!
+//! Each exporter receives our pods after they've been flattened and run
+//! through the usual transform pipeline using `Operation::Export`, so
+//! default tags, secrets and other plugins are handled exactly as they
+//! are for `cage export`'s default output.
+
+use compose_yml::v2 as dc;
+use serde_yaml;
+use shlex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use errors::*;
+use plugins::transform::secrets;
+#[cfg(feature="vault")]
+use plugins::transform::vault;
+use pod::Pod;
+use project::Project;
+use serde_helpers::yaml_value_to_string;
+use util::ConductorPathExt;
+
+pub mod combined;
+pub mod kubernetes;
+pub mod nomad;
+pub mod stack;
+pub mod systemd;
+
+/// Environment variables which are always treated as secrets, in
+/// addition to those supplied by the `secrets` and `vault` plugins.
+const SECRET_VARS: &'static [&'static str] = &["VAULT_TOKEN", "VAULT_ROLE_ID", "VAULT_SECRET_ID"];
+
+/// The formats supported by `cage export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One standalone `docker-compose.yml` file per pod, with tasks in a
+    /// `tasks` subdirectory.
+    Compose,
+    /// A single `docker-compose.yml` file containing all our pods.
+    Combined,
+    /// Kubernetes manifests, one file per pod.
+    Kubernetes,
+    /// Nomad job specifications in JSON format, one file per pod.
+    Nomad,
+    /// A single version 3 stack file containing all our service pods.
+    Stack,
+    /// One systemd unit per service, for running on a single host.
+    Systemd,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExportFormat> {
+        match s {
+            "compose" => Ok(ExportFormat::Compose),
+            "combined" => Ok(ExportFormat::Combined),
+            "kubernetes" => Ok(ExportFormat::Kubernetes),
+            "nomad" => Ok(ExportFormat::Nomad),
+            "stack" => Ok(ExportFormat::Stack),
+            "systemd" => Ok(ExportFormat::Systemd),
+            _ => Err(err!("unknown export format '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ExportFormat::Compose => "compose",
+            ExportFormat::Combined => "combined",
+            ExportFormat::Kubernetes => "kubernetes",
+            ExportFormat::Nomad => "nomad",
+            ExportFormat::Stack => "stack",
+            ExportFormat::Systemd => "systemd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A pod which has been flattened and transformed for export.
+#[derive(Debug)]
+pub struct ExportedPod<'a> {
+    /// The pod we exported.
+    pub pod: &'a Pod,
+    /// The transformed version of the pod's `docker-compose.yml` file.
+    pub file: dc::File,
+    /// The labels of each service before our plugins ran, indexed by
+    /// service name.  The `labels` plugin removes cage's metadata labels
+    /// from exported files, but some formats can use them elsewhere.
+    pub original_labels: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// The parts of a service which our exporters know how to convert.  We
+/// read these from the service's `docker-compose.yml` representation,
+/// which is the same for both literal values and interpolated ones.
+#[derive(Debug, Default)]
+pub struct ServiceInfo {
+    /// The image to run.
+    pub image: Option<String>,
+    /// The command to run, overriding the image's default.
+    pub command: Option<Vec<String>>,
+    /// The entrypoint to use, overriding the image's default.
+    pub entrypoint: Option<Vec<String>>,
+    /// The working directory for `command`.
+    pub working_dir: Option<String>,
+    /// Environment variables.
+    pub environment: BTreeMap<String, String>,
+    /// Published ports, in `docker-compose` syntax.
+    pub ports: Vec<String>,
+    /// Volume mounts, in `docker-compose` syntax.
+    pub volumes: Vec<String>,
+    /// Docker labels.
+    pub labels: BTreeMap<String, String>,
+    /// Any other fields, which an exporter may not know how to convert.
+    pub other: BTreeMap<String, Yaml>,
+}
+
+impl ServiceInfo {
+    /// Extract information about `service`.
+    pub fn new(service: &dc::Service) -> Result<ServiceInfo> {
+        let hash = match serde_yaml::to_value(service) {
+            Yaml::Hash(hash) => hash,
+            _ => return Err(err!("expected service to serialize as a hash")),
+        };
+        let mut info = ServiceInfo::default();
+        for (key, value) in hash {
+            let key = try!(key.as_str()
+                    .ok_or_else(|| err!("expected service keys to be strings")))
+                .to_owned();
+            match &key[..] {
+                "image" => info.image = Some(try!(yaml_to_string(&key, &value))),
+                "command" => info.command = Some(try!(yaml_to_words(&key, &value))),
+                "entrypoint" => info.entrypoint = Some(try!(yaml_to_words(&key, &value))),
+                "working_dir" => info.working_dir = Some(try!(yaml_to_string(&key, &value))),
+                "environment" => info.environment = try!(yaml_to_string_map(&key, &value)),
+                "ports" => info.ports = try!(yaml_to_strings(&key, &value)),
+                "volumes" => info.volumes = try!(yaml_to_strings(&key, &value)),
+                "labels" => info.labels = try!(yaml_to_string_map(&key, &value)),
+                _ => {
+                    info.other.insert(key, value);
+                }
+            }
+        }
+
+        // `docker-compose` files write a literal `$` as `$$`, but our
+        // exporters apply their own quoting, so undo that escape here.
+        {
+            let strings = info.image
+                .iter_mut()
+                .chain(info.working_dir.iter_mut())
+                .chain(info.command.iter_mut().flat_map(|words| words.iter_mut()))
+                .chain(info.entrypoint.iter_mut().flat_map(|words| words.iter_mut()))
+                .chain(info.environment.values_mut())
+                .chain(info.ports.iter_mut())
+                .chain(info.volumes.iter_mut())
+                .chain(info.labels.values_mut());
+            for s in strings {
+                *s = s.replace("$$", "$");
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// A volume mount, written using `docker-compose`'s
+/// `[SOURCE:]TARGET[:MODE]` syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VolumeSpec<'a> {
+    /// A named volume or a host path, if any.
+    pub source: Option<&'a str>,
+    /// The path inside the container.
+    pub target: &'a str,
+    /// Is this mount read-only?
+    pub read_only: bool,
+}
+
+impl<'a> VolumeSpec<'a> {
+    /// Parse a volume specification.
+    pub fn parse(spec: &'a str) -> VolumeSpec<'a> {
+        let parts: Vec<&str> = spec.splitn(3, ':').collect();
+        match parts.len() {
+            1 => {
+                VolumeSpec {
+                    source: None,
+                    target: parts[0],
+                    read_only: false,
+                }
+            }
+            2 if parts[1] == "ro" || parts[1] == "rw" => {
+                VolumeSpec {
+                    source: None,
+                    target: parts[0],
+                    read_only: parts[1] == "ro",
+                }
+            }
+            _ => {
+                VolumeSpec {
+                    source: Some(parts[0]),
+                    target: parts[1],
+                    read_only: parts.get(2) == Some(&"ro"),
+                }
+            }
+        }
+    }
+
+    /// Does this mount refer to a named volume, as opposed to a path on
+    /// the host or an anonymous volume?
+    pub fn is_named(&self) -> bool {
+        self.source.map_or(false, |source| {
+            !(source.starts_with('/') || source.starts_with('.') || source.starts_with('~'))
+        })
+    }
+}
+
+/// Convert a scalar YAML value to a string.  `name` is used for error
+/// messages.
+pub fn yaml_to_string(name: &str, value: &Yaml) -> Result<String> {
+    match *value {
+        Yaml::String(ref s) |
+        Yaml::Real(ref s) => Ok(s.to_owned()),
+        Yaml::Integer(i) => Ok(i.to_string()),
+        Yaml::Boolean(b) => Ok(b.to_string()),
+        _ => Err(err!("expected `{}` to be a string", name)),
+    }
+}
+
+/// Convert a YAML list of scalars to strings.
+fn yaml_to_strings(name: &str, value: &Yaml) -> Result<Vec<String>> {
+    match *value {
+        Yaml::Array(ref items) => items.iter().map(|item| yaml_to_string(name, item)).collect(),
+        Yaml::Null => Ok(vec![]),
+        _ => Err(err!("expected `{}` to be a list", name)),
+    }
+}
+
+/// Convert a YAML command, which may be either a list or a string
+/// containing shell words, to a list of words.
+fn yaml_to_words(name: &str, value: &Yaml) -> Result<Vec<String>> {
+    if let Yaml::Array(_) = *value {
+        return yaml_to_strings(name, value);
+    }
+    let raw = try!(yaml_to_string(name, value));
+    let mut lexer = shlex::Shlex::new(&raw);
+    let words: Vec<String> = lexer.by_ref().collect();
+    if lexer.had_error {
+        Err(err!("cannot parse `{}` <{}> into shell words", name, raw))
+    } else {
+        Ok(words)
+    }
+}
+
+/// The labels attached to `service`.
+pub fn service_labels(service: &dc::Service) -> Result<BTreeMap<String, String>> {
+    match serde_yaml::to_value(service)["labels"] {
+        Yaml::BadValue => Ok(BTreeMap::new()),
+        ref labels => yaml_to_string_map("labels", labels),
+    }
+}
+
+/// The environment variables of `service_name` in `pod_name` which
+/// contain secrets, and which shouldn't be written out in plain text.
+pub fn secret_vars(project: &Project,
+                   pod_name: &str,
+                   service_name: &str)
+                   -> Result<BTreeSet<String>> {
+    let mut vars = try!(secrets::Plugin::secret_vars(project, pod_name, service_name));
+    vars.extend(try!(vault_vars(project, pod_name, service_name)));
+    vars.extend(SECRET_VARS.iter().map(|&var| var.to_owned()));
+    Ok(vars)
+}
+
+/// The environment variables which the `vault` plugin adds to
+/// `service_name` in `pod_name`.
+#[cfg(feature="vault")]
+fn vault_vars(project: &Project, pod_name: &str, service_name: &str) -> Result<BTreeSet<String>> {
+    vault::Plugin::secret_vars(project, pod_name, service_name)
+}
+
+/// The environment variables which the `vault` plugin adds to a service,
+/// in builds without the `vault` plugin.
+#[cfg(not(feature="vault"))]
+fn vault_vars(_project: &Project,
+              _pod_name: &str,
+              _service_name: &str)
+              -> Result<BTreeSet<String>> {
+    Ok(BTreeSet::new())
+}
+
+/// Convert a YAML hash of scalars to a map of strings.
+fn yaml_to_string_map(name: &str, value: &Yaml) -> Result<BTreeMap<String, String>> {
+    match *value {
+        Yaml::Hash(ref hash) => {
+            hash.iter()
+                .map(|(k, v)| Ok((try!(yaml_to_string(name, k)), try!(yaml_to_string(name, v)))))
+                .collect()
+        }
+        Yaml::Null => Ok(BTreeMap::new()),
+        _ => Err(err!("expected `{}` to be a hash", name)),
+    }
+}
+
+/// Convert `s` to a YAML string.
+pub fn yaml_str<S: Into<String>>(s: S) -> Yaml {
+    Yaml::String(s.into())
+}
+
+/// Build a YAML hash from a list of keys and values.
+pub fn yaml_hash(entries: Vec<(&str, Yaml)>) -> Yaml {
+    let hash: Hash = entries.into_iter().map(|(k, v)| (yaml_str(k), v)).collect();
+    Yaml::Hash(hash)
+}
+
+/// Build a YAML hash from a map of strings.
+pub fn yaml_string_map(map: &BTreeMap<String, String>) -> Yaml {
+    let hash: Hash = map.iter().map(|(k, v)| (yaml_str(&k[..]), yaml_str(&v[..]))).collect();
+    Yaml::Hash(hash)
+}
+
+/// Add the top-level `volumes` or `networks` in `defs` to `all`, making
+/// sure that pods which share a definition agree about it.
+pub fn merge_definitions(all: &mut Hash, defs: &Yaml, kind: &str) -> Result<()> {
+    match *defs {
+        Yaml::Hash(ref defs) => {
+            for (name, def) in defs {
+                match all.get(name) {
+                    Some(existing) if existing != def => {
+                        return Err(err!("{} {} has conflicting definitions",
+                                        kind,
+                                        name.as_str().unwrap_or("(unknown)")));
+                    }
+                    _ => {}
+                }
+                all.insert(name.clone(), def.clone());
+            }
+            Ok(())
+        }
+        Yaml::Null | Yaml::BadValue => Ok(()),
+        _ => Err(err!("expected `{}s` to be a hash", kind)),
+    }
+}
+
+/// Write `docs` to `path` as a YAML file containing multiple documents.
+pub fn write_yaml_docs(path: &Path, docs: &[Yaml]) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    let mut out = String::new();
+    for doc in docs {
+        out.push_str(&try!(yaml_value_to_string(doc).chain_err(&mkerr)));
+    }
+    try!(path.with_guaranteed_parent().chain_err(&mkerr));
+    let mut f = try!(fs::File::create(path).chain_err(&mkerr));
+    try!(f.write_all(out.as_bytes()).chain_err(&mkerr));
+    Ok(())
+}
+
+#[test]
+fn volume_specs_can_be_parsed() {
+    let data = VolumeSpec::parse("data:/var/lib/data");
+    assert_eq!(data.source, Some("data"));
+    assert_eq!(data.target, "/var/lib/data");
+    assert!(data.is_named());
+    assert!(!data.read_only);
+
+    let host = VolumeSpec::parse("/etc/certs:/certs:ro");
+    assert!(!host.is_named());
+    assert!(host.read_only);
+
+    let anonymous = VolumeSpec::parse("/tmp");
+    assert_eq!(anonymous.source, None);
+    assert!(!anonymous.is_named());
+}
+
+#[test]
+fn service_info_unescapes_interpolated_dollar_signs() {
+    let file = dc::File::from_str(r#"---
+version: "2"
+services:
+  web:
+    image: "example/web:1.0"
+    command: "echo $$HOME"
+    environment:
+      PRICE: "$$5"
+"#)
+        .unwrap();
+    let info = ServiceInfo::new(file.services.get("web").unwrap()).unwrap();
+    assert_eq!(info.command.unwrap(), &["echo", "$HOME"]);
+    assert_eq!(info.environment.get("PRICE").unwrap(), "$5");
+}
+
+#[test]
+fn service_info_reads_compose_fields() {
+    let file = dc::File::from_str(r#"---
+version: "2"
+services:
+  web:
+    image: "example/web:1.0"
+    command: "bundle exec 'rails server'"
+    environment:
+      PORT: "3000"
+    ports:
+    - "3000:3000"
+    labels:
+      io.fdy.cage.shell: "bash"
+    restart: "always"
+"#)
+        .unwrap();
+    let info = ServiceInfo::new(file.services.get("web").unwrap()).unwrap();
+    assert_eq!(info.image.unwrap(), "example/web:1.0");
+    assert_eq!(info.command.unwrap(), &["bundle", "exec", "rails server"]);
+    assert_eq!(info.environment.get("PORT").unwrap(), "3000");
+    assert_eq!(info.ports, &["3000:3000"]);
+    assert_eq!(info.labels.get("io.fdy.cage.shell").unwrap(), "bash");
+    assert_eq!(info.other.get("restart"), Some(&yaml_str("always")));
+}
@@ -0,0 +1,294 @@
+//! Export a project as Nomad job specifications, in JSON format.
+//!
+//! Each pod becomes a job containing a single task group, with one task
+//! per service using the `docker` driver.  Service pods become `service`
+//! jobs, and task pods become `batch` jobs.
+
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use errors::*;
+use export::{ExportedPod, ServiceInfo, VolumeSpec};
+use plugins::transform::ports::PortSpec;
+use pod::{DeployConfig, PodType, Resources};
+use project::Project;
+use util::ConductorPathExt;
+
+/// Nomad measures CPU in MHz, so we need to decide how fast a "CPU" is
+/// when converting `deploy.resources` from our pod metadata.
+const MHZ_PER_CPU: f64 = 1000.0;
+
+/// Write a Nomad job for each pod in `pods` to `export_dir`.
+pub fn export(project: &Project, pods: &[ExportedPod], export_dir: &Path) -> Result<()> {
+    for exported in pods {
+        let job = try!(pod_job(project, exported)
+            .chain_err(|| format!("could not convert {} to a Nomad job", exported.pod.name())));
+        let path = export_dir.join(format!("{}.json", exported.pod.name()));
+        try!(write_json(&path, &job));
+    }
+    Ok(())
+}
+
+/// Build a JSON object from a list of keys and values.
+fn json_obj(entries: Vec<(&str, Json)>) -> Json {
+    Json::Object(entries.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+/// Build a JSON object from a map of strings.
+fn json_string_map(map: &BTreeMap<String, String>) -> Json {
+    Json::Object(map.iter().map(|(k, v)| (k.to_owned(), Json::String(v.to_owned()))).collect())
+}
+
+/// Build a JSON array from a list of strings.
+fn json_strings(strs: &[String]) -> Json {
+    Json::Array(strs.iter().map(|s| Json::String(s.to_owned())).collect())
+}
+
+/// Build the Nomad job for `exported`.
+fn pod_job(project: &Project, exported: &ExportedPod) -> Result<Json> {
+    let pod = exported.pod;
+    let job_type = match pod.pod_type() {
+        PodType::Task => "batch",
+        PodType::Placeholder | PodType::Service => "service",
+    };
+    let deploy = pod.deploy().cloned().unwrap_or_else(DeployConfig::default);
+
+    let mut tasks = vec![];
+    let mut reserved_ports = vec![];
+    let mut dynamic_ports = vec![];
+    for (service_name, service) in &exported.file.services {
+        let info = try!(ServiceInfo::new(service));
+        let (task, reserved, dynamic) = try!(service_task(service_name, &info, &deploy.resources));
+        tasks.push(task);
+        reserved_ports.extend(reserved);
+        dynamic_ports.extend(dynamic);
+    }
+
+    let mut group = vec![("Name", Json::String(pod.name().to_owned())),
+                         ("Count", Json::U64(deploy.replicas.unwrap_or(1) as u64)),
+                         ("Tasks", Json::Array(tasks))];
+    if !reserved_ports.is_empty() || !dynamic_ports.is_empty() {
+        let network = json_obj(vec![("ReservedPorts", Json::Array(reserved_ports)),
+                                    ("DynamicPorts", Json::Array(dynamic_ports))]);
+        group.push(("Networks", Json::Array(vec![network])));
+    }
+
+    let mut meta = BTreeMap::new();
+    meta.insert("io.fdy.cage.target".to_owned(),
+                project.current_target().name().to_owned());
+    meta.insert("io.fdy.cage.pod".to_owned(), pod.name().to_owned());
+
+    let id = format!("{}-{}", project.name(), pod.name());
+    let job = json_obj(vec![("ID", Json::String(id.clone())),
+                            ("Name", Json::String(id)),
+                            ("Type", Json::String(job_type.to_owned())),
+                            ("Datacenters", Json::Array(vec![Json::String("dc1".to_owned())])),
+                            ("Meta", json_string_map(&meta)),
+                            ("TaskGroups", Json::Array(vec![json_obj(group)]))]);
+    Ok(json_obj(vec![("Job", job)]))
+}
+
+/// Build a Nomad task for `service_name`, along with the reserved and
+/// dynamic ports it needs.
+fn service_task(service_name: &str,
+                info: &ServiceInfo,
+                resources: &Resources)
+                -> Result<(Json, Vec<Json>, Vec<Json>)> {
+    let image = try!(info.image
+        .as_ref()
+        .ok_or_else(|| err!("{} has no image", service_name)));
+    let mut config = vec![("image", Json::String(image.to_owned()))];
+    if let Some(ref entrypoint) = info.entrypoint {
+        config.push(("entrypoint", json_strings(entrypoint)));
+    }
+    if let Some(ref command) = info.command {
+        if let Some((first, rest)) = command.split_first() {
+            config.push(("command", Json::String(first.to_owned())));
+            if !rest.is_empty() {
+                config.push(("args", json_strings(rest)));
+            }
+        }
+    }
+    if let Some(ref working_dir) = info.working_dir {
+        config.push(("work_dir", Json::String(working_dir.to_owned())));
+    }
+
+    // Label each published port, and reserve it if it has a fixed host
+    // port.
+    let mut port_labels = vec![];
+    let mut reserved = vec![];
+    let mut dynamic = vec![];
+    for spec in &info.ports {
+        let spec = PortSpec::parse(spec);
+        let port_str = spec.container_port.splitn(2, '/').next().unwrap_or("");
+        let container_port: u64 = try!(port_str.parse()
+            .map_err(|_| err!("cannot convert port range {} to Nomad", spec.container_port)));
+        let label: String = format!("{}_{}", service_name, container_port)
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        let mut port = vec![("Label", Json::String(label.clone())),
+                            ("To", Json::U64(container_port))];
+        match spec.host_port.and_then(|p| p.parse::<u64>().ok()) {
+            Some(host_port) => {
+                port.push(("Value", Json::U64(host_port)));
+                reserved.push(json_obj(port));
+            }
+            None => dynamic.push(json_obj(port)),
+        }
+        port_labels.push(label);
+    }
+    if !port_labels.is_empty() {
+        config.push(("ports", json_strings(&port_labels)));
+    }
+
+    // Host paths can be passed straight to Docker, but named volumes need
+    // to be mounted explicitly.
+    let mut volumes = vec![];
+    let mut mounts = vec![];
+    for spec in &info.volumes {
+        let parsed = VolumeSpec::parse(spec);
+        match parsed.source {
+            Some(source) if parsed.is_named() => {
+                mounts.push(json_obj(vec![("type", Json::String("volume".to_owned())),
+                                          ("source", Json::String(source.to_owned())),
+                                          ("target", Json::String(parsed.target.to_owned())),
+                                          ("readonly", Json::Boolean(parsed.read_only))]));
+            }
+            Some(_) => volumes.push(spec.to_owned()),
+            None => {
+                warn!("{}: cannot convert anonymous volume {} to Nomad",
+                      service_name,
+                      parsed.target)
+            }
+        }
+    }
+    if !volumes.is_empty() {
+        config.push(("volumes", json_strings(&volumes)));
+    }
+    if !mounts.is_empty() {
+        config.push(("mount", Json::Array(mounts)));
+    }
+    if !info.labels.is_empty() {
+        config.push(("labels", Json::Array(vec![json_string_map(&info.labels)])));
+    }
+
+    for field in info.other.keys() {
+        warn!("{}: cannot convert `{}` to Nomad", service_name, field);
+    }
+
+    let mut task = vec![("Name", Json::String(service_name.to_owned())),
+                        ("Driver", Json::String("docker".to_owned())),
+                        ("Config", json_obj(config))];
+    if !info.environment.is_empty() {
+        task.push(("Env", json_string_map(&info.environment)));
+    }
+    if let Some(resources) = try!(task_resources(resources)) {
+        task.push(("Resources", resources));
+    }
+    Ok((json_obj(task), reserved, dynamic))
+}
+
+/// Convert the resources from our pod metadata to Nomad's format.
+/// Nomad reserves the resources it's asked for, so we prefer our
+/// reservations, and fall back to our limits.
+fn task_resources(resources: &Resources) -> Result<Option<Json>> {
+    let (reservations, limits) = (&resources.reservations, &resources.limits);
+    let mut converted = vec![];
+    if let Some(cpus) = reservations.cpus.as_ref().or(limits.cpus.as_ref()) {
+        let cpus: f64 = try!(cpus.parse().map_err(|_| err!("invalid number of CPUs <{}>", cpus)));
+        converted.push(("CPU", Json::U64((cpus * MHZ_PER_CPU).round() as u64)));
+    }
+    if let Some(memory) = reservations.memory.as_ref().or(limits.memory.as_ref()) {
+        converted.push(("MemoryMB", Json::U64(try!(memory_mb(memory)))));
+    }
+    if reservations.memory.is_some() {
+        if let Some(ref limit) = limits.memory {
+            converted.push(("MemoryMaxMB", Json::U64(try!(memory_mb(limit)))));
+        }
+    }
+    if converted.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(json_obj(converted)))
+    }
+}
+
+/// Convert a `docker-compose` memory size like `"512M"` to megabytes.
+fn memory_mb(memory: &str) -> Result<u64> {
+    let mkerr = || err!("invalid memory size <{}>", memory);
+    let lower = memory.to_lowercase();
+    let trimmed = lower.trim_right_matches('b');
+    let (digits, multiplier) = match trimmed.chars().last() {
+        Some('k') => (&trimmed[..trimmed.len() - 1], 1.0 / 1024.0),
+        Some('m') => (&trimmed[..trimmed.len() - 1], 1.0),
+        Some('g') => (&trimmed[..trimmed.len() - 1], 1024.0),
+        _ => (trimmed, 1.0 / (1024.0 * 1024.0)),
+    };
+    let amount: f64 = try!(digits.parse().map_err(|_| mkerr()));
+    Ok((amount * multiplier).ceil() as u64)
+}
+
+/// Write `json` to `path`.
+fn write_json(path: &Path, json: &Json) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    try!(path.with_guaranteed_parent().chain_err(&mkerr));
+    let mut f = try!(fs::File::create(path).chain_err(&mkerr));
+    try!(write!(f, "{}\n", json.pretty()).chain_err(&mkerr));
+    Ok(())
+}
+
+#[test]
+fn converts_memory_sizes() {
+    assert_eq!(memory_mb("256M").unwrap(), 256);
+    assert_eq!(memory_mb("1g").unwrap(), 1024);
+    assert_eq!(memory_mb("512kb").unwrap(), 1);
+    assert_eq!(memory_mb("1048576").unwrap(), 1);
+    assert!(memory_mb("lots").is_err());
+}
+
+#[test]
+fn exports_pods_as_nomad_jobs() {
+    use env_logger;
+    use export::ExportFormat;
+    use std::io::Read;
+    let _ = env_logger::init();
+
+    let read_job = |dir: &Path, name: &str| -> Json {
+        let mut json = String::new();
+        fs::File::open(dir.join(name)).unwrap().read_to_string(&mut json).unwrap();
+        Json::from_str(&json).unwrap()
+    };
+
+    // Service pods become service jobs, with resources from metadata.
+    let proj = Project::from_example("node_hello").unwrap();
+    let export_dir = proj.output_dir().join("nomad");
+    proj.export_as(ExportFormat::Nomad, &export_dir).unwrap();
+    let job = read_job(&export_dir, "frontend.json");
+    assert_eq!(job.find_path(&["Job", "Type"]).unwrap().as_string(),
+               Some("service"));
+    let group = &job.find_path(&["Job", "TaskGroups"]).unwrap()[0];
+    assert_eq!(group["Count"].as_u64(), Some(2));
+    let port = &group["Networks"][0]["ReservedPorts"][0];
+    assert_eq!(port["Value"].as_u64(), Some(3000));
+    let task = &group["Tasks"][0];
+    assert_eq!(task["Driver"].as_string(), Some("docker"));
+    assert_eq!(task["Config"]["ports"][0].as_string(), Some("web_3000"));
+    assert_eq!(task["Resources"]["CPU"].as_u64(), Some(500));
+    assert_eq!(task["Resources"]["MemoryMB"].as_u64(), Some(256));
+    proj.remove_test_output().unwrap();
+
+    // Task pods become batch jobs.
+    let proj = Project::from_example("rails_hello").unwrap();
+    let export_dir = proj.output_dir().join("nomad");
+    proj.export_as(ExportFormat::Nomad, &export_dir).unwrap();
+    let job = read_job(&export_dir, "rake.json");
+    assert_eq!(job.find_path(&["Job", "Type"]).unwrap().as_string(),
+               Some("batch"));
+    let task = &job.find_path(&["Job", "TaskGroups"]).unwrap()[0]["Tasks"][0];
+    assert_eq!(task["Config"]["entrypoint"][0].as_string(), Some("rake"));
+    proj.remove_test_output().unwrap();
+}
@@ -0,0 +1,267 @@
+//! Export a project as a single version 3 stack file, for use with
+//! `docker stack deploy`.
+//!
+//! Version 3 files drop a number of version 2 fields, mostly in favor of
+//! the new `deploy` section, and swarm mode ignores several more.  We
+//! convert the fields we can, and warn about every field we have to drop.
+
+use serde_yaml;
+use std::path::Path;
+use yaml_rust::Yaml;
+use yaml_rust::yaml::Hash;
+
+use errors::*;
+use export::{ExportedPod, merge_definitions, write_yaml_docs, yaml_hash, yaml_str,
+             yaml_to_string};
+use pod::{DeployConfig, PodType, ResourceValues};
+
+/// The name of the stack file we write.
+pub const STACK_FILE_NAME: &'static str = "docker-stack.yml";
+
+/// Service fields which `docker stack deploy` honors.  We convert
+/// `mem_limit`, `mem_reservation`, `cpus` and `restart` to a `deploy`
+/// section, and drop everything else, including fields like `build` and
+/// `links` which are valid in version 3 files but ignored by swarm mode.
+const SWARM_FIELDS: &'static [&'static str] = &["command",
+                                                 "deploy",
+                                                 "dns",
+                                                 "dns_search",
+                                                 "domainname",
+                                                 "entrypoint",
+                                                 "env_file",
+                                                 "environment",
+                                                 "expose",
+                                                 "extra_hosts",
+                                                 "healthcheck",
+                                                 "hostname",
+                                                 "image",
+                                                 "labels",
+                                                 "logging",
+                                                 "networks",
+                                                 "ports",
+                                                 "read_only",
+                                                 "stdin_open",
+                                                 "stop_grace_period",
+                                                 "stop_signal",
+                                                 "tty",
+                                                 "ulimits",
+                                                 "user",
+                                                 "volumes",
+                                                 "working_dir"];
+
+/// Merge the service pods in `pods` into a single stack file in
+/// `export_dir`.
+pub fn export(pods: &[ExportedPod], export_dir: &Path) -> Result<()> {
+    let mut services = Hash::new();
+    let mut volumes = Hash::new();
+    let mut networks = Hash::new();
+    for exported in pods {
+        let pod = exported.pod;
+        if pod.pod_type() == PodType::Task {
+            warn!("Not including task pod {} in {}", pod.name(), STACK_FILE_NAME);
+            continue;
+        }
+
+        let yaml = serde_yaml::to_value(&exported.file);
+        try!(merge_definitions(&mut volumes, &yaml["volumes"], "volume"));
+        try!(merge_definitions(&mut networks, &yaml["networks"], "network"));
+        if let Yaml::Hash(ref pod_services) = yaml["services"] {
+            for (name, service) in pod_services {
+                let name_str = try!(name.as_str()
+                    .ok_or_else(|| err!("expected service name to be a string")));
+                if services.contains_key(name) {
+                    return Err(err!("service {} is defined in more than one pod", name_str));
+                }
+                let (converted, dropped) = try!(convert_service(service, pod.deploy())
+                    .chain_err(|| format!("could not convert {}/{}", pod.name(), name_str)));
+                for field in &dropped {
+                    warn!("{}/{}: `{}` isn't supported by `docker stack deploy`, so it \
+                           was dropped",
+                          pod.name(),
+                          name_str,
+                          field);
+                }
+                services.insert(name.clone(), converted);
+            }
+        }
+    }
+
+    let mut top = vec![("version", yaml_str("3")), ("services", Yaml::Hash(services))];
+    if !volumes.is_empty() {
+        top.push(("volumes", Yaml::Hash(volumes)));
+    }
+    if !networks.is_empty() {
+        top.push(("networks", Yaml::Hash(networks)));
+    }
+    write_yaml_docs(&export_dir.join(STACK_FILE_NAME), &[yaml_hash(top)])
+}
+
+/// Convert a version 2 `service` to version 3, using `deploy` from our
+/// pod metadata.  Returns the converted service and the names of any
+/// fields we had to drop.
+fn convert_service(service: &Yaml,
+                   deploy: Option<&DeployConfig>)
+                   -> Result<(Yaml, Vec<String>)> {
+    let hash = match *service {
+        Yaml::Hash(ref hash) => hash,
+        _ => return Err(err!("expected service to be a hash")),
+    };
+
+    let mut converted = Hash::new();
+    let mut dropped = vec![];
+    let mut limits = ResourceValues::default();
+    let mut reservations = ResourceValues::default();
+    let mut restart_policy = None;
+    for (key, value) in hash {
+        let key_str = try!(key.as_str()
+            .ok_or_else(|| err!("expected service keys to be strings")));
+        match key_str {
+            "mem_limit" => limits.memory = Some(try!(yaml_to_string(key_str, value))),
+            "mem_reservation" => reservations.memory = Some(try!(yaml_to_string(key_str, value))),
+            "cpus" => limits.cpus = Some(try!(yaml_to_string(key_str, value))),
+            "restart" => {
+                restart_policy = Some(try!(convert_restart(&try!(yaml_to_string(key_str,
+                                                                                value)))))
+            }
+            _ if SWARM_FIELDS.contains(&key_str) => {
+                converted.insert(key.clone(), value.clone());
+            }
+            _ => dropped.push(key_str.to_owned()),
+        }
+    }
+
+    // Settings from our pod metadata override anything we converted.
+    let mut replicas = None;
+    if let Some(deploy) = deploy {
+        replicas = deploy.replicas;
+        override_values(&mut limits, &deploy.resources.limits);
+        override_values(&mut reservations, &deploy.resources.reservations);
+    }
+
+    let mut deploy_section = vec![];
+    if let Some(replicas) = replicas {
+        deploy_section.push(("replicas", Yaml::Integer(replicas as i64)));
+    }
+    let mut resources = vec![];
+    if !limits.is_empty() {
+        resources.push(("limits", resource_values(&limits)));
+    }
+    if !reservations.is_empty() {
+        resources.push(("reservations", resource_values(&reservations)));
+    }
+    if !resources.is_empty() {
+        deploy_section.push(("resources", yaml_hash(resources)));
+    }
+    if let Some(restart_policy) = restart_policy {
+        deploy_section.push(("restart_policy", restart_policy));
+    }
+    if !deploy_section.is_empty() {
+        converted.insert(yaml_str("deploy"), yaml_hash(deploy_section));
+    }
+    Ok((Yaml::Hash(converted), dropped))
+}
+
+/// Replace any values in `values` which are also specified in
+/// `overrides`.
+fn override_values(values: &mut ResourceValues, overrides: &ResourceValues) {
+    if overrides.cpus.is_some() {
+        values.cpus = overrides.cpus.clone();
+    }
+    if overrides.memory.is_some() {
+        values.memory = overrides.memory.clone();
+    }
+}
+
+/// Convert `values` to a `resources.limits` or `resources.reservations`
+/// section.
+fn resource_values(values: &ResourceValues) -> Yaml {
+    let mut section = vec![];
+    if let Some(ref cpus) = values.cpus {
+        section.push(("cpus", yaml_str(&cpus[..])));
+    }
+    if let Some(ref memory) = values.memory {
+        section.push(("memory", yaml_str(&memory[..])));
+    }
+    yaml_hash(section)
+}
+
+/// Convert a version 2 `restart` value to a `deploy.restart_policy`
+/// section.
+fn convert_restart(restart: &str) -> Result<Yaml> {
+    let mut parts = restart.splitn(2, ':');
+    let mut policy = match parts.next() {
+        Some("no") => vec![("condition", yaml_str("none"))],
+        Some("always") |
+        Some("unless-stopped") => vec![("condition", yaml_str("any"))],
+        Some("on-failure") => vec![("condition", yaml_str("on-failure"))],
+        _ => return Err(err!("unknown restart policy <{}>", restart)),
+    };
+    if let Some(attempts) = parts.next() {
+        let attempts: i64 = try!(attempts.parse()
+            .map_err(|_| err!("invalid restart policy <{}>", restart)));
+        policy.push(("max_attempts", Yaml::Integer(attempts)));
+    }
+    Ok(yaml_hash(policy))
+}
+
+#[test]
+fn converts_v2_services_to_v3() {
+    use yaml_rust::YamlLoader;
+
+    let service = YamlLoader::load_from_str(r#"---
+image: "example/worker"
+build: "."
+links: ["db"]
+mem_limit: "512m"
+cpu_shares: 512
+volumes_from: ["data"]
+restart: "on-failure:3"
+"#)
+        .unwrap()
+        .remove(0);
+    let mut deploy = DeployConfig::default();
+    deploy.replicas = Some(3);
+    deploy.resources.reservations.memory = Some("128M".to_owned());
+
+    let (converted, dropped) = convert_service(&service, Some(&deploy)).unwrap();
+    assert_eq!(dropped, &["build", "links", "cpu_shares", "volumes_from"]);
+    assert_eq!(converted["image"].as_str(), Some("example/worker"));
+    assert!(converted["mem_limit"].is_badvalue());
+    assert!(converted["restart"].is_badvalue());
+    let deploy = &converted["deploy"];
+    assert_eq!(deploy["replicas"].as_i64(), Some(3));
+    assert_eq!(deploy["resources"]["limits"]["memory"].as_str(), Some("512m"));
+    assert_eq!(deploy["resources"]["reservations"]["memory"].as_str(),
+               Some("128M"));
+    assert_eq!(deploy["restart_policy"]["condition"].as_str(),
+               Some("on-failure"));
+    assert_eq!(deploy["restart_policy"]["max_attempts"].as_i64(), Some(3));
+}
+
+#[test]
+fn exports_service_pods_as_stack_file() {
+    use env_logger;
+    use export::ExportFormat;
+    use project::Project;
+    use std::fs;
+    use std::io::Read;
+    use yaml_rust::YamlLoader;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("node_hello").unwrap();
+    let export_dir = proj.output_dir().join("stack");
+    proj.export_as(ExportFormat::Stack, &export_dir).unwrap();
+
+    let mut yaml = String::new();
+    fs::File::open(export_dir.join(STACK_FILE_NAME))
+        .unwrap()
+        .read_to_string(&mut yaml)
+        .unwrap();
+    let stack = YamlLoader::load_from_str(&yaml).unwrap().remove(0);
+    assert_eq!(stack["version"].as_str(), Some("3"));
+    let deploy = &stack["services"]["web"]["deploy"];
+    assert_eq!(deploy["replicas"].as_i64(), Some(2));
+    assert_eq!(deploy["resources"]["limits"]["cpus"].as_str(), Some("0.5"));
+
+    proj.remove_test_output().unwrap();
+}
@@ -0,0 +1,297 @@
+//! Export a project as systemd units, for running it on a single host
+//! with plain `docker`.
+//!
+//! We write one unit per service.  Each unit runs `docker run` with the
+//! service's image, environment, ports, volumes and labels, and starts
+//! after the units for any pods which come earlier in our pod ordering
+//! (placeholders, then services, then tasks).  Task pods become `oneshot`
+//! units.  As with `cage up`, containers join a project-wide network,
+//! which each unit creates if it doesn't exist yet, and can reach each
+//! other as `$SERVICE` or `$POD-$SERVICE`.  Isolated pods get a network of
+//! their own.  Everything is written in a stable order, so that exported
+//! units can be diffed in code review.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use errors::*;
+use export::{ExportedPod, ServiceInfo};
+use plugins::transform::shared_network;
+use pod::{Pod, PodType};
+use project::Project;
+use util::ConductorPathExt;
+
+/// The path to `docker` on the hosts where our units will run.
+const DOCKER: &'static str = "/usr/bin/docker";
+
+/// Write a systemd unit for each service in `pods` to `export_dir`.
+pub fn export(project: &Project, pods: &[ExportedPod], export_dir: &Path) -> Result<()> {
+    // Sort our pods by type, keeping them in project order otherwise.
+    let mut pods: Vec<&ExportedPod> = pods.iter().collect();
+    pods.sort_by_key(|exported| exported.pod.pod_type());
+
+    for (i, exported) in pods.iter().enumerate() {
+        // Start after every unit belonging to an earlier type of pod.
+        let pod_type = exported.pod.pod_type();
+        let deps: Vec<String> = pods[..i]
+            .iter()
+            .filter(|earlier| earlier.pod.pod_type() < pod_type)
+            .flat_map(|earlier| pod_unit_names(project, earlier))
+            .collect();
+
+        for (service_name, service) in &exported.file.services {
+            let info = try!(ServiceInfo::new(service));
+            let unit = try!(service_unit(project, exported.pod, service_name, &info, &deps)
+                .chain_err(|| {
+                    format!("could not convert {}/{} to a systemd unit",
+                            exported.pod.name(),
+                            service_name)
+                }));
+            let path = export_dir.join(unit_name(project, exported.pod, service_name));
+            try!(write_unit(&path, &unit));
+        }
+    }
+    Ok(())
+}
+
+/// The name of the unit for `service_name` in `pod`.
+fn unit_name(project: &Project, pod: &Pod, service_name: &str) -> String {
+    format!("{}.service", container_name(project, pod, service_name))
+}
+
+/// The names of the units for all the services in `exported`.
+fn pod_unit_names(project: &Project, exported: &ExportedPod) -> Vec<String> {
+    exported.file
+        .services
+        .keys()
+        .map(|service_name| unit_name(project, exported.pod, service_name))
+        .collect()
+}
+
+/// The name of the container we run for `service_name` in `pod`.  This
+/// only contains characters which are legal in unit names.
+fn container_name(project: &Project, pod: &Pod, service_name: &str) -> String {
+    format!("{}-{}-{}", project.name(), pod.name(), service_name)
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' || c == '.' { c } else { '-' })
+        .collect()
+}
+
+/// The network joined by the containers in `pod`.  Pods share a
+/// project-wide network unless they're isolated.
+fn network_name(project: &Project, pod: &Pod) -> String {
+    let target = project.current_target();
+    if pod.is_isolated() {
+        format!("{}_{}", target.compose_project_name(project), pod.name())
+    } else {
+        shared_network::Plugin::network_name(project, target)
+    }
+}
+
+/// Build the text of a unit running `info`, which starts after `deps`.
+fn service_unit(project: &Project,
+                pod: &Pod,
+                service_name: &str,
+                info: &ServiceInfo,
+                deps: &[String])
+                -> Result<String> {
+    let name = container_name(project, pod, service_name);
+    let is_task = pod.pod_type() == PodType::Task;
+    let image = try!(info.image
+        .as_ref()
+        .ok_or_else(|| err!("{} has no image", service_name)));
+
+    // Build our `docker run` command line, one option per line.
+    let mut args: Vec<Vec<String>> = vec![];
+    args.push(vec![DOCKER.to_owned(),
+                   "run".to_owned(),
+                   "--rm".to_owned(),
+                   "--name".to_owned(),
+                   name.clone()]);
+    let network = network_name(project, pod);
+    args.push(vec!["--network".to_owned(), network.clone()]);
+    args.push(vec!["--network-alias".to_owned(), service_name.to_owned()]);
+    args.push(vec!["--network-alias".to_owned(), format!("{}-{}", pod.name(), service_name)]);
+    for (var, value) in &info.environment {
+        args.push(vec!["-e".to_owned(), format!("{}={}", var, value)]);
+    }
+    for port in &info.ports {
+        args.push(vec!["-p".to_owned(), port.to_owned()]);
+    }
+    for volume in &info.volumes {
+        args.push(vec!["-v".to_owned(), volume.to_owned()]);
+    }
+    for (label, value) in &info.labels {
+        args.push(vec!["-l".to_owned(), format!("{}={}", label, value)]);
+    }
+    if let Some(ref working_dir) = info.working_dir {
+        args.push(vec!["-w".to_owned(), working_dir.to_owned()]);
+    }
+
+    // `docker run --entrypoint` only accepts a single word, so pass any
+    // remaining words as the start of our command.
+    let mut command = vec![];
+    if let Some(ref entrypoint) = info.entrypoint {
+        let mut words = entrypoint.iter();
+        if let Some(first) = words.next() {
+            args.push(vec!["--entrypoint".to_owned(), first.to_owned()]);
+        }
+        command.extend(words.cloned());
+    }
+    if let Some(ref cmd) = info.command {
+        command.extend(cmd.iter().cloned());
+    }
+    let mut last = vec![image.to_owned()];
+    last.extend(command);
+    args.push(last);
+
+    for field in info.other.keys() {
+        if field == "networks" {
+            warn!("{}: using {} instead of the networks it specifies",
+                  service_name,
+                  &network);
+        } else {
+            warn!("{}: cannot convert `{}` to a systemd unit", service_name, field);
+        }
+    }
+
+    let exec_start = args.iter()
+        .map(|words| words.iter().map(|w| quote(w)).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join(" \\\n    ");
+
+    let mut unit = String::new();
+    unit.push_str(&format!("# Generated by `cage export --format systemd` for target {}.\n",
+                           project.current_target().name()));
+    unit.push_str("[Unit]\n");
+    unit.push_str(&format!("Description={} {}/{}\n",
+                           project.name(),
+                           pod.name(),
+                           service_name));
+    unit.push_str("Requires=docker.service\n");
+    unit.push_str("After=docker.service\n");
+    if !deps.is_empty() {
+        unit.push_str(&format!("Wants={}\n", deps.join(" ")));
+        unit.push_str(&format!("After={}\n", deps.join(" ")));
+    }
+    unit.push_str("\n[Service]\n");
+    if is_task {
+        unit.push_str("Type=oneshot\n");
+    } else {
+        unit.push_str("Type=simple\n");
+        unit.push_str("Restart=always\n");
+    }
+    unit.push_str(&format!("ExecStartPre=-{} network create {}\n",
+                           DOCKER,
+                           quote(&network)));
+    unit.push_str(&format!("ExecStartPre=-{} rm -f {}\n", DOCKER, quote(&name)));
+    unit.push_str(&format!("ExecStart={}\n", exec_start));
+    if !is_task {
+        unit.push_str(&format!("ExecStop={} stop {}\n", DOCKER, quote(&name)));
+        unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+    }
+    Ok(unit)
+}
+
+/// Quote `word` for use in a systemd command line.
+fn quote(word: &str) -> String {
+    let escaped = word.replace('%', "%%").replace('$', "$$");
+    let is_plain = |c: char| !c.is_whitespace() && !"\"'\\;".contains(c);
+    if !escaped.is_empty() && escaped.chars().all(is_plain) {
+        escaped
+    } else {
+        format!("\"{}\"",
+                escaped.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Write `unit` to `path`.
+fn write_unit(path: &Path, unit: &str) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    try!(path.with_guaranteed_parent().chain_err(&mkerr));
+    let mut f = try!(fs::File::create(path).chain_err(&mkerr));
+    try!(f.write_all(unit.as_bytes()).chain_err(&mkerr));
+    Ok(())
+}
+
+#[test]
+fn quotes_systemd_arguments() {
+    assert_eq!(quote("faraday/rails_hello"), "faraday/rails_hello");
+    assert_eq!(quote("PATH=$PATH"), "PATH=$$PATH");
+    assert_eq!(quote("100%"), "100%%");
+    assert_eq!(quote("bundle exec"), "\"bundle exec\"");
+    assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+    assert_eq!(quote(""), "\"\"");
+}
+
+#[test]
+fn escapes_dollar_signs_in_values_once() {
+    use compose_yml::v2 as dc;
+    use std::str::FromStr;
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let file = dc::File::from_str(r#"---
+version: "2"
+services:
+  web:
+    image: "example/web:1.0"
+    environment:
+      PRICE: "$$5"
+"#)
+        .unwrap();
+    let info = ServiceInfo::new(file.services.get("web").unwrap()).unwrap();
+    let pod = proj.pod("frontend").unwrap();
+    let unit = service_unit(&proj, pod, "web", &info, &[]).unwrap();
+    assert!(unit.contains(" -e PRICE=$$5 "));
+}
+
+#[test]
+fn exports_services_as_systemd_units() {
+    use env_logger;
+    use export::ExportFormat;
+    use std::io::Read;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let read_unit = |dir: &Path, name: &str| -> String {
+        let mut unit = String::new();
+        fs::File::open(dir.join(name)).unwrap().read_to_string(&mut unit).unwrap();
+        unit
+    };
+
+    let export_dir = proj.output_dir().join("systemd");
+    proj.export_as(ExportFormat::Systemd, &export_dir).unwrap();
+
+    // Placeholders start first, and tasks are oneshot units.
+    let db = read_unit(&export_dir, "rails_hello-db-db.service");
+    assert!(!db.contains("Wants="));
+    let web = read_unit(&export_dir, "rails_hello-frontend-web.service");
+    assert!(web.contains("After=rails_hello-db-db.service\n"));
+    assert!(web.contains("Type=simple\n"));
+    assert!(web.contains("-p 3000:3000"));
+    assert!(web.contains("-l io.fdy.cage.pod=frontend"));
+    assert!(!web.contains("io.fdy.cage.shell"));
+
+    // Containers can find each other on our shared network.
+    assert!(db.contains("ExecStartPre=-/usr/bin/docker network create rails_hello_shared\n"));
+    assert!(db.contains("--network rails_hello_shared"));
+    assert!(db.contains("--network-alias db"));
+    assert!(web.contains("--network-alias frontend-web"));
+    let rake = read_unit(&export_dir, "rails_hello-rake-rake.service");
+    let deps = "After=rails_hello-db-db.service rails_hello-frontend-web.service\n";
+    assert!(rake.contains(deps));
+    assert!(rake.contains("Type=oneshot\n"));
+    assert!(rake.contains("--entrypoint rake"));
+
+    // Exporting again should produce identical units.
+    let again_dir = proj.output_dir().join("systemd-again");
+    proj.export_as(ExportFormat::Systemd, &again_dir).unwrap();
+    for name in &["rails_hello-db-db.service",
+                  "rails_hello-frontend-web.service",
+                  "rails_hello-rake-rake.service"] {
+        assert_eq!(read_unit(&export_dir, name), read_unit(&again_dir, name));
+    }
+
+    proj.remove_test_output().unwrap();
+}
@@ -0,0 +1,232 @@
+//! Fingerprints of everything that affects our generated output, so that
+//! we can skip regenerating it when nothing has changed.
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use errors::*;
+use project::Project;
+use util::{self, ConductorPathExt, ToStrOrErr};
+use version;
+
+/// Environment variables which affect our output, because our plugins
+/// read them.
+const ENV_INPUTS: &'static [&'static str] = &["CAGE_SECRETS_KEY", "VAULT_ADDR"];
+
+/// Builds a fingerprint by hashing labeled pieces of input.
+struct Fingerprinter {
+    /// Our underlying hash function.
+    hasher: Sha256,
+}
+
+impl Fingerprinter {
+    /// Create a new fingerprinter.
+    fn new() -> Fingerprinter {
+        Fingerprinter { hasher: Sha256::new() }
+    }
+
+    /// Add `data` to our fingerprint, labeled with `label`.  We include
+    /// lengths so that different inputs can't run together.
+    fn input(&mut self, label: &str, data: &[u8]) {
+        self.hasher.input_str(&format!("{}:{}:{}\n", label.len(), label, data.len()));
+        self.hasher.input(data);
+    }
+
+    /// Add all the files under `dir` to our fingerprint, in sorted order.
+    fn input_dir(&mut self, project: &Project, dir: &Path) -> Result<()> {
+        if !dir.exists() {
+            return Ok(());
+        }
+        let mut paths: Vec<PathBuf> = vec![];
+        for path in try!(dir.glob("**/*")) {
+            let path = try!(path);
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            try!(self.input_file(project, &path));
+        }
+        Ok(())
+    }
+
+    /// Add the file at `path` to our fingerprint, labeled with its path
+    /// relative to `project`.
+    fn input_file(&mut self, project: &Project, path: &Path) -> Result<()> {
+        let mkerr = || ErrorKind::CouldNotReadFile(path.to_owned());
+        let mut data = vec![];
+        let mut f = try!(fs::File::open(path).chain_err(&mkerr));
+        try!(f.read_to_end(&mut data).chain_err(&mkerr));
+        let rel_path = path.strip_prefix(project.root_dir()).unwrap_or(path);
+        self.input(try!(rel_path.to_str_or_err()), &data);
+        Ok(())
+    }
+
+    /// Return our fingerprint as a hex string.
+    fn result(mut self) -> String {
+        self.hasher.result_str()
+    }
+}
+
+/// Compute a fingerprint of all the inputs which affect
+/// `Project::output`: our pods, targets and env files (including any
+/// outside of `pods`), our configuration, the environment variables in
+/// `ENV_INPUTS`, our default tags, which sources are mounted, and the
+/// version of `cage` itself.
+pub fn output_inputs(project: &Project) -> Result<String> {
+    let mut fp = Fingerprinter::new();
+    fp.input("cage_version", version().to_string().as_bytes());
+    fp.input("project", project.name().as_bytes());
+    fp.input("target", project.current_target().name().as_bytes());
+    try!(fp.input_dir(project, &project.pods_dir()));
+    try!(fp.input_dir(project, &project.root_dir().join("config")));
+    for path in try!(external_env_files(project)) {
+        try!(fp.input_file(project, &path));
+    }
+    for var in ENV_INPUTS {
+        if let Some(value) = env::var_os(var) {
+            let value = try!(value.to_str_or_err()).to_owned();
+            fp.input(&format!("env {}", var), value.as_bytes());
+        }
+    }
+    if let Some(default_tags) = project.default_tags() {
+        fp.input("default_tags", format!("{:?}", default_tags).as_bytes());
+    }
+    for source in project.sources().iter() {
+        let state = format!("mounted={} available={}",
+                            source.mounted(),
+                            source.is_available_locally(project));
+        fp.input(&format!("source {}", source.alias()), state.as_bytes());
+    }
+    Ok(fp.result())
+}
+
+/// The `env_file` entries used by pods in the current target of
+/// `project` which aren't under `pods`, and so aren't covered by
+/// `input_dir`.  Missing files are left for `Project::output` to report.
+fn external_env_files(project: &Project) -> Result<BTreeSet<PathBuf>> {
+    let target = project.current_target();
+    let pods_dir = project.pods_dir();
+    let mut paths = BTreeSet::new();
+    for pod in project.pods().filter(|pod| pod.enabled_in(target)) {
+        for service in try!(pod.merged_file(target)).services.values() {
+            for env_file in &service.env_files {
+                let path = pods_dir.join(try!(env_file.value()));
+                let is_external = !path.starts_with(&pods_dir) ||
+                                  path.components().any(|c| c == Component::ParentDir);
+                if is_external && path.is_file() {
+                    paths.insert(path);
+                }
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// The file in which we record the fingerprint of our last output.
+fn recorded_path(project: &Project) -> PathBuf {
+    project.output_dir().join("fingerprint")
+}
+
+/// The fingerprint recorded by our last successful output, if any.
+pub fn recorded(project: &Project) -> Result<Option<String>> {
+    let path = recorded_path(project);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mkerr = || ErrorKind::CouldNotReadFile(path.clone());
+    let mut recorded = String::new();
+    let mut f = try!(fs::File::open(&path).chain_err(&mkerr));
+    try!(f.read_to_string(&mut recorded).chain_err(&mkerr));
+    Ok(Some(recorded.trim().to_owned()))
+}
+
+/// Record `fingerprint` as the fingerprint of our last output.
+pub fn record(project: &Project, fingerprint: &str) -> Result<()> {
+    let path = recorded_path(project);
+    try!(util::write_if_changed(&path, format!("{}\n", fingerprint).as_bytes()));
+    Ok(())
+}
+
+/// Forget the fingerprint of our last output, so that the next call to
+/// `Project::output` will regenerate everything.
+pub fn forget(project: &Project) -> Result<()> {
+    let path = recorded_path(project);
+    if path.exists() {
+        try!(fs::remove_file(&path).chain_err(|| ErrorKind::CouldNotWriteFile(path.clone())));
+    }
+    Ok(())
+}
+
+#[test]
+fn fingerprint_changes_with_inputs() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    let development = output_inputs(&proj).unwrap();
+    assert_eq!(output_inputs(&proj).unwrap(), development);
+
+    proj.set_current_target_name("production").unwrap();
+    assert!(output_inputs(&proj).unwrap() != development);
+    proj.set_current_target_name("development").unwrap();
+
+    // Cloning a source tree changes what we mount.
+    proj.sources().find_by_alias("rails_hello").unwrap().fake_clone_source(&proj).unwrap();
+    assert!(output_inputs(&proj).unwrap() != development);
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn forgetting_fingerprint_forces_output() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("hello").unwrap();
+    proj.output().unwrap();
+    assert!(recorded(&proj).unwrap().is_some());
+    assert!(proj.output().unwrap().is_empty());
+
+    // If we regenerate our output, we record our fingerprint again.
+    forget(&proj).unwrap();
+    assert!(recorded(&proj).unwrap().is_none());
+    proj.output().unwrap();
+    assert!(recorded(&proj).unwrap().is_some());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn fingerprint_includes_env_files_outside_pods() {
+    use cmd::{CommandGenerate, NewOptions};
+    use env_logger;
+    use rand::random;
+    use std::io::Write;
+    let _ = env_logger::init();
+
+    // Generate a throwaway project, because we add files to it.
+    let parent = env::current_dir().unwrap().join("target").join("test_output");
+    let name = format!("fingerprint-{}", random::<u16>());
+    let root = Project::generate_new(&parent, &name, &NewOptions::default()).unwrap();
+    let write = |path: &Path, contents: &str| {
+        fs::File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    };
+    write(&root.join("pods").join("extra.yml"),
+          "version: \"2\"\nservices:\n  extra:\n    image: \"alpine\"\n    env_file: \
+           \"../shared.env\"\n");
+    write(&root.join("shared.env"), "GREETING=hello\n");
+
+    let proj = Project::from_test_dir(&root).unwrap();
+    let before = output_inputs(&proj).unwrap();
+    write(&root.join("shared.env"), "GREETING=goodbye\n");
+    assert!(output_inputs(&proj).unwrap() != before);
+
+    fs::remove_dir_all(&root).unwrap();
+}
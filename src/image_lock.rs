@@ -0,0 +1,630 @@
+//! Lock files which pin each image used by a project to a content digest.
+//!
+//! `DefaultTags` can tell us which tag to use for an image, but tags can
+//! be moved to point at new builds.  A lock file records the digest that
+//! each image reference pointed to when we exported the project, so that
+//! we can deploy exactly those images, and check later whether anything
+//! has changed.
+
+use compose_yml::v2 as dc;
+#[cfg(feature="registry")]
+use crypto::digest::Digest;
+#[cfg(feature="registry")]
+use crypto::sha2::Sha256;
+#[cfg(feature="registry")]
+use hyper;
+#[cfg(feature="registry")]
+use hyper::header::Headers;
+#[cfg(feature="registry")]
+use hyper::status::StatusCode;
+#[cfg(feature="registry")]
+use rustc_serialize::base64::{STANDARD, ToBase64};
+use rustc_serialize::json::Json;
+use serde_yaml;
+use std::collections::BTreeMap;
+#[cfg(feature="registry")]
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+#[cfg(feature="registry")]
+use url;
+
+use command_runner::{Command, CommandRunner};
+use errors::*;
+use util;
+
+/// A source of content digests for images.
+pub trait DigestSource {
+    /// Look up the current digest of `image`, in the form
+    /// `sha256:<hex>`.
+    fn digest(&self, image: &str) -> Result<String>;
+}
+
+/// Looks up digests using `docker inspect`, which only knows about
+/// images which have been pushed to or pulled from a registry.
+#[derive(Debug)]
+pub struct DockerInspect<'a, CR: CommandRunner + 'a> {
+    /// The runner we use to run `docker`.
+    runner: &'a CR,
+}
+
+impl<'a, CR: CommandRunner + 'a> DockerInspect<'a, CR> {
+    /// Create a `DockerInspect` which runs `docker` using `runner`.
+    pub fn new(runner: &'a CR) -> DockerInspect<'a, CR> {
+        DockerInspect { runner: runner }
+    }
+}
+
+impl<'a, CR: CommandRunner + 'a> DigestSource for DockerInspect<'a, CR> {
+    fn digest(&self, image: &str) -> Result<String> {
+        let mut command = self.runner.build("docker");
+        command.args(&["inspect", "--type=image", "--format={{json .RepoDigests}}", image]);
+        let output = try!(command.output());
+        if !output.status.success() {
+            return Err(command.command_failed_error().into());
+        }
+        let json = try!(String::from_utf8(output.stdout));
+        let repo_digests = try!(Json::from_str(&json)
+            .map_err(|e| err!("could not parse output of docker inspect: {}", e)));
+        let repo_digests: Vec<&str> = repo_digests.as_array()
+            .map_or_else(|| vec![], |a| a.iter().filter_map(|d| d.as_string()).collect());
+        digest_for_repository(image, &repo_digests)
+    }
+}
+
+/// Choose the digest belonging to the same repository as `image` from
+/// `repo_digests`, a list of `name@sha256:...` references.
+fn digest_for_repository(image: &str, repo_digests: &[&str]) -> Result<String> {
+    let repository = split_reference(image).0;
+    repo_digests.iter()
+        .filter_map(|d| {
+            let (name, digest) = split_reference(d);
+            match digest {
+                Some(digest) if name == repository && digest.contains(':') => {
+                    Some(digest.to_owned())
+                }
+                _ => None,
+            }
+        })
+        .next()
+        .ok_or_else(|| {
+            err!("{} has no digest (it must be pushed to or pulled from a registry)",
+                 image)
+        })
+}
+
+/// The environment variable from which we read our registry username.
+#[cfg(feature="registry")]
+const REGISTRY_USERNAME_VAR: &'static str = "CAGE_REGISTRY_USERNAME";
+/// The environment variable from which we read our registry password.
+#[cfg(feature="registry")]
+const REGISTRY_PASSWORD_VAR: &'static str = "CAGE_REGISTRY_PASSWORD";
+
+/// The manifest types we ask registries for.  We accept manifest lists so
+/// that multi-arch images get the same digest `docker image inspect`
+/// reports for them, instead of the digest of one platform's manifest.
+#[cfg(feature="registry")]
+const MANIFEST_TYPES: &'static str =
+    "application/vnd.docker.distribution.manifest.list.v2+json, \
+     application/vnd.docker.distribution.manifest.v2+json";
+
+/// Looks up digests using the HTTP API of Docker registries.  Images which
+/// name a registry host, like `registry.example.com/team/app`, are looked
+/// up in that registry, and all other images in our default registry.  If
+/// a registry asks us to authenticate, we request a bearer token from the
+/// authentication server it specifies, anonymously or (for our default
+/// registry only) using a username and password.
+#[cfg(feature="registry")]
+#[derive(Debug)]
+pub struct Registry {
+    /// The base URL of our default registry, without a trailing `/`.
+    url: String,
+    /// A username and password to send to our default registry's token
+    /// server.
+    credentials: Option<(String, String)>,
+}
+
+#[cfg(feature="registry")]
+impl Registry {
+    /// Create a new digest source which uses the registry at `url` for
+    /// images which don't name a registry host.
+    pub fn new<S: Into<String>>(url: S) -> Registry {
+        let mut url = url.into();
+        while url.ends_with('/') {
+            url.pop();
+        }
+        Registry {
+            url: url,
+            credentials: None,
+        }
+    }
+
+    /// Authenticate to our default registry's token server as `username`.
+    pub fn with_credentials<S1, S2>(mut self, username: S1, password: S2) -> Registry
+        where S1: Into<String>,
+              S2: Into<String>
+    {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    /// The base URL of the registry at `host`, or of our default registry
+    /// if `host` is `None`.  Like `docker`, we only use plain HTTP for
+    /// registries on the local machine.
+    fn base_url(&self, host: Option<&str>) -> String {
+        match host {
+            None => self.url.clone(),
+            Some(host) => {
+                let local = host == "localhost" || host.starts_with("localhost:") ||
+                            host.starts_with("127.");
+                format!("{}://{}", if local { "http" } else { "https" }, host)
+            }
+        }
+    }
+
+    /// Make a GET request to `url`, returning the response and its body.
+    fn get(&self,
+           url: &str,
+           mut headers: Headers,
+           authorization: Option<String>)
+           -> Result<(hyper::client::Response, Vec<u8>)> {
+        debug!("Registry request: GET {}", url);
+        if let Some(authorization) = authorization {
+            headers.set_raw("Authorization", vec![authorization.into_bytes()]);
+        }
+        let client = hyper::Client::new();
+        let mkerr = || format!("could not GET {}", url);
+        let mut res = try!(client.get(url).headers(headers).send().chain_err(&mkerr));
+        let mut body = vec![];
+        try!(res.read_to_end(&mut body).chain_err(&mkerr));
+        Ok((res, body))
+    }
+
+    /// Fetch a bearer token from the authentication server described by
+    /// `challenge`, a `WWW-Authenticate` header, sending our credentials
+    /// if `authenticate` is true.
+    fn token(&self, challenge: &str, authenticate: bool) -> Result<String> {
+        let params = try!(parse_bearer_challenge(challenge)
+            .ok_or_else(|| err!("unsupported registry authentication: {}", challenge)));
+        let realm = try!(params.get("realm")
+            .ok_or_else(|| err!("no realm in registry authentication: {}", challenge)));
+        let mut url = try!(url::Url::parse(realm)
+            .map_err(|e| err!("invalid registry authentication realm {}: {}", realm, e)));
+        for key in &["service", "scope"] {
+            if let Some(value) = params.get(*key) {
+                url.query_pairs_mut().append_pair(key, value);
+            }
+        }
+
+        let credentials = if authenticate { self.credentials.as_ref() } else { None };
+        let authorization = credentials.map(|&(ref user, ref password)| {
+            let encoded = format!("{}:{}", user, password).as_bytes().to_base64(STANDARD);
+            format!("Basic {}", encoded)
+        });
+        let (res, body) = try!(self.get(url.as_str(), Headers::new(), authorization));
+        if !res.status.is_success() {
+            return Err(err!("{} returned {}", realm, res.status));
+        }
+        let json = try!(Json::from_str(&try!(String::from_utf8(body)))
+            .map_err(|e| err!("could not parse token from {}: {}", realm, e)));
+        json.find("token")
+            .or_else(|| json.find("access_token"))
+            .and_then(|token| token.as_string())
+            .map(|token| token.to_owned())
+            .ok_or_else(|| err!("no token in response from {}", realm))
+    }
+}
+
+#[cfg(feature="registry")]
+impl DigestSource for Registry {
+    fn digest(&self, image: &str) -> Result<String> {
+        let (repository, tag) = split_reference(image);
+        let (host, path) = registry_host_and_path(repository);
+        let base_url = self.base_url(host);
+        let url = format!("{}/v2/{}/manifests/{}",
+                          &base_url,
+                          path,
+                          tag.unwrap_or("latest"));
+
+        let mut headers = Headers::new();
+        headers.set_raw("Accept", vec![MANIFEST_TYPES.as_bytes().to_vec()]);
+        let mkerr = || format!("could not look up {} in {}", image, &base_url);
+        let (mut res, mut body) = try!(self.get(&url, headers.clone(), None).chain_err(&mkerr));
+
+        // Most registries require a token, even for public images.
+        if res.status == StatusCode::Unauthorized {
+            let challenge = try!(res.headers
+                .get_raw("WWW-Authenticate")
+                .and_then(|values| values.first())
+                .ok_or_else(|| err!("{} returned {} with no challenge", url, res.status)));
+            let challenge = try!(String::from_utf8(challenge.clone()));
+            // Only send our credentials to the registry they're for.
+            let authenticate = base_url == self.url;
+            let token = try!(self.token(&challenge, authenticate).chain_err(&mkerr));
+            let authorization = Some(format!("Bearer {}", token));
+            let (new_res, new_body) = try!(self.get(&url, headers, authorization)
+                .chain_err(&mkerr));
+            res = new_res;
+            body = new_body;
+        }
+        if !res.status.is_success() {
+            return Err(err!("{} returned {}", url, res.status));
+        }
+
+        // Registries normally tell us the digest, but it's just the
+        // SHA-256 hash of the manifest, so we can compute it if they don't.
+        if let Some(digest) = res.headers.get_raw("Docker-Content-Digest") {
+            if let Some(digest) = digest.first() {
+                return Ok(try!(String::from_utf8(digest.clone())));
+            }
+        }
+        let mut hasher = Sha256::new();
+        hasher.input(&body);
+        Ok(format!("sha256:{}", hasher.result_str()))
+    }
+}
+
+/// Choose a digest source: the registry at `registry_url` if specified,
+/// or else our local `docker` images, which we inspect using `runner`.
+pub fn digest_source<'a, CR>(runner: &'a CR,
+                             registry_url: Option<&str>)
+                             -> Result<Box<DigestSource + 'a>>
+    where CR: CommandRunner + 'a
+{
+    match registry_url {
+        None => Ok(Box::new(DockerInspect::new(runner))),
+        Some(url) => registry_source(url),
+    }
+}
+
+/// Create a digest source for the registry at `url`, using credentials
+/// from the environment if present.
+#[cfg(feature="registry")]
+fn registry_source(url: &str) -> Result<Box<DigestSource>> {
+    let registry = Registry::new(url);
+    match (env::var(REGISTRY_USERNAME_VAR), env::var(REGISTRY_PASSWORD_VAR)) {
+        (Ok(username), Ok(password)) => {
+            Ok(Box::new(registry.with_credentials(username, password)))
+        }
+        _ => Ok(Box::new(registry)),
+    }
+}
+
+/// Create a digest source for the registry at `url`, in builds without
+/// the `registry` feature.
+#[cfg(not(feature="registry"))]
+fn registry_source(_url: &str) -> Result<Box<DigestSource>> {
+    Err(err!("This build of cage was compiled without registry support"))
+}
+
+/// Split `image` into a repository name and either a tag or a digest, if
+/// present.  Registry hosts may contain a port, so we only treat `:` as
+/// the start of a tag if it comes after the last `/`.
+fn split_reference(image: &str) -> (&str, Option<&str>) {
+    if let Some(at) = image.find('@') {
+        return (&image[..at], Some(&image[at + 1..]));
+    }
+    let slash = image.rfind('/').map_or(0, |i| i + 1);
+    match image[slash..].rfind(':') {
+        Some(colon) => (&image[..slash + colon], Some(&image[slash + colon + 1..])),
+        None => (image, None),
+    }
+}
+
+/// Parse a `WWW-Authenticate` header of the form `Bearer
+/// realm="...",service="...",scope="..."` into its parameters.  Returns
+/// `None` for other authentication schemes.
+#[cfg(feature="registry")]
+fn parse_bearer_challenge(challenge: &str) -> Option<BTreeMap<String, String>> {
+    let challenge = challenge.trim();
+    if !challenge.starts_with("Bearer ") {
+        return None;
+    }
+    let mut params = BTreeMap::new();
+    let mut rest = challenge["Bearer ".len()..].trim_left();
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => return None,
+        };
+        let key = rest[..eq].trim().to_owned();
+        rest = &rest[eq + 1..];
+        // Quoted values may contain commas, as in `scope`.
+        let value = if rest.starts_with('"') {
+            let end = match rest[1..].find('"') {
+                Some(end) => end + 1,
+                None => return None,
+            };
+            let value = rest[1..end].to_owned();
+            rest = &rest[end + 1..];
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+            value
+        };
+        params.insert(key, value);
+        rest = rest.trim_left();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_left();
+        }
+    }
+    Some(params)
+}
+
+/// Split `repository` into the registry host it names, if any, and its
+/// path in that registry's API, with the `library/` prefix used for
+/// official images in our default registry.  Docker Hub's own host names
+/// count as no host, so that we look those images up in our default
+/// registry.
+#[cfg(feature="registry")]
+fn registry_host_and_path(repository: &str) -> (Option<&str>, String) {
+    let mut parts: Vec<&str> = repository.split('/').collect();
+    let mut host = None;
+    if parts.len() > 1 &&
+       (parts[0].contains('.') || parts[0].contains(':') || parts[0] == "localhost") {
+        host = Some(parts.remove(0));
+    }
+    if host == Some("docker.io") || host == Some("index.docker.io") {
+        host = None;
+    }
+    if host.is_none() && parts.len() == 1 {
+        parts.insert(0, "library");
+    }
+    (host, parts.join("/"))
+}
+
+/// A mapping from image references to content digests.
+///
+/// The on-disk format is a YAML file mapping each image to its digest:
+///
+/// ```yaml
+/// alpine:3.4: "sha256:a2f4d5..."
+/// example.com/app1:30: "sha256:3c6b1e..."
+/// ```
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImageLock {
+    /// Our digests, indexed by image reference.
+    digests: BTreeMap<String, String>,
+}
+
+impl ImageLock {
+    /// Look up the digest of each image in `images` using `source`.
+    pub fn resolve<'a, I>(images: I, source: &DigestSource) -> Result<ImageLock>
+        where I: IntoIterator<Item = &'a str>
+    {
+        let mut digests = BTreeMap::new();
+        for image in images {
+            if !digests.contains_key(image) {
+                let digest = try!(source.digest(image)
+                    .chain_err(|| format!("could not find digest for {}", image)));
+                digests.insert(image.to_owned(), digest);
+            }
+        }
+        Ok(ImageLock { digests: digests })
+    }
+
+    /// Read in a lock file.
+    pub fn read_from_path(path: &Path) -> Result<ImageLock> {
+        let mkerr = || ErrorKind::CouldNotReadFile(path.to_owned());
+        let mut yaml = String::new();
+        let mut f = try!(fs::File::open(path).chain_err(&mkerr));
+        try!(f.read_to_string(&mut yaml).chain_err(&mkerr));
+        let digests = try!(serde_yaml::from_str(&yaml).chain_err(&mkerr));
+        Ok(ImageLock { digests: digests })
+    }
+
+    /// Write out this lock file, unless `path` is already up to date.
+    pub fn write_to_path(&self, path: &Path) -> Result<()> {
+        let yaml = try!(serde_yaml::to_string(&self.digests));
+        try!(util::write_if_changed(path, format!("{}\n", yaml).as_bytes()));
+        Ok(())
+    }
+
+    /// The digest recorded for `image`, if any.
+    pub fn digest(&self, image: &str) -> Option<&str> {
+        self.digests.get(image).map(|d| &d[..])
+    }
+
+    /// The pinned version of `image`, in the form `name@sha256:...`, if
+    /// we have a digest for it.
+    pub fn pinned(&self, image: &str) -> Option<String> {
+        self.digest(image)
+            .map(|digest| format!("{}@{}", split_reference(image).0, digest))
+    }
+
+    /// Rewrite every image in `file` in the form `name@sha256:...`.  Fails
+    /// if `file` uses an image which isn't in this lock file.
+    pub fn pin_file(&self, file: &mut dc::File) -> Result<()> {
+        for (name, service) in &mut file.services {
+            let image = match service.image {
+                Some(ref image) => try!(image.value()).to_string(),
+                None => continue,
+            };
+            let pinned = try!(self.pinned(&image).ok_or_else(|| {
+                err!("{} uses {}, which is not in the lock file", name, image)
+            }));
+            service.image = Some(try!(dc::raw(pinned)));
+        }
+        Ok(())
+    }
+
+    /// Check each image in this lock file against `source`, returning a
+    /// description of every image whose digest has changed or can no
+    /// longer be found.
+    pub fn verify(&self, source: &DigestSource) -> Vec<String> {
+        self.digests
+            .iter()
+            .filter_map(|(image, expected)| match source.digest(image) {
+                Ok(ref actual) if actual == expected => None,
+                Ok(actual) => {
+                    Some(format!("{} is now {} (locked to {})", image, actual, expected))
+                }
+                Err(err) => Some(format!("{}: {}", image, err)),
+            })
+            .collect()
+    }
+}
+
+/// A digest source which returns canned digests, for testing.
+#[cfg(test)]
+impl DigestSource for BTreeMap<String, String> {
+    fn digest(&self, image: &str) -> Result<String> {
+        self.get(image).cloned().ok_or_else(|| err!("no such image {}", image))
+    }
+}
+
+#[test]
+fn splits_image_references() {
+    assert_eq!(split_reference("alpine"), ("alpine", None));
+    assert_eq!(split_reference("alpine:3.4"), ("alpine", Some("3.4")));
+    assert_eq!(split_reference("localhost:5000/app"), ("localhost:5000/app", None));
+    assert_eq!(split_reference("localhost:5000/app:1"),
+               ("localhost:5000/app", Some("1")));
+    assert_eq!(split_reference("app@sha256:abc"), ("app", Some("sha256:abc")));
+}
+
+#[test]
+fn picks_digest_for_matching_repository() {
+    let digests = &["example.com/app@sha256:111", "alpine@sha256:222"];
+    assert_eq!(digest_for_repository("alpine:3.4", digests).unwrap(), "sha256:222");
+    assert!(digest_for_repository("busybox", digests).is_err());
+}
+
+#[test]
+fn docker_inspect_runs_docker_using_runner() {
+    use command_runner::TestCommandRunner;
+
+    // Our test runner doesn't print anything, so there's no digest.
+    let runner = TestCommandRunner::new();
+    assert!(DockerInspect::new(&runner).digest("alpine:3.4").is_err());
+    assert_ran!(runner, {
+        ["docker", "inspect", "--type=image", "--format={{json .RepoDigests}}", "alpine:3.4"]
+    });
+}
+
+#[test]
+fn resolves_pins_and_verifies_images() {
+    use project::Project;
+
+    let mut source = BTreeMap::new();
+    source.insert("alpine:3.4".to_owned(), "sha256:aaa".to_owned());
+    source.insert("example.com:5000/app:1".to_owned(), "sha256:bbb".to_owned());
+
+    let lock = ImageLock::resolve(vec!["alpine:3.4", "example.com:5000/app:1", "alpine:3.4"],
+                                  &source)
+        .unwrap();
+    assert_eq!(lock.pinned("alpine:3.4").unwrap(), "alpine@sha256:aaa");
+    assert_eq!(lock.pinned("example.com:5000/app:1").unwrap(),
+               "example.com:5000/app@sha256:bbb");
+    assert_eq!(lock.pinned("busybox"), None);
+    assert!(ImageLock::resolve(vec!["busybox"], &source).is_err());
+
+    // Lock files should survive a round trip to disk.
+    let proj = Project::from_example("hello").unwrap();
+    let path = proj.output_dir().join("images.lock.yml");
+    lock.write_to_path(&path).unwrap();
+    assert_eq!(ImageLock::read_from_path(&path).unwrap(), lock);
+    proj.remove_test_output().unwrap();
+
+    assert!(lock.verify(&source).is_empty());
+    source.insert("alpine:3.4".to_owned(), "sha256:ccc".to_owned());
+    source.remove("example.com:5000/app:1");
+    let problems = lock.verify(&source);
+    assert_eq!(problems.len(), 2);
+    assert_eq!(problems[0], "alpine:3.4 is now sha256:ccc (locked to sha256:aaa)");
+}
+
+#[test]
+#[cfg(feature="registry")]
+fn looks_up_digests_in_registry() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    let manifest = r#"{"schemaVersion":2}"#;
+    server.respond("GET", "/v2/library/alpine/manifests/3.4", 200, manifest);
+    server.respond("GET", "/v2/team/app/manifests/latest", 200, manifest);
+
+    let mut hasher = Sha256::new();
+    hasher.input_str(manifest);
+    let expected = format!("sha256:{}", hasher.result_str());
+    let registry = Registry::new(format!("{}/", server.url()));
+    assert_eq!(registry.digest("alpine:3.4").unwrap(), expected);
+    assert_eq!(registry.digest("docker.io/library/alpine:3.4").unwrap(),
+               expected);
+    assert!(registry.digest("missing").is_err());
+    let requests = server.requests();
+    assert_eq!(requests[0].headers.get("accept").unwrap(), MANIFEST_TYPES);
+
+    // Images which name a registry host are looked up there instead.
+    let private = MockServer::new();
+    private.respond("GET", "/v2/team/app/manifests/latest", 200, manifest);
+    let private_host = private.url().trim_left_matches("http://").to_owned();
+    assert_eq!(registry.digest(&format!("{}/team/app", private_host)).unwrap(),
+               expected);
+    assert_eq!(private.requests().len(), 1);
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[test]
+#[cfg(feature="registry")]
+fn finds_registry_hosts_in_repositories() {
+    assert_eq!(registry_host_and_path("alpine"),
+               (None, "library/alpine".to_owned()));
+    assert_eq!(registry_host_and_path("team/app"),
+               (None, "team/app".to_owned()));
+    assert_eq!(registry_host_and_path("docker.io/team/app"),
+               (None, "team/app".to_owned()));
+    assert_eq!(registry_host_and_path("registry.example.com/team/app"),
+               (Some("registry.example.com"), "team/app".to_owned()));
+    assert_eq!(registry_host_and_path("localhost:5000/app"),
+               (Some("localhost:5000"), "app".to_owned()));
+}
+
+#[test]
+#[cfg(feature="registry")]
+fn parses_bearer_challenges() {
+    let params = parse_bearer_challenge("Bearer realm=\"https://auth.example.com/token\",\
+                                         service=\"registry.example.com\",\
+                                         scope=\"repository:team/app:pull,push\"")
+        .unwrap();
+    assert_eq!(params["realm"], "https://auth.example.com/token");
+    assert_eq!(params["service"], "registry.example.com");
+    assert_eq!(params["scope"], "repository:team/app:pull,push");
+    assert!(parse_bearer_challenge("Basic realm=\"Registry\"").is_none());
+}
+
+#[test]
+#[cfg(feature="registry")]
+fn fetches_registry_tokens_when_challenged() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    let manifest = r#"{"schemaVersion":2}"#;
+    let challenge = format!("Bearer realm=\"{}/token\",service=\"registry.test\",\
+                             scope=\"repository:library/alpine:pull\"",
+                            server.url());
+    server.respond_with_headers("GET",
+                                "/v2/library/alpine/manifests/3.4",
+                                401,
+                                &[("WWW-Authenticate", &challenge[..])],
+                                r#"{"errors":[]}"#);
+    server.respond_if_header("GET",
+                             "/v2/library/alpine/manifests/3.4",
+                             ("Authorization", "Bearer secret"),
+                             200,
+                             manifest);
+    server.respond("GET",
+                   "/token?service=registry.test&scope=repository%3Alibrary%2Falpine%3Apull",
+                   200,
+                   r#"{"token":"secret"}"#);
+
+    let registry = Registry::new(server.url()).with_credentials("me", "pw");
+    assert!(registry.digest("alpine:3.4").unwrap().starts_with("sha256:"));
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1].headers.get("authorization").unwrap(),
+               &format!("Basic {}", b"me:pw".to_base64(STANDARD)));
+}
@@ -74,21 +74,23 @@
 
 extern crate colored;
 extern crate compose_yml;
+extern crate crypto;
 #[cfg(test)]
 extern crate env_logger;
 #[macro_use]
 extern crate error_chain;
 extern crate glob;
 extern crate handlebars;
-#[cfg(feature="hashicorp_vault")]
+#[cfg(feature="vault")]
 extern crate hashicorp_vault as vault;
+#[cfg(any(feature="vault", feature="registry"))]
+extern crate hyper;
 extern crate includedir;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
 extern crate phf;
-#[cfg(test)]
 extern crate rand;
 extern crate rayon;
 extern crate retry;
@@ -101,11 +103,14 @@ extern crate serde_derive;
 extern crate serde_yaml;
 extern crate shlex;
 extern crate url;
+extern crate yaml_rust;
 
 pub use default_tags::DefaultTags;
 pub use errors::*;
-pub use project::{PodOrService, Project, ProjectConfig, Pods, Targets};
-pub use pod::{Pod, PodType, TargetFiles, AllFiles};
+pub use project::{ExternalPluginConfig, PodOrService, PluginsConfig, Project,
+                  ProjectConfig, Pods, Targets};
+pub use pod::{DeployConfig, Pod, PodType, ResourceValues, Resources, TargetFiles,
+              AllFiles};
 pub use sources::{Sources, Source};
 pub use sources::Iter as SourceIter;
 pub use target::Target;
@@ -119,9 +124,15 @@ pub mod command_runner;
 pub mod cmd;
 mod default_tags;
 pub mod dir;
+mod encryption;
 mod errors;
+pub mod export;
 mod ext;
+mod fingerprint;
 pub mod hook;
+pub mod image_lock;
+#[cfg(all(test, any(feature="vault", feature="registry")))]
+mod mock_http;
 pub mod plugins;
 mod pod;
 mod project;
@@ -130,6 +141,9 @@ mod service_locations;
 mod sources;
 mod target;
 mod template;
+mod vars;
+#[cfg(feature="vault")]
+mod vault_client;
 
 /// Include raw data files into our binary at compile time using the
 /// `includedir_codegen` and `includedir` crates.  The actual code
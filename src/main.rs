@@ -16,7 +16,6 @@ extern crate rustc_serialize;
 extern crate yaml_rust;
 
 use std::env;
-use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
 use std::process;
@@ -24,6 +23,7 @@ use yaml_rust::yaml;
 
 use cage::command_runner::{Command, CommandRunner, OsCommandRunner};
 use cage::cmd::*;
+use cage::image_lock;
 use cage::Result;
 
 /// Load our command-line interface definitions from an external `clap`
@@ -60,11 +60,18 @@ trait ArgMatchesExt {
 
     /// Extract 'logs' options from our command-line arguments.
     fn to_logs_options(&self) -> cage::args::opts::Logs;
+
+    /// Extract `new` options from our command-line arguments.
+    fn to_new_options(&self) -> cage::cmd::NewOptions;
 }
 
 impl<'a> ArgMatchesExt for clap::ArgMatches<'a> {
     fn should_output_project(&self) -> bool {
-        self.subcommand_name() != Some("export")
+        match self.subcommand_name() {
+            Some("export") | Some("images") | Some("plugins") | Some("secrets") |
+            Some("vault") => false,
+            _ => true,
+        }
     }
 
     fn target_name(&self) -> &str {
@@ -130,6 +137,19 @@ impl<'a> ArgMatchesExt for clap::ArgMatches<'a> {
         opts
     }
 
+    fn to_new_options(&self) -> cage::cmd::NewOptions {
+        let mut opts = cage::cmd::NewOptions::default();
+        if let Some(template) = self.value_of("template") {
+            opts.template = template.to_owned();
+        }
+        if let Some(targets) = self.values_of("targets") {
+            opts.targets = targets.map(|t| t.to_owned()).collect();
+        }
+        opts.secrets = !self.is_present("no-secrets");
+        opts.vault = self.is_present("vault");
+        opts
+    }
+
     fn to_exec_command(&self) -> Option<cage::args::Command> {
         if self.is_present("COMMAND") {
             let values: Vec<&str> = self.values_of("COMMAND").unwrap().collect();
@@ -141,6 +161,16 @@ impl<'a> ArgMatchesExt for clap::ArgMatches<'a> {
     }
 }
 
+/// Ask the user a yes-or-no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    try!(io::stdout().flush());
+    let mut answer = String::new();
+    try!(io::stdin().read_line(&mut answer));
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
 /// The function which does the real work.  Unlike `main`, we have a return
 /// type of `Result` and may therefore use `try!` to handle errors.
 fn run(matches: &clap::ArgMatches) -> Result<()> {
@@ -157,8 +187,16 @@ fn run(matches: &clap::ArgMatches) -> Result<()> {
             return Ok(());
         }
         "new" => {
-            try!(cage::Project::generate_new(&try!(env::current_dir()),
-                                             sc_matches.value_of("NAME").unwrap()));
+            let parent_dir = try!(env::current_dir());
+            let name = sc_matches.value_of("NAME").unwrap();
+            let mut opts = sc_matches.to_new_options();
+            let proj_dir = parent_dir.join(name);
+            if proj_dir.exists() && !sc_matches.is_present("non-interactive") {
+                let question = format!("{} already exists.  Overwrite files in it?",
+                                       proj_dir.display());
+                opts.allow_existing = try!(confirm(&question));
+            }
+            try!(cage::Project::generate_new(&parent_dir, name, &opts));
             return Ok(());
         }
         _ => {}
@@ -169,12 +207,12 @@ fn run(matches: &clap::ArgMatches) -> Result<()> {
     if let Some(project_name) = matches.value_of("project-name") {
         proj.set_name(project_name);
     }
-    if let Some(default_tags_path) = matches.value_of("default-tags") {
-        let f = try!(fs::File::open(default_tags_path));
-        let reader = io::BufReader::new(f);
-        proj.set_default_tags(try!(cage::DefaultTags::read(reader)));
+    if let Some(default_tags_paths) = matches.values_of("default-tags") {
+        let paths = default_tags_paths.map(Path::new);
+        proj.set_default_tags(try!(cage::DefaultTags::read_from_paths(paths)));
     }
     try!(proj.set_current_target_name(matches.target_name()));
+    proj.set_force_output(matches.is_present("force-output"));
 
     // Output our project's `*.yml` files for `docker-compose` if we'll
     // need it.
@@ -237,6 +275,9 @@ fn run(matches: &clap::ArgMatches) -> Result<()> {
         }
         "source" => try!(run_source(&runner, &mut proj, sc_matches)),
         "generate" => try!(run_generate(&runner, &proj, sc_matches)),
+        "secrets" => try!(run_secrets(&runner, &proj, sc_matches)),
+        "vault" => try!(run_vault(&proj, sc_matches)),
+        "images" => try!(run_images(&runner, &proj, sc_matches)),
         "logs" => {
             let acts_on = sc_matches.to_acts_on("POD_OR_SERVICE");
             let opts = sc_matches.to_logs_options();
@@ -244,8 +285,26 @@ fn run(matches: &clap::ArgMatches) -> Result<()> {
         }
         "export" => {
             let dir = sc_matches.value_of("DIR").unwrap();
-            try!(proj.export(&Path::new(dir)));
+            let format = try!(sc_matches.value_of("format").unwrap().parse());
+            proj.set_strict_tags(sc_matches.is_present("strict-tags"));
+            if let Some(lock_path) = sc_matches.value_of("lock-file") {
+                let source = try!(image_lock::digest_source(&runner,
+                                                            sc_matches.value_of("registry")));
+                try!(proj.export_locked_as(format,
+                                           Path::new(dir),
+                                           Path::new(lock_path),
+                                           &*source,
+                                           sc_matches.is_present("pin-images")));
+            } else {
+                try!(proj.export_as(format, &Path::new(dir)));
+            }
         }
+        "port" => {
+            let service = sc_matches.value_of("SERVICE").unwrap();
+            let container_port = sc_matches.value_of("CONTAINER_PORT").unwrap();
+            try!(proj.port(service, container_port));
+        }
+        "plugins" => try!(proj.plugins_list()),
         unknown => unreachable!("Unexpected subcommand '{}'", unknown),
     }
 
@@ -286,14 +345,92 @@ fn run_source<R>(runner: &R,
         unknown => unreachable!("Unexpected subcommand '{}'", unknown),
     }
 
-    // Regenerate our output if it might have changed.
+    // Regenerate our output if it might have changed, and tell the user
+    // how to restart any running pods which it affects.
     if re_output {
-        try!(proj.output());
+        let target = proj.current_target();
+        let pods: Vec<String> = try!(proj.output())
+            .into_iter()
+            .filter(|name| {
+                proj.pod(name).map_or(false, |pod| {
+                    pod.enabled_in(target) && pod.pod_type() != cage::PodType::Task
+                })
+            })
+            .collect();
+        if !pods.is_empty() {
+            println!("Now run `cage up {}` for these changes to take effect.",
+                     pods.join(" "));
+        }
     }
 
     Ok(())
 }
 
+/// Our `secrets` subcommand.
+fn run_secrets<R>(runner: &R,
+                  proj: &cage::Project,
+                  matches: &clap::ArgMatches)
+                  -> Result<()>
+    where R: CommandRunner
+{
+    // We know that we always have a subcommand because our `cli.yml`
+    // requires this and `clap` is supposed to enforce it.
+    let sc_name = matches.subcommand_name().unwrap();
+    match sc_name {
+        "encrypt" => try!(proj.secrets_encrypt()),
+        "decrypt" => try!(proj.secrets_decrypt()),
+        "edit" => try!(proj.secrets_edit(runner)),
+        unknown => unreachable!("Unexpected subcommand '{}'", unknown),
+    }
+    Ok(())
+}
+
+/// Our `images` subcommand.
+fn run_images<R>(runner: &R, proj: &cage::Project, matches: &clap::ArgMatches) -> Result<()>
+    where R: CommandRunner
+{
+    // We know that we always have a subcommand because our `cli.yml`
+    // requires this and `clap` is supposed to enforce it.
+    let sc_name = matches.subcommand_name().unwrap();
+    let sc_matches: &clap::ArgMatches = matches.subcommand_matches(sc_name).unwrap();
+    match sc_name {
+        "tag" => {
+            let tag_path = sc_matches.value_of("TAG_FILE").unwrap();
+            let version = sc_matches.value_of("version");
+            try!(proj.images_tag(runner, version, Path::new(tag_path)));
+        }
+        "verify" => {
+            let lock_path = sc_matches.value_of("LOCK_FILE").unwrap();
+            let source = try!(image_lock::digest_source(runner, sc_matches.value_of("registry")));
+            try!(proj.images_verify(Path::new(lock_path), &*source));
+        }
+        unknown => unreachable!("Unexpected subcommand '{}'", unknown),
+    }
+    Ok(())
+}
+
+/// Our `vault` subcommand.
+#[cfg(feature="vault")]
+fn run_vault(proj: &cage::Project, matches: &clap::ArgMatches) -> Result<()> {
+    // We know that we always have a subcommand because our `cli.yml`
+    // requires this and `clap` is supposed to enforce it.
+    let sc_name = matches.subcommand_name().unwrap();
+    let sc_matches: &clap::ArgMatches = matches.subcommand_matches(sc_name).unwrap();
+    match sc_name {
+        "tokens" if sc_matches.is_present("revoke") => try!(proj.vault_tokens_revoke()),
+        "tokens" => try!(proj.vault_tokens_list()),
+        "check-policies" => try!(proj.vault_check_policies()),
+        unknown => unreachable!("Unexpected subcommand '{}'", unknown),
+    }
+    Ok(())
+}
+
+/// Our `vault` subcommand, in builds without Vault support.
+#[cfg(not(feature="vault"))]
+fn run_vault(_proj: &cage::Project, _matches: &clap::ArgMatches) -> Result<()> {
+    Err(cage::err("This build of cage does not include Vault support"))
+}
+
 /// Our `generate` subcommand.
 fn run_generate<R>(_runner: &R,
                    proj: &cage::Project,
@@ -301,9 +438,12 @@ fn run_generate<R>(_runner: &R,
                    -> Result<()>
     where R: CommandRunner
 {
-    // We know that we always have a subcommand because our `cli.yml`
-    // requires this and `clap` is supposed to enforce it.
-    let sc_name = matches.subcommand_name().unwrap();
+    // With no subcommand, list our generators, including any templates
+    // supplied by the project or user.
+    let sc_name = match matches.subcommand_name() {
+        Some(sc_name) => sc_name,
+        None => return proj.generate_list(),
+    };
     let sc_matches: &clap::ArgMatches = matches.subcommand_matches(sc_name).unwrap();
 
     match sc_name {
@@ -0,0 +1,218 @@
+//! A tiny HTTP server for testing code which talks to HTTP APIs.  This is
+//! only compiled in test mode.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request received by a `MockServer`.
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// The HTTP method, such as `GET`.
+    pub method: String,
+    /// The path requested, including any query string.
+    pub path: String,
+    /// Our request headers.  Header names are converted to lowercase.
+    pub headers: BTreeMap<String, String>,
+    /// The body of the request.
+    pub body: String,
+}
+
+/// A canned response.
+#[derive(Debug, Clone)]
+struct Response {
+    /// Only use this response for requests with this header and value.
+    /// Header names are lowercase.
+    required_header: Option<(String, String)>,
+    /// Our HTTP status.
+    status: u16,
+    /// Extra response headers.
+    headers: Vec<(String, String)>,
+    /// Our response body.
+    body: String,
+}
+
+/// Canned responses, indexed by method and path.
+type Responses = BTreeMap<(String, String), Vec<Response>>;
+
+/// A local HTTP server which returns canned JSON responses and records
+/// the requests it receives.  The server thread runs until the test
+/// process exits.
+#[derive(Debug)]
+pub struct MockServer {
+    /// The base URL of our server.
+    url: String,
+    /// The responses we'll return.
+    responses: Arc<Mutex<Responses>>,
+    /// The requests we've received so far.
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    /// Start a new server on a random local port.
+    pub fn new() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .expect("could not bind mock HTTP server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = Arc::new(Mutex::new(BTreeMap::new()));
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let thread_responses = responses.clone();
+        let thread_requests = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    handle(stream, &thread_responses, &thread_requests);
+                }
+            }
+        });
+
+        MockServer {
+            url: url,
+            responses: responses,
+            requests: requests,
+        }
+    }
+
+    /// The base URL of this server, without a trailing `/`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Return `body` with `status` for all requests matching `method`
+    /// and `path`.  Unknown requests get a 404.
+    pub fn respond(&self, method: &str, path: &str, status: u16, body: &str) {
+        self.respond_with_headers(method, path, status, &[], body)
+    }
+
+    /// Like `respond`, but also send the specified response `headers`.
+    pub fn respond_with_headers(&self,
+                                method: &str,
+                                path: &str,
+                                status: u16,
+                                headers: &[(&str, &str)],
+                                body: &str) {
+        self.add_response(method, path, None, status, headers, body)
+    }
+
+    /// Like `respond`, but only for requests which include `header`, which
+    /// is a header name and value.  These take precedence over responses
+    /// without a required header.
+    pub fn respond_if_header(&self,
+                             method: &str,
+                             path: &str,
+                             header: (&str, &str),
+                             status: u16,
+                             body: &str) {
+        let required = Some((header.0.to_lowercase(), header.1.to_owned()));
+        self.add_response(method, path, required, status, &[], body)
+    }
+
+    /// Add a response, replacing any with the same `required_header`.
+    fn add_response(&self,
+                    method: &str,
+                    path: &str,
+                    required_header: Option<(String, String)>,
+                    status: u16,
+                    headers: &[(&str, &str)],
+                    body: &str) {
+        let mut responses = self.responses.lock().unwrap();
+        let matching = responses.entry((method.to_owned(), path.to_owned()))
+            .or_insert_with(Vec::new);
+        matching.retain(|r| r.required_header != required_header);
+        matching.push(Response {
+            required_header: required_header,
+            status: status,
+            headers: headers.iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            body: body.to_owned(),
+        });
+    }
+
+    /// The requests we've received so far.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Read a single request from `stream`, record it, and reply.  We only
+/// support one request per connection.
+fn handle(stream: TcpStream,
+          responses: &Mutex<Responses>,
+          requests: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    let mut words = request_line.split_whitespace();
+    let method = words.next().unwrap_or("").to_owned();
+    let path = words.next().unwrap_or("").to_owned();
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).is_err() {
+            return;
+        }
+        let line = line.trim_right();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            let (name, value) = line.split_at(colon);
+            headers.insert(name.trim().to_lowercase(), value[1..].trim().to_owned());
+        }
+    }
+
+    let len = headers.get("content-length")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; len];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let response = {
+        let responses = responses.lock().unwrap();
+        let matching = responses.get(&(method.clone(), path.clone()));
+        let matches_header = |r: &&Response| match r.required_header {
+            Some((ref name, ref value)) => headers.get(name) == Some(value),
+            None => false,
+        };
+        matching.and_then(|rs| rs.iter().find(&matches_header))
+            .or_else(|| matching.and_then(|rs| rs.iter().find(|r| r.required_header.is_none())))
+            .cloned()
+            .unwrap_or_else(|| {
+                Response {
+                    required_header: None,
+                    status: 404,
+                    headers: vec![],
+                    body: r#"{"errors":[]}"#.to_owned(),
+                }
+            })
+    };
+    requests.lock().unwrap().push(Request {
+        method: method,
+        path: path,
+        headers: headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    });
+
+    let mut extra_headers = String::new();
+    for &(ref name, ref value) in &response.headers {
+        extra_headers.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    let mut stream = reader.into_inner();
+    let _ = write!(stream,
+                   "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\n{}Content-Length: \
+                    {}\r\nConnection: close\r\n\r\n{}",
+                   response.status,
+                   extra_headers,
+                   response.body.len(),
+                   response.body);
+}
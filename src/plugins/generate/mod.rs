@@ -0,0 +1,5 @@
+//! Plugins which generate files, but don't transform `dc::File` objects.
+
+pub mod template;
+#[cfg(feature="vault")]
+pub mod vault_policies;
@@ -0,0 +1,184 @@
+//! Generators for templates supplied by a project or user at runtime,
+//! rather than compiled into `cage`.
+//!
+//! Each template is a directory of Handlebars files, which will be
+//! rendered into the project using the same data as our built-in
+//! templates.  A template may describe itself using a `_template.yml`
+//! file:
+//!
+//! ```yaml
+//! description: "Add a standard Rails pod"
+//! ```
+
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use yaml_rust::YamlLoader;
+
+use errors::*;
+use plugins;
+use plugins::PluginGenerate;
+use project::Project;
+use template::Template;
+use util::ToStrOrErr;
+
+/// The file in which a template describes itself.
+const TEMPLATE_METADATA: &'static str = "_template.yml";
+
+/// Generates a template found on disk.
+#[derive(Debug)]
+pub struct Plugin {
+    /// The name of this template, which is the name of its directory.
+    name: String,
+    /// A short description of this template.
+    description: String,
+    /// The directory containing this template.
+    dir: PathBuf,
+}
+
+impl Plugin {
+    /// Load the template in `dir`.
+    fn new(dir: &Path) -> Result<Plugin> {
+        let name = try!(dir.file_name()
+            .ok_or_else(|| err!("no template name in {}", dir.display())));
+        let name = try!(Path::new(name).to_str_or_err()).to_owned();
+        let description = try!(Plugin::read_description(dir))
+            .unwrap_or_else(|| format!("Generate template from {}", dir.display()));
+        Ok(Plugin {
+            name: name,
+            description: description,
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Read our description from `TEMPLATE_METADATA`, if it exists.
+    fn read_description(dir: &Path) -> Result<Option<String>> {
+        let path = dir.join(TEMPLATE_METADATA);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mkerr = || ErrorKind::CouldNotReadFile(path.clone());
+        let mut yaml = String::new();
+        let mut f = try!(fs::File::open(&path).chain_err(&mkerr));
+        try!(f.read_to_string(&mut yaml).chain_err(&mkerr));
+        let docs = try!(YamlLoader::load_from_str(&yaml)
+            .map_err(|e| err!("could not parse {}: {}", path.display(), e)));
+        Ok(docs.get(0)
+            .and_then(|doc| doc["description"].as_str())
+            .map(|d| d.to_owned()))
+    }
+
+    /// The directories in which we look for templates for `project`, in
+    /// order of precedence: first the project's own templates, and then
+    /// the current user's.
+    pub fn template_dirs(project: &Project) -> Vec<PathBuf> {
+        let mut dirs = vec![project.root_dir().join("config").join("templates")];
+        if let Some(home) = env::home_dir() {
+            dirs.push(home.join(".cage").join("templates"));
+        }
+        dirs
+    }
+
+    /// Find all the templates in `dirs`.  If several directories contain
+    /// a template with the same name, the first one wins.  Templates which
+    /// can't be loaded are skipped with a warning.
+    pub fn discover(dirs: &[PathBuf]) -> Result<Vec<Plugin>> {
+        let mut plugins: Vec<Plugin> = vec![];
+        for dir in dirs {
+            if !dir.is_dir() {
+                continue;
+            }
+            let mkerr = || ErrorKind::CouldNotReadDirectory(dir.clone());
+            let mut template_dirs = vec![];
+            for entry in try!(fs::read_dir(dir).chain_err(&mkerr)) {
+                let path = try!(entry.chain_err(&mkerr)).path();
+                if path.is_dir() {
+                    template_dirs.push(path);
+                }
+            }
+            template_dirs.sort();
+            for template_dir in template_dirs {
+                let plugin = match Plugin::new(&template_dir) {
+                    Ok(plugin) => plugin,
+                    Err(err) => {
+                        warn!("Skipping template {}: {}", template_dir.display(), err);
+                        continue;
+                    }
+                };
+                if plugins.iter().any(|p| p.name == plugin.name) {
+                    debug!("Template {} is overridden by an earlier template",
+                           template_dir.display());
+                } else {
+                    plugins.push(plugin);
+                }
+            }
+        }
+        Ok(plugins)
+    }
+}
+
+impl plugins::Plugin for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PluginGenerate for Plugin {
+    fn generator_description(&self) -> &str {
+        &self.description
+    }
+
+    fn generate(&self, project: &Project, out: &mut io::Write) -> Result<()> {
+        let mut tmpl = try!(Template::from_dir(self.name.clone(), &self.dir));
+        try!(tmpl.generate(&project.root_dir(), project, out));
+        Ok(())
+    }
+}
+
+#[test]
+fn discovers_and_generates_templates() {
+    use env_logger;
+    use std::io::Write;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("hello").unwrap();
+    let write = |path: PathBuf, data: &str| {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+    };
+
+    // Set up a "project" and a "user" template directory.
+    let project_dir = proj.output_dir().join("project_templates");
+    let user_dir = proj.output_dir().join("user_templates");
+    write(project_dir.join("sidecar").join(TEMPLATE_METADATA),
+          "description: \"Add our sidecar\"\n");
+    write(project_dir.join("sidecar").join("pods").join("sidecar.yml"),
+          "# Sidecar for {{name}}\n");
+    write(user_dir.join("sidecar").join("pods").join("sidecar.yml"), "# Ignored\n");
+    write(user_dir.join("rails").join("pods").join("rails.yml"), "# Rails\n");
+    write(user_dir.join("broken").join(TEMPLATE_METADATA), "description: [\n");
+
+    let plugins = Plugin::discover(&[project_dir, user_dir.clone(), user_dir.join("missing")])
+        .unwrap();
+    let names: Vec<_> = plugins.iter().map(|p| &p.name[..]).collect();
+    assert_eq!(names, &["sidecar", "rails"]);
+    assert_eq!(plugins[0].generator_description(), "Add our sidecar");
+    assert!(plugins[1].generator_description().starts_with("Generate template from "));
+
+    // Generate into a copy of our project's output directory, so we don't
+    // modify our example.
+    let root = proj.output_dir().join("generated");
+    let mut tmpl = Template::from_dir("sidecar", &plugins[0].dir).unwrap();
+    tmpl.generate(&root, &proj, &mut io::sink()).unwrap();
+    let mut generated = String::new();
+    fs::File::open(root.join("pods").join("sidecar.yml"))
+        .unwrap()
+        .read_to_string(&mut generated)
+        .unwrap();
+    assert_eq!(generated, "# Sidecar for hello\n");
+    assert!(!root.join(TEMPLATE_METADATA).exists());
+
+    proj.remove_test_output().unwrap();
+}
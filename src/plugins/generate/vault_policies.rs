@@ -0,0 +1,180 @@
+//! Plugin which generates skeleton Vault policies for the policies named
+//! in `config/vault.yml`.
+
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use errors::*;
+use plugins;
+use plugins::{PluginGenerate, PluginNew};
+use plugins::transform::vault::{self, PolicyUser};
+use project::Project;
+use util::ConductorPathExt;
+
+#[cfg(feature = "serde_derive")]
+include!(concat!("vault_policies_config.in.rs"));
+#[cfg(feature = "serde_codegen")]
+include!(concat!(env!("OUT_DIR"), "/plugins/generate/vault_policies_config.rs"));
+
+/// Generates `config/vault_policies/*.hcl`.
+#[derive(Debug)]
+pub struct Plugin;
+
+impl Plugin {
+    /// The directory in which we keep our Vault policies.  This may be
+    /// overridden using `plugins.settings.vault_policies.dir`.
+    pub fn policies_dir(project: &Project) -> Result<PathBuf> {
+        let settings: Settings =
+            try!(project.config().plugins.settings_for(Self::plugin_name()));
+        Ok(match settings.dir {
+            Some(dir) => project.root_dir().join(dir),
+            None => project.root_dir().join("config").join("vault_policies"),
+        })
+    }
+
+    /// The path to the file containing the named policy.
+    pub fn policy_path(project: &Project, name: &str) -> Result<PathBuf> {
+        Ok(try!(Self::policies_dir(project)).join(format!("{}.hcl", name)))
+    }
+
+    /// Policies which are referenced by `config/vault.yml`, but which
+    /// don't exist in `policies_dir`, along with the services which use
+    /// them.
+    pub fn missing_policies(project: &Project) -> Result<BTreeMap<String, Vec<PolicyUser>>> {
+        let users = try!(vault::Plugin::policy_users(project));
+        let dir = try!(Self::policies_dir(project));
+        Ok(users.into_iter()
+            .filter(|&(ref name, _)| !dir.join(format!("{}.hcl", name)).exists())
+            .collect())
+    }
+}
+
+impl plugins::Plugin for Plugin {
+    fn name(&self) -> &str {
+        Self::plugin_name()
+    }
+}
+
+impl PluginNew for Plugin {
+    fn plugin_name() -> &'static str {
+        "vault_policies"
+    }
+
+    fn new(_project: &Project) -> Result<Self> {
+        Ok(Plugin)
+    }
+}
+
+impl PluginGenerate for Plugin {
+    fn generator_description(&self) -> &str {
+        "Write skeleton policies for config/vault.yml"
+    }
+
+    fn generate(&self, project: &Project, out: &mut io::Write) -> Result<()> {
+        // Never overwrite existing policies, which may have been edited.
+        for (name, users) in try!(Self::missing_policies(project)) {
+            let path = try!(Self::policy_path(project, &name));
+            let rel_path = path.strip_prefix(project.root_dir()).unwrap_or(&path).to_owned();
+            try!(writeln!(out, "Generating: {}", rel_path.display()));
+            let mkerr = || ErrorKind::CouldNotWriteFile(path.clone());
+            try!(path.with_guaranteed_parent());
+            let mut f = try!(fs::File::create(&path).chain_err(&mkerr));
+            try!(f.write_all(skeleton_policy(project, &name, &users).as_bytes())
+                .chain_err(&mkerr));
+        }
+        Ok(())
+    }
+}
+
+/// Generate a skeleton policy granting read access to the conventional
+/// secret path shared by all of `users`.  For a single service, this is
+/// `secret/$PROJECT/$TARGET/$POD/$SERVICE/*`, and for groups of services,
+/// we drop trailing path components until all the services match.
+fn skeleton_policy(project: &Project, name: &str, users: &[PolicyUser]) -> String {
+    let first = &users[0];
+    let mut components = vec![&first.target[..], &first.pod[..], &first.service[..]];
+    for user in &users[1..] {
+        let user_components = [&user.target[..], &user.pod[..], &user.service[..]];
+        let common = components.iter()
+            .zip(user_components.iter())
+            .take_while(|&(a, b)| a == b)
+            .count();
+        components.truncate(common);
+    }
+    let mut path = format!("secret/{}", project.name());
+    for component in components {
+        path.push('/');
+        path.push_str(component);
+    }
+
+    let mut policy = format!("# Vault policy {}, generated by cage.\n#\n# Used by:\n",
+                             name);
+    for user in users {
+        policy.push_str(&format!("#   {}/{} ({})\n", user.pod, user.service, user.target));
+    }
+    policy.push_str(&format!("\npath \"{}/*\" {{\n  policy = \"read\"\n}}\n", path));
+    policy
+}
+
+#[test]
+fn generates_and_checks_policies() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    use serde_helpers::YamlValue;
+    use serde_yaml;
+
+    // Keep our generated policies out of our example's source tree.
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    let dir = env::current_dir().unwrap().join(proj.output_dir()).join("vault_policies");
+    let yaml = format!("dir: {:?}\n", dir.to_str().unwrap());
+    let settings: YamlValue = serde_yaml::from_str(&yaml).unwrap();
+    proj.config_mut().plugins.settings.insert("vault_policies".to_owned(), settings);
+    assert_eq!(Plugin::policies_dir(&proj).unwrap(), dir);
+
+    let missing = Plugin::missing_policies(&proj).unwrap();
+    let names: Vec<_> = missing.keys().cloned().collect();
+    // The `rake` service uses AppRole, and `db` is only enabled in
+    // development, where we don't use vault.
+    assert_eq!(names,
+               &["vault_integration-production",
+                 "vault_integration-production-frontend-web",
+                 "vault_integration-production-ssl"]);
+    assert_eq!(missing["vault_integration-production-ssl"],
+               &[PolicyUser {
+                     target: "production".to_owned(),
+                     pod: "frontend".to_owned(),
+                     service: "web".to_owned(),
+                 }]);
+
+    // Check the paths used in our skeleton policies.
+    let policy = skeleton_policy(&proj,
+                                 "vault_integration-production-frontend-web",
+                                 &missing["vault_integration-production-frontend-web"]);
+    assert!(policy.contains("path \"secret/vault_integration/production/frontend/web/*\""));
+    let shared = skeleton_policy(&proj,
+                                 "shared",
+                                 &[PolicyUser {
+                                       target: "production".to_owned(),
+                                       pod: "frontend".to_owned(),
+                                       service: "web".to_owned(),
+                                   },
+                                   PolicyUser {
+                                       target: "production".to_owned(),
+                                       pod: "rake".to_owned(),
+                                       service: "rake".to_owned(),
+                                   }]);
+    assert!(shared.contains("path \"secret/vault_integration/production/*\""));
+
+    // Generate our policies, after which none should be missing.
+    let mut out = vec![];
+    Plugin.generate(&proj, &mut out).unwrap();
+    assert!(dir.join("vault_integration-production.hcl").exists());
+    assert!(Plugin::missing_policies(&proj).unwrap().is_empty());
+
+    proj.remove_test_output().unwrap();
+}
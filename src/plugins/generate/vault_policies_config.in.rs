@@ -0,0 +1,13 @@
+// This is not a standalone Rust module.  It gets processed by serde to
+// generate serialization code and included directly into another module.
+
+/// Settings for the `vault_policies` plugin, taken from
+/// `plugins.settings.vault_policies` in `config/project.yml`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    /// The directory containing our policies, relative to the project
+    /// root.  Defaults to `config/vault_policies`.
+    #[serde(default)]
+    dir: Option<PathBuf>,
+}
@@ -5,11 +5,15 @@ use std::fmt;
 use std::io;
 use std::marker::PhantomData;
 
+use args;
+use command_runner::DynCommandRunner;
 use errors::*;
 use pod::Pod;
 use project::Project;
+use target::Target;
 use template::Template;
 
+pub mod generate;
 pub mod transform;
 
 /// The context in which a plugin is being applied.
@@ -44,6 +48,60 @@ pub enum Operation {
     Export,
 }
 
+/// A command which can trigger lifecycle callbacks.  (Adding new kinds of
+/// events will be a breaking API change for plugins.)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// Starting services with `up`.
+    Up,
+    /// Stopping services with `stop`.
+    Stop,
+    /// Removing containers with `rm`.
+    Rm,
+    /// Building images with `build`.
+    Build,
+    /// Pulling images with `pull`.
+    Pull,
+    /// Running a task pod with `run`.
+    Run,
+    /// Executing a command in a running container with `exec`.
+    Exec,
+    /// Running tests with `test`.
+    Test,
+}
+
+impl LifecycleEvent {
+    /// Look up the event corresponding to a `docker-compose` subcommand
+    /// passed through by `CommandCompose`, if there is one.  (`run`,
+    /// `exec` and `test` are handled by their own commands.)
+    pub fn from_compose_command(command: &str) -> Option<LifecycleEvent> {
+        match command {
+            "up" => Some(LifecycleEvent::Up),
+            "stop" => Some(LifecycleEvent::Stop),
+            "rm" => Some(LifecycleEvent::Rm),
+            "build" => Some(LifecycleEvent::Build),
+            "pull" => Some(LifecycleEvent::Pull),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LifecycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            LifecycleEvent::Up => "up",
+            LifecycleEvent::Stop => "stop",
+            LifecycleEvent::Rm => "rm",
+            LifecycleEvent::Build => "build",
+            LifecycleEvent::Pull => "pull",
+            LifecycleEvent::Run => "run",
+            LifecycleEvent::Exec => "exec",
+            LifecycleEvent::Test => "test",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// The "super-trait" of all our specific plugin traits.  This needs to be
 /// usable as a [trait object][], so it may not contain any static "class"
 /// methods or methods with type parameters.  Those can be found in
@@ -55,7 +113,7 @@ pub enum Operation {
 /// [trait object]: https://doc.rust-lang.org/book/trait-objects.html
 pub trait Plugin: Sync {
     /// The name of this plugin (available after we create an instance).
-    fn name(&self) -> &'static str;
+    fn name(&self) -> &str;
 }
 
 /// Initialization for `Plugin`.  These methods can't be part of `Plugin`
@@ -86,13 +144,46 @@ pub trait PluginTransform: Plugin {
                  ctx: &Context,
                  file: &mut dc::File)
                  -> Result<()>;
+
+    /// Will this plugin actually do anything in the specified target?
+    /// This is only used to give the user an accurate picture of our
+    /// transform pipeline, so it's fine to be conservative and return
+    /// `true`.
+    fn is_enabled_in(&self, _target: &Target) -> bool {
+        true
+    }
+}
+
+/// A plugin which wants to be notified before and after commands which
+/// act on pods and services.  Both callbacks do nothing by default.
+pub trait PluginLifecycle: Plugin {
+    /// Called before `event` runs.  Returning an error will prevent the
+    /// command from running.
+    fn before(&self,
+              _event: LifecycleEvent,
+              _project: &Project,
+              _act_on: &args::ActOn,
+              _runner: &DynCommandRunner)
+              -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after `event` has run successfully.
+    fn after(&self,
+             _event: LifecycleEvent,
+             _project: &Project,
+             _act_on: &args::ActOn,
+             _runner: &DynCommandRunner)
+             -> Result<()> {
+        Ok(())
+    }
 }
 
 /// A plugin which can generate source code.
 pub trait PluginGenerate: Plugin {
     /// A short, human-readable description of what this generator does in
     /// fewer than 60 characters (for display on monospaced terminals).
-    fn generator_description(&self) -> &'static str;
+    fn generator_description(&self) -> &str;
 
     /// Generate source code.  The default implementation generates the
     /// template of the same name as the plugin, using the project as
@@ -111,6 +202,13 @@ pub struct Manager {
 
     /// Our code generator plugins.
     generators: Vec<Box<PluginGenerate>>,
+
+    /// Our plugins which want to hear about lifecycle events.
+    lifecycles: Vec<Box<PluginLifecycle>>,
+
+    /// The names of all our built-in transforms, including any which were
+    /// disabled or aren't configured for this project.
+    builtin_transforms: Vec<&'static str>,
 }
 
 impl Manager {
@@ -119,6 +217,8 @@ impl Manager {
         let mut manager = Manager {
             transforms: vec![],
             generators: vec![],
+            lifecycles: vec![],
+            builtin_transforms: vec![],
         };
         // We instantiate some of these plugins twice, could we be more
         // clever about it?
@@ -130,11 +230,19 @@ impl Manager {
         try!(manager.register_transform::<transform::sources::Plugin>(proj));
         try!(manager.register_transform::<transform::secrets::Plugin>(proj));
         try!(manager.register_vault_transform(proj));
+        try!(manager.register_transform::<transform::ports::Plugin>(proj));
+        try!(manager.register_transform::<transform::shared_network::Plugin>(proj));
 
         // Run this last, in case it wants to remove any labels used by
         // other plugins.
         try!(manager.register_transform::<transform::labels::Plugin>(proj));
 
+        // Now that we know where all our built-in plugins go, we can
+        // insert any external plugins specified by the project.
+        try!(manager.register_external_transforms(proj));
+
+        try!(manager.register_lifecycle_plugin::<transform::shared_network::Plugin>(proj));
+
         Ok(manager)
     }
 
@@ -160,26 +268,50 @@ impl Manager {
         Ok(())
     }
 
-    /// Register our vault generator.  We put this in a separate function
+    /// Register our vault generators.  We put this in a separate function
     /// so we can use `cfg`.
-    #[cfg(feature="hashicorp_vault")]
+    #[cfg(feature="vault")]
     fn register_vault_generator(&mut self, proj: &Project) -> Result<()> {
-        self.register_generator::<transform::vault::Plugin>(proj)
+        try!(self.register_generator::<transform::vault::Plugin>(proj));
+        self.register_generator::<generate::vault_policies::Plugin>(proj)
     }
 
-    /// Pretend to register our vault generator, but just leave a note in
+    /// Pretend to register our vault generators, but just leave a note in
     /// the logs.
-    #[cfg(not(feature="hashicorp_vault"))]
+    #[cfg(not(feature="vault"))]
     fn register_vault_generator(&mut self, _: &Project) -> Result<()> {
-        debug!("vault generator was disabled at build time");
+        debug!("vault generators were disabled at build time");
         Ok(())
     }
 
+    /// Find generators for any templates supplied by the project or the
+    /// current user.  Built-in generators take precedence.  We only look
+    /// for these when we need them, so that a broken template can't
+    /// interfere with other commands.
+    pub fn template_generators(&self, proj: &Project) -> Result<Vec<generate::template::Plugin>> {
+        let dirs = generate::template::Plugin::template_dirs(proj);
+        let mut templates = vec![];
+        for plugin in try!(generate::template::Plugin::discover(&dirs)) {
+            if self.generators.iter().any(|g| g.name() == plugin.name()) {
+                warn!("Ignoring template {} because it has the same name as a built-in \
+                       generator",
+                      plugin.name());
+            } else {
+                templates.push(plugin);
+            }
+        }
+        Ok(templates)
+    }
+
     /// Register a transform with this manager.
     fn register_transform<T>(&mut self, proj: &Project) -> Result<()>
         where T: PluginNew + PluginTransform + 'static
     {
-        if try!(T::is_configured_for(&proj)) {
+        self.builtin_transforms.push(T::plugin_name());
+        if proj.config().plugins.is_disabled(T::plugin_name()) {
+            debug!("{} transform was disabled by project config",
+                   T::plugin_name());
+        } else if try!(T::is_configured_for(&proj)) {
             let plugin: T = try!(self.new_plugin(&proj));
             self.transforms.push(Box::new(plugin));
         }
@@ -188,25 +320,101 @@ impl Manager {
 
     /// Register our vault transform.  We put this in a separate function
     /// so we can use `cfg`.
-    #[cfg(feature="hashicorp_vault")]
+    #[cfg(feature="vault")]
     fn register_vault_transform(&mut self, proj: &Project) -> Result<()> {
         self.register_transform::<transform::vault::Plugin>(proj)
     }
 
     /// Pretend to register our vault transform, but just leave a note in
-    /// the logs.
-    #[cfg(not(feature="hashicorp_vault"))]
+    /// the logs.  We still remember its name, so that projects can list
+    /// it in `disable` whether or not it was compiled in.
+    #[cfg(not(feature="vault"))]
     fn register_vault_transform(&mut self, _: &Project) -> Result<()> {
         debug!("vault transform was disabled at build time");
+        self.builtin_transforms.push("vault");
+        Ok(())
+    }
+
+    /// Insert the external transforms listed in our project config into
+    /// our list of transforms.
+    fn register_external_transforms(&mut self, proj: &Project) -> Result<()> {
+        // Make sure we're not disabling plugins we don't have.
+        for name in &proj.config().plugins.disable {
+            if !self.builtin_transforms.contains(&&name[..]) {
+                return Err(ErrorKind::UnknownPlugin(name.to_owned()).into());
+            }
+        }
+
+        for config in &proj.config().plugins.external {
+            let plugin = try!(transform::external::Plugin::new(proj, config)
+                .chain_err(|| ErrorKind::PluginFailed(config.name.clone())));
+            let index = match (&config.before, &config.after) {
+                (&Some(_), &Some(_)) => {
+                    return Err(err!("plugin '{}' may specify `before` or `after`, \
+                                     but not both",
+                                    &config.name));
+                }
+                (&Some(ref before), &None) => try!(self.transform_index(before)),
+                (&None, &Some(ref after)) => try!(self.transform_index(after)) + 1,
+                (&None, &None) => self.transforms.len(),
+            };
+            self.transforms.insert(index, Box::new(plugin));
+        }
+        Ok(())
+    }
+
+    /// Find the position of the named transform in our list.  We report
+    /// built-in transforms which were disabled or aren't configured for
+    /// this project separately from ones which don't exist.
+    fn transform_index(&self, name: &str) -> Result<usize> {
+        match self.transforms.iter().position(|t| t.name() == name) {
+            Some(index) => Ok(index),
+            None if self.builtin_transforms.contains(&name) => {
+                Err(ErrorKind::InactivePlugin(name.to_owned()).into())
+            }
+            None => Err(ErrorKind::UnknownPlugin(name.to_owned()).into()),
+        }
+    }
+
+    /// Get the transforms registered with this plugin manager, in the
+    /// order in which they will be applied.
+    pub fn transforms(&self) -> &[Box<PluginTransform>] {
+        &self.transforms
+    }
+
+    /// Get the lifecycle plugins registered with this plugin manager.
+    pub fn lifecycles(&self) -> &[Box<PluginLifecycle>] {
+        &self.lifecycles
+    }
+
+    /// Register a built-in lifecycle plugin with this manager.  These
+    /// can be turned off using the same `disable` list as transforms.
+    fn register_lifecycle_plugin<T>(&mut self, proj: &Project) -> Result<()>
+        where T: PluginNew + PluginLifecycle + 'static
+    {
+        if proj.config().plugins.is_disabled(T::plugin_name()) {
+            debug!("{} lifecycle plugin was disabled by project config",
+                   T::plugin_name());
+        } else if try!(T::is_configured_for(&proj)) {
+            let plugin: T = try!(self.new_plugin(&proj));
+            self.lifecycles.push(Box::new(plugin));
+        }
         Ok(())
     }
 
+    /// (Tests only.) Register an arbitrary lifecycle plugin, so that we
+    /// can test the code which calls them.
+    #[cfg(test)]
+    pub fn register_lifecycle(&mut self, plugin: Box<PluginLifecycle>) {
+        self.lifecycles.push(plugin);
+    }
+
     /// A plugin was missing, so build an appropriate error message.
-    fn missing_plugin(&self, name: &str) -> ErrorKind {
+    fn missing_plugin(&self, name: &str) -> Error {
         if name == "vault" {
-            ErrorKind::FeatureDisabled
+            ErrorKind::FeatureDisabled.into()
         } else {
-            unreachable!("Cannot find a generator named {}", name)
+            err!("no generator or template named '{}' (try `cage generate`)", name)
         }
     }
 
@@ -216,12 +424,16 @@ impl Manager {
                     name: &str,
                     out: &mut io::Write)
                     -> Result<()> {
-        let generator = try!(self.generators
-            .iter()
-            .find(|g| g.name() == name)
+        if let Some(generator) = self.generators.iter().find(|g| g.name() == name) {
+            debug!("Generating {}", generator.name());
+            return generator.generate(project, out);
+        }
+        let templates = try!(self.template_generators(project));
+        let template = try!(templates.iter()
+            .find(|t| t.name() == name)
             .ok_or_else(|| self.missing_plugin(name)));
-        debug!("Generating {}", generator.name());
-        generator.generate(project, out)
+        debug!("Generating template {}", template.name());
+        template.generate(project, out)
     }
 
     /// Apply all our transform plugins.
@@ -237,6 +449,36 @@ impl Manager {
         }
         Ok(())
     }
+
+    /// Notify all our lifecycle plugins that `event` is about to run.
+    pub fn before(&self,
+                  event: LifecycleEvent,
+                  project: &Project,
+                  act_on: &args::ActOn,
+                  runner: &DynCommandRunner)
+                  -> Result<()> {
+        for plugin in &self.lifecycles {
+            trace!("calling {} before '{}'", plugin.name(), event);
+            try!(plugin.before(event, project, act_on, runner)
+                .chain_err(|| ErrorKind::PluginFailed(plugin.name().to_owned())));
+        }
+        Ok(())
+    }
+
+    /// Notify all our lifecycle plugins that `event` has run.
+    pub fn after(&self,
+                 event: LifecycleEvent,
+                 project: &Project,
+                 act_on: &args::ActOn,
+                 runner: &DynCommandRunner)
+                 -> Result<()> {
+        for plugin in &self.lifecycles {
+            trace!("calling {} after '{}'", plugin.name(), event);
+            try!(plugin.after(event, project, act_on, runner)
+                .chain_err(|| ErrorKind::PluginFailed(plugin.name().to_owned())));
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Manager {
@@ -246,6 +488,54 @@ impl fmt::Debug for Manager {
             .iter()
             .map(|p| p.name())
             .collect::<Vec<_>>());
+        names.extend_from_slice(&self.lifecycles
+            .iter()
+            .map(|p| p.name())
+            .collect::<Vec<_>>());
         write!(f, "plugins::Manager {{ {:?} }}", &names)
     }
 }
+
+#[test]
+fn manager_honors_disable_and_external_plugin_order() {
+    use project::ExternalPluginConfig;
+
+    let mut proj = Project::from_example("hello").unwrap();
+    proj.config_mut().plugins.disable = vec!["sources".to_owned()];
+    proj.config_mut().plugins.external = vec![ExternalPluginConfig {
+                                                  name: "identity".to_owned(),
+                                                  command: vec!["cat".to_owned()],
+                                                  before: None,
+                                                  after: Some("abs_path".to_owned()),
+                                              }];
+    let manager = Manager::new(&proj).unwrap();
+    let names: Vec<_> = manager.transforms().iter().map(|t| t.name()).collect();
+    assert_eq!(names,
+               vec!["abs_path",
+                    "identity",
+                    "default_tags",
+                    "ports",
+                    "shared_network",
+                    "labels"]);
+
+    // We can disable `vault` even when it was compiled out.
+    proj.config_mut().plugins.disable = vec!["vault".to_owned()];
+    assert!(Manager::new(&proj).is_ok());
+
+    proj.config_mut().plugins.disable = vec!["no_such_plugin".to_owned()];
+    proj.config_mut().plugins.external = vec![];
+    assert!(Manager::new(&proj).is_err());
+
+    // `hello` has no secrets, so we can't put a plugin after them.
+    proj.config_mut().plugins.disable = vec![];
+    proj.config_mut().plugins.external = vec![ExternalPluginConfig {
+                                                  name: "identity".to_owned(),
+                                                  command: vec!["cat".to_owned()],
+                                                  before: None,
+                                                  after: Some("secrets".to_owned()),
+                                              }];
+    match *Manager::new(&proj).unwrap_err().kind() {
+        ErrorKind::InactivePlugin(ref name) => assert_eq!(name, "secrets"),
+        ref e => panic!("Unexpected error type {}", e),
+    }
+}
@@ -18,7 +18,7 @@ pub struct Plugin {
 }
 
 impl plugins::Plugin for Plugin {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         Self::plugin_name()
     }
 }
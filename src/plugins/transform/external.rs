@@ -0,0 +1,181 @@
+//! Plugin which runs an external command to transform a `dc::File`.
+
+use compose_yml::v2 as dc;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+
+use errors::*;
+use plugins;
+use plugins::{Operation, PluginTransform};
+use project::{ExternalPluginConfig, Project};
+use serde_helpers::{YamlValue, yaml_value_to_string};
+use util::ConductorPathExt;
+
+/// Runs an external command to transform a `dc::File`.  The command
+/// receives the file as YAML on standard input, and it should write the
+/// transformed file to standard output.  Information about the context
+/// is passed using the following environment variables:
+///
+/// - `CAGE_OPERATION`: Either `output` or `export`.
+/// - `CAGE_PROJECT`: The name of the project.
+/// - `CAGE_TARGET`: The name of the current target.
+/// - `CAGE_POD`: The name of the pod being transformed.
+/// - `CAGE_PLUGIN_SETTINGS`: Any settings for this plugin from
+///   `config/project.yml`, in YAML format.
+///
+/// Unlike our built-in plugins, these are configured entirely by
+/// `config/project.yml`, so they don't implement `PluginNew`.
+#[derive(Debug)]
+pub struct Plugin {
+    /// The name of this plugin.
+    name: String,
+    /// The program to run.
+    program: OsString,
+    /// Arguments to pass to `program`.
+    args: Vec<String>,
+    /// Our settings, serialized as YAML.
+    settings: String,
+}
+
+impl Plugin {
+    /// Create a new external plugin using the specified configuration.
+    pub fn new(project: &Project, config: &ExternalPluginConfig) -> Result<Plugin> {
+        let (program, args) = try!(config.command
+            .split_first()
+            .ok_or_else(|| err!("no command specified for plugin '{}'", &config.name)));
+
+        // Interpret relative paths (but not bare command names, which we
+        // look up using `PATH`) relative to the project root.
+        let program = if program.contains('/') && Path::new(program).is_relative() {
+            try!(project.root_dir().join(program).to_absolute()).into_os_string()
+        } else {
+            OsString::from(program)
+        };
+
+        let settings = match project.config().plugins.settings.get(&config.name) {
+            Some(&YamlValue(ref yaml)) => try!(yaml_value_to_string(yaml)),
+            None => "--- {}\n".to_owned(),
+        };
+
+        Ok(Plugin {
+            name: config.name.clone(),
+            program: program,
+            args: args.to_owned(),
+            settings: settings,
+        })
+    }
+}
+
+impl plugins::Plugin for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl PluginTransform for Plugin {
+    fn transform(&self,
+                 op: Operation,
+                 ctx: &plugins::Context,
+                 file: &mut dc::File)
+                 -> Result<()> {
+        let op_name = match op {
+            Operation::Output => "output",
+            Operation::Export => "export",
+        };
+
+        // Serialize our input.
+        let mut input = vec![];
+        try!(file.write(&mut input));
+
+        // Run our command, passing it our input.
+        let mkerr = || {
+            let mut command = vec![self.program.clone()];
+            command.extend(self.args.iter().map(OsString::from));
+            ErrorKind::CommandFailed(command)
+        };
+        debug!("Running external plugin {:?} {:?}", &self.program, &self.args);
+        let mut child = try!(process::Command::new(&self.program)
+            .args(&self.args)
+            .env("CAGE_OPERATION", op_name)
+            .env("CAGE_PROJECT", ctx.project.name())
+            .env("CAGE_TARGET", ctx.project.current_target().name())
+            .env("CAGE_POD", ctx.pod.name())
+            .env("CAGE_PLUGIN_SETTINGS", &self.settings)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .spawn()
+            .chain_err(&mkerr));
+
+        // Write our input from a separate thread, so that a plugin which
+        // writes lots of output before reading all its input can't
+        // deadlock us.  Dropping `stdin` lets the child see end-of-file.
+        let mut stdin = try!(child.stdin
+            .take()
+            .ok_or_else(|| err!("could not write to plugin '{}'", &self.name)));
+        let writer = thread::spawn(move || stdin.write_all(&input));
+        let output = try!(child.wait_with_output().chain_err(&mkerr));
+        let written = try!(writer.join()
+            .map_err(|_| err!("could not write to plugin '{}'", &self.name)));
+        if !output.status.success() {
+            return Err(mkerr().into());
+        }
+        try!(written.chain_err(&mkerr));
+
+        // Replace our file with the transformed version.
+        let yaml = try!(String::from_utf8(output.stdout));
+        *file = try!(dc::File::from_str(&yaml)
+            .chain_err(|| format!("plugin '{}' returned invalid YAML", &self.name)));
+        Ok(())
+    }
+}
+
+#[test]
+fn runs_external_command_to_transform_file() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("hello").unwrap();
+    let config = ExternalPluginConfig {
+        name: "identity".to_owned(),
+        command: vec!["cat".to_owned()],
+        before: None,
+        after: None,
+    };
+    let plugin = Plugin::new(&proj, &config).unwrap();
+
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    let original = file.clone();
+    plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    assert_eq!(file, original);
+}
+
+#[test]
+fn reads_large_output_from_external_command() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    // This plugin writes far more than a pipe buffer's worth of output
+    // before it reads any input.
+    let proj = Project::from_example("hello").unwrap();
+    let script = "yes '# padding' | head -n 100000; cat";
+    let config = ExternalPluginConfig {
+        name: "chatty".to_owned(),
+        command: vec!["sh".to_owned(), "-c".to_owned(), script.to_owned()],
+        before: None,
+        after: None,
+    };
+    let plugin = Plugin::new(&proj, &config).unwrap();
+
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    let original = file.clone();
+    plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    assert_eq!(file, original);
+}
@@ -1,24 +1,48 @@
 //! Plugin which updates the `labels` in a `dc::File`.
 
 use compose_yml::v2 as dc;
-use std::marker::PhantomData;
 
 use errors::*;
 use plugins;
 use plugins::{Operation, PluginNew, PluginTransform};
 use project::Project;
 
-/// Updates the `labels` in a `dc::File`.
+#[cfg(feature = "serde_derive")]
+include!(concat!("labels_config.in.rs"));
+#[cfg(feature = "serde_codegen")]
+include!(concat!(env!("OUT_DIR"), "/plugins/transform/labels_config.rs"));
+
+/// The prefix used by all the labels which have a special meaning to
+/// cage.
+const CAGE_LABEL_PREFIX: &'static str = "io.fdy.cage.";
+
+/// Labels which we add ourselves, and which we always keep.
+const ADDED_LABELS: &'static [&'static str] = &["io.fdy.cage.target", "io.fdy.cage.pod"];
+
+/// Updates the `labels` in a `dc::File`, and removes development-only
+/// metadata labels when exporting.
 #[derive(Debug)]
-#[allow(missing_copy_implementations)]
 pub struct Plugin {
-    /// Placeholder field for future hidden fields, to keep this from being
-    /// directly constructable.
-    _placeholder: PhantomData<()>,
+    /// Our settings from `config/project.yml`.
+    settings: Settings,
+}
+
+impl Plugin {
+    /// Should we remove `label` from exported files?
+    fn should_strip_on_export(&self, label: &str) -> bool {
+        label.starts_with(CAGE_LABEL_PREFIX) && !ADDED_LABELS.iter().any(|l| *l == label) &&
+        !self.settings.keep_on_export.iter().any(|pattern| {
+            if pattern.ends_with('*') {
+                label.starts_with(&pattern[..pattern.len() - 1])
+            } else {
+                label == pattern
+            }
+        })
+    }
 }
 
 impl plugins::Plugin for Plugin {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         Self::plugin_name()
     }
 }
@@ -28,14 +52,15 @@ impl PluginNew for Plugin {
         "labels"
     }
 
-    fn new(_project: &Project) -> Result<Self> {
-        Ok(Plugin { _placeholder: PhantomData })
+    fn new(project: &Project) -> Result<Self> {
+        let settings = try!(project.config().plugins.settings_for(Self::plugin_name()));
+        Ok(Plugin { settings: settings })
     }
 }
 
 impl PluginTransform for Plugin {
     fn transform(&self,
-                 _op: Operation,
+                 op: Operation,
                  ctx: &plugins::Context,
                  file: &mut dc::File)
                  -> Result<()> {
@@ -48,8 +73,62 @@ impl PluginTransform for Plugin {
                 .insert("io.fdy.cage.target".into(), target.into());
             service.labels.insert("io.fdy.cage.pod".into(), ctx.pod.name().into());
 
-            // TODO LOW: Remove metadata-only `io.fdy.cage.` labels?
+            // Remove metadata which is only useful to cage itself during
+            // development.
+            if op == Operation::Export {
+                let stripped: Vec<String> = service.labels
+                    .keys()
+                    .filter(|label| self.should_strip_on_export(label))
+                    .cloned()
+                    .collect();
+                for label in stripped {
+                    service.labels.remove(&label);
+                }
+            }
         }
         Ok(())
     }
 }
+
+#[test]
+fn strips_metadata_labels_on_export() {
+    use env_logger;
+    use serde_helpers::YamlValue;
+    use serde_yaml;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    {
+        let plugin = Plugin::new(&proj).unwrap();
+        let frontend = proj.pod("frontend").unwrap();
+        let ctx = plugins::Context::new(&proj, frontend);
+
+        // Metadata labels should be kept for our own use.
+        let mut file = frontend.merged_file(proj.current_target()).unwrap();
+        plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+        let web = file.services.get("web").unwrap();
+        assert!(web.labels.contains_key("io.fdy.cage.srcdir"));
+        assert!(web.labels.contains_key("io.fdy.cage.test"));
+
+        // But removed from exported files.
+        let mut file = frontend.merged_file(proj.current_target()).unwrap();
+        plugin.transform(Operation::Export, &ctx, &mut file).unwrap();
+        let web = file.services.get("web").unwrap();
+        assert!(!web.labels.contains_key("io.fdy.cage.srcdir"));
+        assert!(!web.labels.contains_key("io.fdy.cage.test"));
+        assert_eq!(web.labels.get("io.fdy.cage.target").unwrap(), "development");
+        assert_eq!(web.labels.get("io.fdy.cage.pod").unwrap(), "frontend");
+    }
+
+    // Unless we've asked to keep them.
+    let yaml = r#"---
+keep_on_export: ["io.fdy.cage.test", "io.fdy.cage.lib.*"]
+"#;
+    let settings: YamlValue = serde_yaml::from_str(yaml).unwrap();
+    proj.config_mut().plugins.settings.insert("labels".to_owned(), settings);
+    let plugin = Plugin::new(&proj).unwrap();
+    assert!(!plugin.should_strip_on_export("io.fdy.cage.test"));
+    assert!(!plugin.should_strip_on_export("io.fdy.cage.lib.coffee_rails"));
+    assert!(plugin.should_strip_on_export("io.fdy.cage.srcdir"));
+    assert!(!plugin.should_strip_on_export("com.example.other"));
+}
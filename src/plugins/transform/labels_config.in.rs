@@ -0,0 +1,13 @@
+// This is not a standalone Rust module.  It gets processed by serde to
+// generate serialization code and included directly into another module.
+
+/// Settings for the `labels` plugin, taken from `plugins.settings.labels`
+/// in `config/project.yml`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    /// `io.fdy.cage.*` labels which should be kept when exporting.  A
+    /// trailing `*` matches any label with the preceding prefix.
+    #[serde(default)]
+    keep_on_export: Vec<String>,
+}
@@ -2,8 +2,11 @@
 
 pub mod abs_path;
 pub mod default_tags;
+pub mod external;
 pub mod labels;
+pub mod ports;
 pub mod secrets;
+pub mod shared_network;
 pub mod sources;
-#[cfg(feature="hashicorp_vault")]
+#[cfg(feature="vault")]
 pub mod vault;
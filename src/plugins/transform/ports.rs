@@ -0,0 +1,428 @@
+//! Plugin which assigns free host ports to published container ports, so
+//! that several projects (or targets) can run side by side.
+
+use compose_yml::v2 as dc;
+use rand::random;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use yaml_rust::Yaml;
+
+use errors::*;
+use plugins;
+use plugins::{Operation, PluginNew, PluginTransform};
+use pod::Pod;
+use project::Project;
+use serde_helpers::{dump_yaml, load_yaml};
+use target::Target;
+
+#[cfg(feature = "serde_derive")]
+include!(concat!("ports_config.in.rs"));
+#[cfg(feature = "serde_codegen")]
+include!(concat!(env!("OUT_DIR"), "/plugins/transform/ports_config.rs"));
+
+impl PortAllocations {
+    /// The path to the port allocations for `project`.
+    fn path_for(project: &Project) -> PathBuf {
+        project.output_dir().join("ports.yml")
+    }
+
+    /// Load the port allocations for `project`, or return an empty set of
+    /// allocations if none exist.
+    fn load(project: &Project) -> Result<PortAllocations> {
+        let path = Self::path_for(project);
+        if path.exists() {
+            load_yaml(&path)
+        } else {
+            Ok(PortAllocations::default())
+        }
+    }
+
+    /// Save these allocations to `path`.
+    fn save(&self, path: &Path) -> Result<()> {
+        dump_yaml(path, self)
+    }
+
+    /// Have we already assigned `port` to a service in any target?
+    fn is_used(&self, port: u16) -> bool {
+        self.targets
+            .values()
+            .flat_map(|services| services.values())
+            .any(|ports| ports.values().any(|p| *p == port))
+    }
+
+    /// Look up the host port assigned to `container_port` on `service`
+    /// in `target`, assigning a new one from the range in `settings` if
+    /// necessary.  Also returns `true` if we made a new assignment.
+    fn allocate(&mut self,
+                settings: &Settings,
+                target: &str,
+                service: &str,
+                container_port: &str)
+                -> Result<(u16, bool)> {
+        let container_port = normalize_container_port(container_port);
+        let existing = self.targets
+            .get(target)
+            .and_then(|services| services.get(service))
+            .and_then(|ports| ports.get(container_port))
+            .cloned();
+        if let Some(port) = existing {
+            return Ok((port, false));
+        }
+
+        let port = try!(self.free_port(settings));
+        debug!("Assigning host port {} to {} {} in {}",
+               port,
+               service,
+               container_port,
+               target);
+        self.targets
+            .entry(target.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .entry(service.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .insert(container_port.to_owned(), port);
+        Ok((port, true))
+    }
+
+    /// Find a port in the range specified by `settings` which we haven't
+    /// assigned yet, and which nothing is listening on right now.  We
+    /// start searching at a random point in the range, so that projects
+    /// which share a machine are unlikely to pick the same ports.
+    fn free_port(&self, settings: &Settings) -> Result<u16> {
+        if settings.first_port == 0 || settings.first_port > settings.last_port {
+            return Err(err!("invalid port range {}-{} in ports plugin settings",
+                            settings.first_port,
+                            settings.last_port));
+        }
+        let count = u32::from(settings.last_port - settings.first_port) + 1;
+        let start = random::<u32>() % count;
+        for i in 0..count {
+            let port = settings.first_port + ((start + i) % count) as u16;
+            if !self.is_used(port) && TcpListener::bind(("127.0.0.1", port)).is_ok() {
+                return Ok(port);
+            }
+        }
+        Err(err!("no free host ports left between {} and {}",
+                 settings.first_port,
+                 settings.last_port))
+    }
+}
+
+/// A published port, written using `docker-compose`'s
+/// `[[HOST_ADDRESS:]HOST_PORT:]CONTAINER_PORT` syntax.  We work with the
+/// raw strings so that we can handle ports which still contain
+/// `docker-compose` variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortSpec<'a> {
+    /// The host address to bind, if any.
+    pub host_address: Option<&'a str>,
+    /// The host port or port range, if any.
+    pub host_port: Option<&'a str>,
+    /// The container port or port range, with an optional `/tcp` or
+    /// `/udp` suffix.
+    pub container_port: &'a str,
+}
+
+impl<'a> PortSpec<'a> {
+    /// Parse a port specification.
+    pub fn parse(spec: &'a str) -> PortSpec<'a> {
+        let mut parts = spec.rsplitn(3, ':');
+        let container_port = parts.next().unwrap_or("");
+        let host_port = parts.next().and_then(|p| if p.is_empty() { None } else { Some(p) });
+        PortSpec {
+            host_address: parts.next(),
+            host_port: host_port,
+            container_port: container_port,
+        }
+    }
+
+    /// Does this spec have a single, literal container port to which we
+    /// can assign a host port?
+    fn can_allocate(&self) -> bool {
+        let port = self.container_port.splitn(2, '/').next().unwrap_or("");
+        !port.is_empty() && port.chars().all(|c| c.is_digit(10)) &&
+        !self.host_address.map_or(false, |addr| addr.contains('$'))
+    }
+
+    /// Format this spec with a different host port.
+    fn with_host_port(&self, host_port: u16) -> String {
+        match self.host_address {
+            Some(addr) => format!("{}:{}:{}", addr, host_port, self.container_port),
+            None => format!("{}:{}", host_port, self.container_port),
+        }
+    }
+}
+
+/// Normalize `container_port` so that we can compare it with other
+/// ports.  TCP is the default protocol, so `3000/tcp` is the same as
+/// `3000`.
+pub fn normalize_container_port(container_port: &str) -> &str {
+    container_port.trim_right_matches("/tcp")
+}
+
+/// The port specs published by `service`, as strings.
+pub fn published_ports(service: &dc::Service) -> Vec<String> {
+    let yaml = serde_yaml::to_value(service);
+    match yaml["ports"] {
+        Yaml::Array(ref ports) => {
+            ports.iter().filter_map(|p| p.as_str()).map(|p| p.to_owned()).collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Assigns free host ports to the published ports of each service, and
+/// remembers them in `.cage/ports.yml`.
+#[derive(Debug)]
+pub struct Plugin {
+    /// Our settings from `config/project.yml`.
+    settings: Settings,
+    /// The host ports we've assigned so far.
+    allocations: Mutex<PortAllocations>,
+    /// The path to which we'll save `allocations` when they change.
+    allocations_path: PathBuf,
+}
+
+impl Plugin {
+    /// Should we assign host ports for `pod` in `target`?
+    fn should_allocate(&self, pod: &Pod, target: &Target) -> bool {
+        pod.allocate_ports()
+            .unwrap_or_else(|| {
+                self.settings.allocate_in_targets.iter().any(|t| t == target.name())
+            })
+    }
+
+    /// The host ports assigned to `service_name` in `pod` for the current
+    /// target, indexed by container port.
+    pub fn allocated_ports(project: &Project,
+                           pod: &Pod,
+                           service_name: &str)
+                           -> Result<BTreeMap<String, u16>> {
+        let allocations = try!(PortAllocations::load(project));
+        let key = format!("{}/{}", pod.name(), service_name);
+        Ok(allocations.targets
+            .get(project.current_target().name())
+            .and_then(|services| services.get(&key))
+            .cloned()
+            .unwrap_or_else(BTreeMap::new))
+    }
+}
+
+impl plugins::Plugin for Plugin {
+    fn name(&self) -> &str {
+        Self::plugin_name()
+    }
+}
+
+impl PluginNew for Plugin {
+    fn plugin_name() -> &'static str {
+        "ports"
+    }
+
+    fn new(project: &Project) -> Result<Self> {
+        let settings = try!(project.config().plugins.settings_for(Self::plugin_name()));
+        Ok(Plugin {
+            settings: settings,
+            allocations: Mutex::new(try!(PortAllocations::load(project))),
+            allocations_path: PortAllocations::path_for(project),
+        })
+    }
+}
+
+impl PluginTransform for Plugin {
+    fn transform(&self,
+                 op: Operation,
+                 ctx: &plugins::Context,
+                 file: &mut dc::File)
+                 -> Result<()> {
+        // Exported files are run elsewhere, so leave their ports alone.
+        let target = ctx.project.current_target();
+        if op != Operation::Output || !self.should_allocate(ctx.pod, target) {
+            return Ok(());
+        }
+
+        let mut allocations = self.allocations.lock().expect("port allocations lock poisoned");
+        let mut changed = false;
+
+        // Edit the file as YAML, for the same reasons as `Vars`.
+        let mut yaml = serde_yaml::to_value(&*file);
+        if let Yaml::Hash(ref mut top) = yaml {
+            if let Some(&mut Yaml::Hash(ref mut services)) =
+                   top.get_mut(&Yaml::String("services".to_owned())) {
+                for (name, service) in services.iter_mut() {
+                    let name = try!(name.as_str()
+                        .ok_or_else(|| err!("expected service name to be a string")));
+                    let key = format!("{}/{}", ctx.pod.name(), name);
+                    let new = try!(allocate_service_ports(&mut allocations,
+                                                          &self.settings,
+                                                          target,
+                                                          &key,
+                                                          service));
+                    changed = changed || new;
+                }
+            }
+        }
+        *file = try!(serde_yaml::from_value(yaml));
+
+        if changed {
+            try!(allocations.save(&self.allocations_path));
+        }
+        Ok(())
+    }
+}
+
+/// Replace the host ports published by `service` with the ones assigned
+/// in `allocations`, which we index using `key`, assigning new ports as
+/// specified by `settings`.  Returns `true` if we needed to assign any new
+/// ports.
+fn allocate_service_ports(allocations: &mut PortAllocations,
+                          settings: &Settings,
+                          target: &Target,
+                          key: &str,
+                          service: &mut Yaml)
+                          -> Result<bool> {
+    let ports = match *service {
+        Yaml::Hash(ref mut service) => {
+            match service.get_mut(&Yaml::String("ports".to_owned())) {
+                Some(&mut Yaml::Array(ref mut ports)) => ports,
+                _ => return Ok(false),
+            }
+        }
+        _ => return Err(err!("expected service to be a hash")),
+    };
+
+    let mut changed = false;
+    for port in ports.iter_mut() {
+        let updated = match *port {
+            Yaml::String(ref spec) => {
+                let spec = PortSpec::parse(spec);
+                if !spec.can_allocate() {
+                    continue;
+                }
+                let (host_port, new) =
+                    try!(allocations.allocate(settings, target.name(), key, spec.container_port));
+                changed = changed || new;
+                spec.with_host_port(host_port)
+            }
+            _ => continue,
+        };
+        *port = Yaml::String(updated);
+    }
+    Ok(changed)
+}
+
+#[test]
+fn port_specs_can_be_parsed() {
+    let parse = PortSpec::parse;
+    assert_eq!(parse("3000"),
+               PortSpec {
+                   host_address: None,
+                   host_port: None,
+                   container_port: "3000",
+               });
+    assert_eq!(parse("8080:3000/udp"),
+               PortSpec {
+                   host_address: None,
+                   host_port: Some("8080"),
+                   container_port: "3000/udp",
+               });
+    assert_eq!(parse("127.0.0.1::3000"),
+               PortSpec {
+                   host_address: Some("127.0.0.1"),
+                   host_port: None,
+                   container_port: "3000",
+               });
+    assert_eq!(parse("127.0.0.1:8080:3000").with_host_port(49000),
+               "127.0.0.1:49000:3000");
+    assert_eq!(parse("${PORT}:3000").with_host_port(49000), "49000:3000");
+    assert!(parse("3000/tcp").can_allocate());
+    assert!(!parse("3000-3005").can_allocate());
+    assert!(!parse("8080:$PORT").can_allocate());
+}
+
+#[test]
+fn allocates_one_host_port_per_container_port() {
+    let settings = Settings::default();
+    let mut allocations = PortAllocations::default();
+    let (port, new) = allocations.allocate(&settings, "development", "frontend/web", "3000")
+        .unwrap();
+    assert!(new);
+    assert!(port >= settings.first_port && port <= settings.last_port);
+    assert_eq!(allocations.allocate(&settings, "development", "frontend/web", "3000/tcp")
+                   .unwrap(),
+               (port, false));
+    let (udp_port, new) =
+        allocations.allocate(&settings, "development", "frontend/web", "3000/udp").unwrap();
+    assert!(new);
+    assert!(udp_port != port);
+}
+
+#[test]
+fn allocates_host_ports_from_configured_range() {
+    let mut settings = Settings::default();
+    settings.first_port = 28761;
+    settings.last_port = 28762;
+    let mut allocations = PortAllocations::default();
+    let mut ports = vec![allocations.allocate(&settings, "development", "a/web", "80")
+                             .unwrap()
+                             .0,
+                         allocations.allocate(&settings, "development", "b/web", "80")
+                             .unwrap()
+                             .0];
+    ports.sort();
+    assert_eq!(ports, vec![28761, 28762]);
+
+    // Once the range is used up, we report an error.
+    assert!(allocations.allocate(&settings, "development", "c/web", "80").is_err());
+}
+
+#[test]
+fn allocates_stable_host_ports() {
+    use env_logger;
+    use serde_helpers::YamlValue;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    let yaml = "---\nallocate_in_targets: [\"development\"]\n";
+    let settings: YamlValue = serde_yaml::from_str(yaml).unwrap();
+    proj.config_mut().plugins.settings.insert("ports".to_owned(), settings);
+
+    {
+        let frontend = proj.pod("frontend").unwrap();
+        let ctx = plugins::Context::new(&proj, frontend);
+        let output_ports = || {
+            let plugin = Plugin::new(&proj).unwrap();
+            let mut file = frontend.merged_file(proj.current_target()).unwrap();
+            plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+            published_ports(file.services.get("web").unwrap())
+        };
+
+        // We should replace the host port, and keep using the same one.
+        let ports = output_ports();
+        assert_eq!(ports.len(), 1);
+        assert!(ports[0].ends_with(":3000"));
+        assert!(!ports[0].starts_with("$"));
+        assert_eq!(output_ports(), ports);
+
+        let allocated = Plugin::allocated_ports(&proj, frontend, "web").unwrap();
+        assert_eq!(format!("{}:3000", allocated["3000"]), ports[0]);
+    }
+
+    // Other targets get their own ports, and only if enabled.
+    proj.set_current_target_name("production").unwrap();
+    {
+        let frontend = proj.pod("frontend").unwrap();
+        let ctx = plugins::Context::new(&proj, frontend);
+        let plugin = Plugin::new(&proj).unwrap();
+        let mut file = frontend.merged_file(proj.current_target()).unwrap();
+        plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+        assert_eq!(published_ports(file.services.get("web").unwrap()),
+                   vec!["${RAILS_PORT}:3000".to_owned()]);
+        assert!(Plugin::allocated_ports(&proj, frontend, "web").unwrap().is_empty());
+    }
+
+    proj.remove_test_output().unwrap();
+}
@@ -0,0 +1,55 @@
+// This is not a standalone Rust module.  It gets processed by serde to
+// generate serialization code and included directly into another module.
+
+/// Settings for the `ports` plugin, taken from `plugins.settings.ports`
+/// in `config/project.yml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    /// Targets in which we should assign free host ports to all published
+    /// ports.  Individual pods may override this by setting
+    /// `allocate_ports` in their metadata.
+    #[serde(default)]
+    allocate_in_targets: Vec<String>,
+    /// The lowest host port we may assign.
+    #[serde(default = "default_first_port")]
+    first_port: u16,
+    /// The highest host port we may assign.  Together with `first_port`,
+    /// this should stay below the range which the OS hands out for
+    /// outgoing connections (32768 and up on Linux, 49152 and up on most
+    /// other systems).
+    #[serde(default = "default_last_port")]
+    last_port: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            allocate_in_targets: vec![],
+            first_port: default_first_port(),
+            last_port: default_last_port(),
+        }
+    }
+}
+
+/// The default value of `Settings::first_port`.
+fn default_first_port() -> u16 {
+    20000
+}
+
+/// The default value of `Settings::last_port`.
+fn default_last_port() -> u16 {
+    29999
+}
+
+/// Host ports which we've assigned to services, stored in
+/// `.cage/ports.yml` so that they stay the same from one run to the next.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PortAllocations {
+    /// Assigned host ports, indexed by target name, then by
+    /// `pod/service`, then by container port (such as `3000` or
+    /// `53/udp`).
+    #[serde(default)]
+    targets: BTreeMap<String, BTreeMap<String, BTreeMap<String, u16>>>,
+}
@@ -1,17 +1,22 @@
-//! Plugin which loads secrets from `config/secrets.yml` and adds them to a
-//! project.
+//! Plugin which loads secrets from `config/secrets.yml` (or its encrypted
+//! counterpart, `config/secrets.yml.enc`) and adds them to a project.
 
 use compose_yml::v2 as dc;
-use std::collections::BTreeMap;
-#[cfg(test)]
-use std::path::Path;
-use std::path::PathBuf;
+use serde_yaml;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
 
+use encryption::Key;
 use errors::*;
 use plugins;
 use plugins::{Operation, PluginGenerate, PluginNew, PluginTransform};
 use project::Project;
 use serde_helpers::load_yaml;
+use util::{ConductorPathExt, ToStrOrErr, create_private_dir_all, write_private_file};
 
 #[cfg(feature = "serde_derive")]
 include!(concat!("secrets_config.in.rs"));
@@ -22,20 +27,94 @@ include!(concat!(env!("OUT_DIR"), "/plugins/transform/secrets_config.rs"));
 #[derive(Debug)]
 pub struct Plugin {
     /// Our `config/secrets.yml` YAML file, parsed and read into memory.
-    /// Optional because if we're being run as a `PluginGenerate`, we won't
-    /// have it (but it's guaranteed otherwise).
-    config: Option<Config>,
+    /// We don't load (or decrypt) this until the first `transform`, so
+    /// that commands which never need our secrets will work without a
+    /// key, and so that our `PluginGenerate` instance never reads it.
+    config: Mutex<Option<Config>>,
 }
 
 impl Plugin {
     /// Get the path to this plugin's config file.
-    fn config_path(project: &Project) -> PathBuf {
+    pub fn config_path(project: &Project) -> PathBuf {
         project.root_dir().join("config").join("secrets.yml")
     }
+
+    /// Get the path to the encrypted version of this plugin's config
+    /// file, which is safe to check into version control.
+    pub fn encrypted_config_path(project: &Project) -> PathBuf {
+        project.root_dir().join("config").join("secrets.yml.enc")
+    }
+
+    /// Make sure that `yaml` is a valid `config/secrets.yml` file.
+    pub fn validate_config(yaml: &str) -> Result<()> {
+        let _config: Config = try!(serde_yaml::from_str(yaml));
+        Ok(())
+    }
+
+    /// Read and decrypt `config/secrets.yml.enc`, returning the decrypted
+    /// YAML.
+    pub fn decrypt_config(project: &Project) -> Result<String> {
+        Self::decrypt_config_with_key(project, &try!(Key::find(project)))
+    }
+
+    /// Like `decrypt_config`, but using an explicit `key`.
+    pub fn decrypt_config_with_key(project: &Project, key: &Key) -> Result<String> {
+        let path = Self::encrypted_config_path(project);
+        let mkerr = || ErrorKind::CouldNotReadFile(path.clone());
+        let mut encrypted = String::new();
+        let mut f = try!(fs::File::open(&path).chain_err(&mkerr));
+        try!(f.read_to_string(&mut encrypted).chain_err(&mkerr));
+        let plaintext = try!(key.decrypt(&encrypted).chain_err(&mkerr));
+        Ok(try!(String::from_utf8(plaintext)))
+    }
+
+    /// The names of the environment variables which this plugin adds to
+    /// `service_name` in `pod_name`, and which therefore contain secrets.
+    /// Secrets delivered as files are represented by their `$NAME_FILE`
+    /// variables, which are not themselves secret.
+    pub fn secret_vars(project: &Project,
+                       pod_name: &str,
+                       service_name: &str)
+                       -> Result<BTreeSet<String>> {
+        if project.config().plugins.is_disabled(Self::plugin_name()) {
+            return Ok(BTreeSet::new());
+        }
+        let config = match try!(Self::load_config(project)) {
+            Some(config) => config,
+            None => return Ok(BTreeSet::new()),
+        };
+        let target_name = project.current_target().name();
+        Ok(config.secrets_for(target_name, pod_name, service_name)
+            .into_iter()
+            .map(|(var, _)| var)
+            .filter(|var| !config.is_delivered_as_file(pod_name, service_name, var))
+            .collect())
+    }
+
+    /// Load our config, decrypting it if necessary.
+    fn load_config(project: &Project) -> Result<Option<Config>> {
+        let path = Self::config_path(project);
+        let encrypted_path = Self::encrypted_config_path(project);
+        match (path.exists(), encrypted_path.exists()) {
+            (true, true) => {
+                Err(err!("Found both {} and {}; please remove one",
+                         path.display(),
+                         encrypted_path.display()))
+            }
+            (true, false) => Ok(Some(try!(load_yaml(&path)))),
+            (false, true) => {
+                let yaml = try!(Self::decrypt_config(project));
+                let config = try!(serde_yaml::from_str(&yaml)
+                    .chain_err(|| ErrorKind::CouldNotReadFile(encrypted_path.clone())));
+                Ok(Some(config))
+            }
+            (false, false) => Ok(None),
+        }
+    }
 }
 
 impl plugins::Plugin for Plugin {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         Self::plugin_name()
     }
 }
@@ -46,59 +125,109 @@ impl PluginNew for Plugin {
     }
 
     fn is_configured_for(project: &Project) -> Result<bool> {
-        let path = Self::config_path(project);
-        Ok(path.exists())
+        Ok(Self::config_path(project).exists() ||
+           Self::encrypted_config_path(project).exists())
     }
 
-    fn new(project: &Project) -> Result<Self> {
-        let path = Self::config_path(project);
-        let config = if path.exists() {
-            Some(try!(load_yaml(&path)))
-        } else {
-            None
-        };
-        Ok(Plugin { config: config })
+    fn new(_project: &Project) -> Result<Self> {
+        Ok(Plugin { config: Mutex::new(None) })
     }
 }
 
 impl PluginGenerate for Plugin {
-    fn generator_description(&self) -> &'static str {
+    fn generator_description(&self) -> &str {
         "Store passwords & other secrets in a local file"
     }
 }
 
 impl PluginTransform for Plugin {
     fn transform(&self,
-                 _op: Operation,
+                 op: Operation,
                  ctx: &plugins::Context,
                  file: &mut dc::File)
                  -> Result<()> {
 
-        let config = self.config
-            .as_ref()
+        let mut cached = self.config.lock().expect("config lock poisoned");
+        if cached.is_none() {
+            *cached = try!(Self::load_config(ctx.project));
+        }
+        let config = cached.as_ref()
             .expect("config should always be present for transform");
 
-        let append_service =
-            |service: &mut dc::Service, pods: &BTreeMap<_, PodSecrets>, name| {
-                let opt_env = pods.get(ctx.pod.name()).and_then(|p| p.get(name));
-                if let Some(env) = opt_env {
-                    service.environment.append(&mut env.clone());
-                }
-            };
-
         for (name, mut service) in &mut file.services {
-            service.environment.append(&mut config.common.clone());
-            append_service(&mut service, &config.pods, name);
+            // Collect all the secrets for this service.
             let target_name = ctx.project.current_target().name();
-            if let Some(target) = config.targets.get(target_name) {
-                service.environment.append(&mut target.common.clone());
-                append_service(&mut service, &target.pods, name);
+            let secrets = config.secrets_for(target_name, ctx.pod.name(), name);
+
+            // Deliver each secret as either an environment variable or a
+            // file.
+            let (file_secrets, mut env_secrets): (ServiceSecrets, ServiceSecrets) =
+                secrets.into_iter()
+                    .partition(|&(ref var, _)| {
+                        config.is_delivered_as_file(ctx.pod.name(), name, var)
+                    });
+            service.environment.append(&mut env_secrets);
+            if !file_secrets.is_empty() {
+                let files = config.files
+                    .as_ref()
+                    .expect("should only have file secrets if `files` is present");
+                try!(self.deliver_as_files(op, ctx, name, &mut service, files,
+                                           &file_secrets));
             }
         }
         Ok(())
     }
 }
 
+impl Plugin {
+    /// Deliver `secrets` to `service` as files mounted into the container,
+    /// pointing to each file with an environment variable `$NAME_FILE`.
+    /// When exporting, we don't know where the files should come from,
+    /// so we leave it up to the deployment platform to supply them at
+    /// `files.mount_path`.
+    fn deliver_as_files(&self,
+                        op: Operation,
+                        ctx: &plugins::Context,
+                        service_name: &str,
+                        service: &mut dc::Service,
+                        files: &FilesConfig,
+                        secrets: &ServiceSecrets)
+                        -> Result<()> {
+        let mount_path = Path::new(&files.mount_path);
+        if op == Operation::Output {
+            // Replace any secret files left over from a previous run, and
+            // keep other users out of the directory we put them in.
+            let dir = try!(ctx.project
+                    .output_dir()
+                    .join("secrets")
+                    .join(ctx.pod.name())
+                    .join(service_name)
+                    .to_absolute());
+            if dir.exists() {
+                try!(fs::remove_dir_all(&dir)
+                    .chain_err(|| ErrorKind::CouldNotWriteFile(dir.clone())));
+            }
+            try!(create_private_dir_all(&dir));
+            for (var, value) in secrets.iter() {
+                try!(write_private_file(&dir.join(var), value.as_bytes()));
+            }
+
+            // Mount our secrets into the container.
+            let mount = format!("{}:{}:ro",
+                                try!(dir.to_str_or_err()),
+                                try!(mount_path.to_str_or_err()));
+            service.volumes.push(dc::value(try!(dc::VolumeMount::from_str(&mount))));
+        }
+
+        for (var, _) in secrets.iter() {
+            let path = mount_path.join(var);
+            service.environment
+                .insert(format!("{}_FILE", var), try!(path.to_str_or_err()).to_owned());
+        }
+        Ok(())
+    }
+}
+
 #[test]
 fn enabled_for_projects_with_config_file() {
     use env_logger;
@@ -128,3 +257,55 @@ fn injects_secrets_into_services() {
     assert_eq!(web.environment.get("SOME_PASSWORD").expect("has SOME_PASSWORD"),
                "production secret");
 }
+
+#[test]
+fn validates_config() {
+    assert!(Plugin::validate_config("common:\n  PASSWORD: magic\n").is_ok());
+    assert!(Plugin::validate_config("unknown_key: true\n").is_err());
+}
+
+#[test]
+fn delivers_secrets_as_files() {
+    use env_logger;
+    let _ = env_logger::init();
+    let proj = Project::from_example("rails_hello").unwrap();
+    let config = r#"
+common:
+  GLOBAL_PASSWORD: "magic"
+pods:
+  frontend:
+    web:
+      API_TOKEN: "token"
+files:
+  secrets: ["API_TOKEN"]
+"#;
+    let plugin = Plugin { config: Mutex::new(Some(serde_yaml::from_str(config).unwrap())) };
+
+    let target = proj.current_target();
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(target).unwrap();
+    plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    let web = file.services.get("web").unwrap();
+
+    // `API_TOKEN` should be delivered as a file at the default path.
+    assert!(web.environment.get("API_TOKEN").is_none());
+    assert_eq!(web.environment.get("API_TOKEN_FILE").expect("has API_TOKEN_FILE"),
+               "/run/secrets/API_TOKEN");
+    let secret_path = proj.output_dir()
+        .join("secrets")
+        .join("frontend")
+        .join("web")
+        .join("API_TOKEN");
+    let mut contents = String::new();
+    fs::File::open(&secret_path).unwrap().read_to_string(&mut contents).unwrap();
+    assert_eq!(contents, "token");
+    let mount = web.volumes.last().expect("has secrets volume").value().unwrap();
+    assert_eq!(mount.container, Path::new("/run/secrets"));
+
+    // `GLOBAL_PASSWORD` should still be an environment variable.
+    assert_eq!(web.environment.get("GLOBAL_PASSWORD").expect("has GLOBAL_PASSWORD"),
+               "magic");
+
+    proj.remove_test_output().unwrap();
+}
@@ -19,6 +19,29 @@ struct TargetSecrets {
     pods: BTreeMap<String, PodSecrets>,
 }
 
+/// The default location at which to mount secret files.
+fn default_mount_path() -> String {
+    "/run/secrets".to_owned()
+}
+
+/// Which secrets should we deliver as files instead of environment
+/// variables?
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilesConfig {
+    /// The directory inside each container where we mount our secret
+    /// files.
+    #[serde(default = "default_mount_path")]
+    mount_path: String,
+    /// Secrets which should always be delivered as files.
+    #[serde(default)]
+    secrets: Vec<String>,
+    /// Services, specified as `pod/service`, whose secrets should all be
+    /// delivered as files.
+    #[serde(default)]
+    services: Vec<String>,
+}
+
 /// The deserialized form of `secrets.yml`.  This is basically
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -32,6 +55,39 @@ struct Config {
     /// Secrets for each of our targets.
     #[serde(default)]
     targets: BTreeMap<String, TargetSecrets>,
+    /// Secrets which should be delivered as files.
+    #[serde(default)]
+    files: Option<FilesConfig>,
+}
+
+impl Config {
+    /// Collect all the secrets for `service_name` in `pod_name`, when
+    /// running in `target_name`.
+    fn secrets_for(&self, target_name: &str, pod_name: &str, service_name: &str) -> ServiceSecrets {
+        let append_service = |secrets: &mut ServiceSecrets, pods: &BTreeMap<_, PodSecrets>| {
+            let opt_env = pods.get(pod_name).and_then(|p| p.get(service_name));
+            if let Some(env) = opt_env {
+                secrets.append(&mut env.clone());
+            }
+        };
+
+        let mut secrets = self.common.clone();
+        append_service(&mut secrets, &self.pods);
+        if let Some(target) = self.targets.get(target_name) {
+            secrets.append(&mut target.common.clone());
+            append_service(&mut secrets, &target.pods);
+        }
+        secrets
+    }
+
+    /// Should we deliver the secret `var` to `service_name` in `pod_name`
+    /// as a file?
+    fn is_delivered_as_file(&self, pod_name: &str, service_name: &str, var: &str) -> bool {
+        self.files.as_ref().map_or(false, |files| {
+            files.secrets.iter().any(|s| s == var) ||
+            files.services.iter().any(|s| *s == format!("{}/{}", pod_name, service_name))
+        })
+    }
 }
 
 #[test]
@@ -0,0 +1,211 @@
+//! Plugin which attaches all our services to a project-wide network, so
+//! that services in different pods can reach each other.
+
+use compose_yml::v2 as dc;
+use std::ffi::OsStr;
+
+use args;
+use command_runner::{Command, DynCommandRunner};
+use errors::*;
+use plugins;
+use plugins::{LifecycleEvent, Operation, PluginLifecycle, PluginNew, PluginTransform};
+use project::{PodOrService, Project};
+use target::Target;
+
+/// Declares an external network shared by every pod in the project,
+/// attaches each service to it under the alias `$POD-$SERVICE`, and
+/// creates the network before we start any containers.  Pods with
+/// `isolated: true` in their metadata are left alone.
+#[derive(Debug)]
+pub struct Plugin;
+
+impl Plugin {
+    /// The name of the network shared by all the pods in `project` when
+    /// running in `target`.
+    pub fn network_name(project: &Project, target: &Target) -> String {
+        format!("{}_shared", target.compose_project_name(project))
+    }
+
+    /// Do any of the pods affected by `act_on` use our shared network?
+    fn is_needed_by(project: &Project, act_on: &args::ActOn) -> Result<bool> {
+        let target = project.current_target();
+        for pod_or_service in act_on.pods_or_services(project) {
+            let pod = match try!(pod_or_service) {
+                PodOrService::Pod(pod) |
+                PodOrService::Service(pod, _) => pod,
+            };
+            if pod.enabled_in(target) && !pod.is_isolated() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl plugins::Plugin for Plugin {
+    fn name(&self) -> &str {
+        Self::plugin_name()
+    }
+}
+
+impl PluginNew for Plugin {
+    fn plugin_name() -> &'static str {
+        "shared_network"
+    }
+
+    fn new(_project: &Project) -> Result<Self> {
+        Ok(Plugin)
+    }
+}
+
+impl PluginTransform for Plugin {
+    fn transform(&self,
+                 op: Operation,
+                 ctx: &plugins::Context,
+                 file: &mut dc::File)
+                 -> Result<()> {
+        // We only create our network before running `docker-compose`
+        // ourselves, so don't refer to it from exported files.
+        if op != Operation::Output || ctx.pod.is_isolated() {
+            return Ok(());
+        }
+        let network = Self::network_name(ctx.project, ctx.project.current_target());
+
+        let mut shared = dc::Network::default();
+        shared.external = Some(Default::default());
+        file.networks.insert(network.clone(), shared);
+
+        for (name, service) in &mut file.services {
+            // Services using `network_mode` can't be attached to networks.
+            if service.network_mode.is_some() {
+                continue;
+            }
+
+            // If a service doesn't list any networks, `docker-compose`
+            // attaches it to `default`, so we need to keep it there
+            // explicitly.
+            if service.networks.is_empty() {
+                service.networks.insert("default".to_owned(), Default::default());
+            }
+            let mut interface = dc::NetworkInterface::default();
+            interface.aliases.push(dc::value(format!("{}-{}", ctx.pod.name(), name)));
+            service.networks.insert(network.clone(), interface);
+        }
+        Ok(())
+    }
+}
+
+impl PluginLifecycle for Plugin {
+    fn before(&self,
+              event: LifecycleEvent,
+              project: &Project,
+              act_on: &args::ActOn,
+              runner: &DynCommandRunner)
+              -> Result<()> {
+        match event {
+            LifecycleEvent::Up |
+            LifecycleEvent::Run |
+            LifecycleEvent::Test => {}
+            _ => return Ok(()),
+        }
+        if !try!(Self::is_needed_by(project, act_on)) {
+            return Ok(());
+        }
+
+        // Check for our network quietly, and only create it if `docker`
+        // tells us that it's missing.
+        let network = Self::network_name(project, project.current_target());
+        let output = try!(runner.build_dyn(OsStr::new("docker"))
+            .args(&["network", "inspect", "--format", "{{.Name}}", &network])
+            .output());
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !is_missing_network_error(&stderr, &network) {
+            return Err(err!("could not inspect network {}: {}", network, stderr.trim()));
+        }
+        try!(runner.build_dyn(OsStr::new("docker"))
+            .args(&["network", "create", &network])
+            .exec());
+        Ok(())
+    }
+}
+
+/// Does `stderr` from `docker network inspect` say that `network`
+/// doesn't exist?  Older versions of `docker` say "No such network", and
+/// newer ones say "network $NAME not found".
+fn is_missing_network_error(stderr: &str, network: &str) -> bool {
+    stderr.contains("No such network") ||
+    stderr.contains(&format!("network {} not found", network))
+}
+
+#[test]
+fn attaches_services_to_shared_network() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    Plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+
+    let network = file.networks.get("rails_hello_shared").expect("has shared network");
+    assert!(network.external.is_some());
+    let web_networks = &file.services.get("web").unwrap().networks;
+    assert!(web_networks.contains_key("default"));
+    let aliases = &web_networks.get("rails_hello_shared").expect("attached").aliases;
+    assert_eq!(aliases, &vec![dc::value("frontend-web".to_owned())]);
+
+    // Exported files shouldn't refer to our network.
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    Plugin.transform(Operation::Export, &ctx, &mut file).unwrap();
+    assert!(!file.services.get("web").unwrap().networks.contains_key("rails_hello_shared"));
+}
+
+#[test]
+fn leaves_isolated_pods_alone() {
+    use command_runner::TestCommandRunner;
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("node_hello").unwrap();
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    Plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    assert!(!file.networks.contains_key("node_hello_shared"));
+
+    // We don't need to create a network for isolated pods, either.
+    let runner = TestCommandRunner::new();
+    Plugin.before(LifecycleEvent::Up, &proj, &args::ActOn::All, &runner).unwrap();
+    assert!(runner.cmds().is_empty());
+}
+
+#[test]
+fn recognizes_missing_network_errors() {
+    let network = "rails_hello_shared";
+    assert!(is_missing_network_error("Error: No such network: rails_hello_shared\n",
+                                     network));
+    assert!(is_missing_network_error("Error response from daemon: network \
+                                      rails_hello_shared not found\n",
+                                     network));
+    assert!(!is_missing_network_error("Cannot connect to the Docker daemon.\n", network));
+    assert!(!is_missing_network_error("Error: context \"remote\" not found\n", network));
+}
+
+#[test]
+fn checks_for_shared_network_before_up() {
+    use command_runner::TestCommandRunner;
+    use env_logger;
+    let _ = env_logger::init();
+
+    let proj = Project::from_example("rails_hello").unwrap();
+    let runner = TestCommandRunner::new();
+    Plugin.before(LifecycleEvent::Up, &proj, &args::ActOn::All, &runner).unwrap();
+    Plugin.before(LifecycleEvent::Stop, &proj, &args::ActOn::All, &runner).unwrap();
+    assert_ran!(runner, {
+        ["docker", "network", "inspect", "--format", "{{.Name}}", "rails_hello_shared"]
+    });
+}
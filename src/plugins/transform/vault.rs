@@ -1,17 +1,19 @@
-//! Plugin which issues vault tokens to services.
+//! Plugin which issues vault tokens or AppRole credentials to services.
 
 use compose_yml::v2 as dc;
+use rustc_serialize::json::Json;
 use std::result;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use serde_yaml;
 use std::env;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use std::fs;
 use std::io::{self, Read};
-#[cfg(test)]
-use std::path::Path;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 #[cfg(test)]
 use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use vault;
 use vault::client::VaultDuration;
 
@@ -20,13 +22,124 @@ use plugins;
 use plugins::{Operation, PluginGenerate, PluginNew, PluginTransform};
 use project::Project;
 use serde_helpers::load_yaml;
-use util::err;
+use target::Target;
+use util::{err, write_private_file};
+use vault_client;
 
 #[cfg(feature = "serde_derive")]
 include!(concat!("vault_config.in.rs"));
 #[cfg(feature = "serde_codegen")]
 include!(concat!(env!("OUT_DIR"), "/plugins/transform/vault_config.rs"));
 
+#[cfg(feature = "serde_derive")]
+include!(concat!("vault_tokens.in.rs"));
+#[cfg(feature = "serde_codegen")]
+include!(concat!(env!("OUT_DIR"), "/plugins/transform/vault_tokens.rs"));
+
+impl Config {
+    /// Look up the configuration for a specific service, if any.
+    fn service_config(&self, pod: &str, service: &str) -> Option<&ServiceConfig> {
+        self.pods.get(pod).and_then(|p| p.get(service))
+    }
+
+    /// How should the specified service authenticate itself?
+    fn auth_type_for(&self, pod: &str, service: &str) -> AuthType {
+        self.service_config(pod, service)
+            .and_then(|s| s.auth_type)
+            .unwrap_or(self.auth_type)
+    }
+
+    /// The interpolated names of the policies which apply to the service
+    /// described by `env`.
+    fn policies_for(&self, env: &ConfigEnvironment) -> Result<Vec<String>> {
+        // Get a list of policy "patterns" that apply to this service.
+        let mut raw_policies = self.default_policies.clone();
+        raw_policies.extend(self.service_config(env.pod, env.service)
+            .map_or_else(|| vec![], |s| s.policies.clone()));
+
+        // Interpolate the variables found in our policy patterns.
+        let mut policies = vec![];
+        for result in raw_policies.iter().map(|p| env.interpolate(p)) {
+            // We'd like to use std::result::fold here but it's unstable.
+            policies.push(try!(result));
+        }
+        Ok(policies)
+    }
+}
+
+/// A service which is issued tokens with a given policy.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PolicyUser {
+    /// The target in which the service receives the policy.
+    pub target: String,
+    /// The pod containing the service.
+    pub pod: String,
+    /// The name of the service.
+    pub service: String,
+}
+
+/// Don't try to renew cached tokens which will expire in less than this
+/// many seconds; just issue new ones.
+const MIN_RENEWABLE_SECS: u64 = 60;
+
+/// The current time, in seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Don't include our token in any debugging output.
+impl fmt::Debug for CachedToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "CachedToken {{ policies: {:?}, expires_at: {}, .. }}",
+               self.policies,
+               self.expires_at)
+    }
+}
+
+impl CachedToken {
+    /// The number of seconds until this token expires, or 0 if it has
+    /// already expired.
+    pub fn expires_in(&self) -> u64 {
+        self.expires_at.saturating_sub(now_secs())
+    }
+
+    /// Can we reuse this token as is, without renewing it, for a service
+    /// which wants tokens that last `ttl` seconds?
+    fn is_reusable(&self, ttl: u64) -> bool {
+        self.expires_in() > ttl / 2
+    }
+}
+
+impl TokenCache {
+    /// The path to the token cache for `project`.
+    fn path_for(project: &Project) -> PathBuf {
+        project.output_dir().join("vault_tokens.yml")
+    }
+
+    /// Load the token cache for `project`, or return an empty cache if
+    /// none exists.
+    fn load(project: &Project) -> Result<TokenCache> {
+        let path = Self::path_for(project);
+        if path.exists() {
+            load_yaml(&path)
+        } else {
+            Ok(TokenCache::default())
+        }
+    }
+
+    /// Save this cache to `path`.  Since it contains tokens, only the
+    /// current user may read it.
+    fn save(&self, path: &Path) -> Result<()> {
+        let yaml = try!(serde_yaml::to_string(self)
+            .chain_err(|| ErrorKind::CouldNotWriteFile(path.to_owned())));
+        write_private_file(path, yaml.as_bytes())
+    }
+}
+
 /// Load a vault token from `~/.vault-token`, where the command line client
 /// puts it.
 fn load_vault_token_from_file() -> Result<String> {
@@ -48,29 +161,73 @@ fn find_vault_token() -> Result<String> {
         .or_else(|_| load_vault_token_from_file())
         .map_err(|e| {
             err!("{}.  You probably want to log in using the vault client or set \
-                  VAULT_MASTER_TOKEN",
+                  VAULT_MASTER_TOKEN (or VAULT_ROLE_ID and VAULT_SECRET_ID)",
                  e)
         })
 }
 
+/// The credentials that cage itself uses to talk to vault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MasterCredentials {
+    /// An existing vault token.
+    Token(String),
+    /// AppRole credentials, which we exchange for a token.
+    AppRole {
+        /// The `role_id` of our AppRole.
+        role_id: String,
+        /// A `secret_id` for our AppRole.
+        secret_id: String,
+    },
+}
+
+impl MasterCredentials {
+    /// Find our credentials.  We use `VAULT_ROLE_ID` and
+    /// `VAULT_SECRET_ID` if both are set, and fall back to looking for a
+    /// token.
+    fn from_env() -> Result<MasterCredentials> {
+        match (env::var("VAULT_ROLE_ID"), env::var("VAULT_SECRET_ID")) {
+            (Ok(role_id), Ok(secret_id)) => {
+                Ok(MasterCredentials::AppRole {
+                    role_id: role_id,
+                    secret_id: secret_id,
+                })
+            }
+            _ => Ok(MasterCredentials::Token(try!(find_vault_token()))),
+        }
+    }
+}
+
 /// The "environment" in which to interpret a configuration file.  We don't
 /// want to use the OS environment variables, but rather a fake environment
 /// with a few carefully selected values.
 #[derive(Debug)]
 struct ConfigEnvironment<'a> {
-    /// The context for our transformation, including the project, pod,
-    /// etc.
-    ctx: &'a plugins::Context<'a>,
+    /// The project we're working with.
+    project: &'a Project,
+    /// The target we're interpreting our configuration for.  This is
+    /// normally the project's current target, but may be any target when
+    /// we're expanding policy names.
+    target: &'a Target,
+    /// The name of the current pod.
+    pod: &'a str,
     /// The name of the current service.
     service: &'a str,
 }
 
+impl<'a> ConfigEnvironment<'a> {
+    /// Interpolate a `RawOr<String>` value from our config file.
+    fn interpolate(&self, raw_val: &dc::RawOr<String>) -> Result<String> {
+        let mut val = raw_val.to_owned();
+        Ok(try!(val.interpolate_env(self)).to_owned())
+    }
+}
+
 impl<'a> dc::Environment for ConfigEnvironment<'a> {
     fn var(&self, key: &str) -> result::Result<String, env::VarError> {
         let result = match key {
-            "PROJECT" => Ok(self.ctx.project.name()),
-            "TARGET" => Ok(self.ctx.project.current_target().name()),
-            "POD" => Ok(self.ctx.pod.name()),
+            "PROJECT" => Ok(self.project.name()),
+            "TARGET" => Ok(self.target.name()),
+            "POD" => Ok(self.pod),
             "SERVICE" => Ok(self.service),
             _ => Err(env::VarError::NotPresent),
         };
@@ -78,8 +235,8 @@ impl<'a> dc::Environment for ConfigEnvironment<'a> {
     }
 }
 
-/// An abstract interface to Vault's token-generation capabilities.  We use
-/// this to mock vault during tests.
+/// An abstract interface to Vault's token-generation and secret-reading
+/// capabilities.  We use this to mock vault during tests.
 trait GenerateToken: Debug + Sync {
     /// Get a `VAULT_ADDR` value to use along with this token.
     fn addr(&self) -> &str;
@@ -89,12 +246,33 @@ trait GenerateToken: Debug + Sync {
                       policies: Vec<String>,
                       ttl: VaultDuration)
                       -> Result<String>;
+    /// Look up the `role_id` for the specified AppRole.
+    fn approle_role_id(&self, role: &str) -> Result<String>;
+    /// Generate a new `secret_id` for the specified AppRole.
+    fn generate_approle_secret_id(&self, role: &str, display_name: &str) -> Result<String>;
+    /// Read `field` from the secret stored at `path`.
+    fn read_secret(&self, path: &str, field: &str) -> Result<String>;
+    /// Renew `token` for `ttl` seconds, returning the new lease duration
+    /// in seconds.
+    fn renew_token(&self, token: &str, ttl: u64) -> Result<u64>;
+    /// Revoke `token`.
+    fn revoke_token(&self, token: &str) -> Result<()>;
 }
 
 /// A list of calls made to a `MockVault` instance.
 #[cfg(test)]
 type MockVaultCalls = Arc<RwLock<Vec<(String, Vec<String>, VaultDuration)>>>;
 
+/// A list of AppRole roles and display names for which a `MockVault`
+/// instance generated secret IDs.
+#[cfg(test)]
+type MockVaultAppRoleCalls = Arc<RwLock<Vec<(String, String)>>>;
+
+/// A list of tokens renewed or revoked by a `MockVault` instance, in the
+/// form `"renew TOKEN"` or `"revoke TOKEN"`.
+#[cfg(test)]
+type MockVaultTokenCalls = Arc<RwLock<Vec<String>>>;
+
 /// A fake interface to vault for testing purposes.
 #[derive(Debug)]
 #[cfg(test)]
@@ -103,19 +281,37 @@ struct MockVault {
     /// so that we can have "interior" mutability, because we don't want
     /// `generate_token` to be `&mut self` in the general case.
     calls: MockVaultCalls,
+    /// The AppRole secret IDs we were asked to generate.
+    approle_calls: MockVaultAppRoleCalls,
+    /// The tokens we were asked to renew or revoke.
+    token_calls: MockVaultTokenCalls,
 }
 
 #[cfg(test)]
 impl MockVault {
     /// Create a new MockVault.
     fn new() -> MockVault {
-        MockVault { calls: Arc::new(RwLock::new(vec![])) }
+        MockVault {
+            calls: Arc::new(RwLock::new(vec![])),
+            approle_calls: Arc::new(RwLock::new(vec![])),
+            token_calls: Arc::new(RwLock::new(vec![])),
+        }
     }
 
     /// Return a reference to record of calls made to our vault.
     fn calls(&self) -> MockVaultCalls {
         self.calls.clone()
     }
+
+    /// Return a reference to the record of AppRole secret IDs generated.
+    fn approle_calls(&self) -> MockVaultAppRoleCalls {
+        self.approle_calls.clone()
+    }
+
+    /// Return a reference to the record of tokens renewed or revoked.
+    fn token_calls(&self) -> MockVaultTokenCalls {
+        self.token_calls.clone()
+    }
 }
 
 #[cfg(test)]
@@ -132,24 +328,63 @@ impl GenerateToken for MockVault {
         self.calls.write().unwrap().push((display_name.to_owned(), policies, ttl));
         Ok("fake_token".to_owned())
     }
+
+    fn approle_role_id(&self, role: &str) -> Result<String> {
+        Ok(format!("fake_role_id_for_{}", role))
+    }
+
+    fn generate_approle_secret_id(&self, role: &str, display_name: &str) -> Result<String> {
+        self.approle_calls
+            .write()
+            .unwrap()
+            .push((role.to_owned(), display_name.to_owned()));
+        Ok("fake_secret_id".to_owned())
+    }
+
+    fn read_secret(&self, path: &str, field: &str) -> Result<String> {
+        Ok(format!("fake_secret_from_{}:{}", path, field))
+    }
+
+    fn renew_token(&self, token: &str, ttl: u64) -> Result<u64> {
+        self.token_calls.write().unwrap().push(format!("renew {}", token));
+        Ok(ttl)
+    }
+
+    fn revoke_token(&self, token: &str) -> Result<()> {
+        self.token_calls.write().unwrap().push(format!("revoke {}", token));
+        Ok(())
+    }
 }
 
-/// An interface to an actual vault server.
+/// An interface to an actual vault server.  We don't log in until we
+/// first need to talk to Vault, so commands which never issue credentials
+/// don't create login tokens.
 #[derive(Debug)]
 struct Vault {
     /// The address of our vault server.
     addr: String,
-    /// The master token that we'll use to issue new tokens.
-    token: String,
+    /// The credentials we'll log in with, or `None` to look them up using
+    /// `MasterCredentials::from_env` when we log in.
+    creds: Option<MasterCredentials>,
+    /// The master token that we'll use to issue new tokens, once we've
+    /// logged in, and whether we created it by logging in (in which case
+    /// we revoke it when we're done).
+    login: Mutex<Option<(String, bool)>>,
 }
 
 impl Vault {
     /// Create a new vault client.
     fn new() -> Result<Vault> {
-        let mut addr = try!(env::var("VAULT_ADDR").map_err(|_| {
+        let addr = try!(env::var("VAULT_ADDR").map_err(|_| {
             err("Please set the environment variable VAULT_ADDR to the URL of \
                  your vault server")
         }));
+        Ok(Vault::with_credentials(addr, None))
+    }
+
+    /// Create a new vault client for the server at `addr`, which will log
+    /// in with `creds` when first needed.
+    fn with_credentials(mut addr: String, creds: Option<MasterCredentials>) -> Vault {
         // TODO MED: Temporary fix because of broken URL handling in
         // hashicorp_vault.  Upstream bug:
         // https://github.com/ChrisMacNaughton/vault-rs/issues/14
@@ -157,11 +392,55 @@ impl Vault {
             let new_len = addr.len() - 1;
             addr.truncate(new_len);
         }
-        let token = try!(find_vault_token());
-        Ok(Vault {
+        Vault {
             addr: addr,
-            token: token,
-        })
+            creds: creds,
+            login: Mutex::new(None),
+        }
+    }
+
+    /// Get our master token, logging in if we haven't already.
+    fn token(&self) -> Result<String> {
+        let mut login = self.login.lock().expect("vault login lock poisoned");
+        if let Some((ref token, _)) = *login {
+            return Ok(token.clone());
+        }
+        let creds = match self.creds {
+            Some(ref creds) => creds.clone(),
+            None => try!(MasterCredentials::from_env()),
+        };
+        let (token, logged_in) = match creds {
+            MasterCredentials::Token(token) => (token, false),
+            MasterCredentials::AppRole { role_id, secret_id } => {
+                debug!("Logging in to Vault using AppRole");
+                let client = try!(vault_client::Client::approle_login(&self.addr,
+                                                                      &role_id,
+                                                                      &secret_id));
+                let token = client.token().expect("login should always return a token");
+                (token.to_owned(), true)
+            }
+        };
+        *login = Some((token.clone(), logged_in));
+        Ok(token)
+    }
+
+    /// Create a client for the parts of the vault API which aren't
+    /// supported by `hashicorp_vault`.
+    fn client(&self) -> Result<vault_client::Client> {
+        Ok(vault_client::Client::new(self.addr.clone(), Some(try!(self.token()))))
+    }
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        // Nobody else knows about a token we got by logging in, so clean
+        // it up instead of leaving it around until it expires.
+        if let Ok(&mut Some((ref token, true))) = self.login.get_mut() {
+            let client = vault_client::Client::new(self.addr.clone(), Some(token.clone()));
+            if let Err(e) = client.revoke_self() {
+                warn!("Could not revoke our Vault login token: {}", e);
+            }
+        }
     }
 }
 
@@ -182,8 +461,8 @@ impl GenerateToken for Vault {
         // probably not the worst idea, because it uses `hyper` for HTTP,
         // and `hyper` HTTP connections used to have expiration issues that
         // were tricky for clients to deal with correctly.
-        let client = try!(vault::Client::new(&self.addr, &self.token)
-            .chain_err(&mkerr));
+        let token = try!(self.token());
+        let client = try!(vault::Client::new(&self.addr, &token).chain_err(&mkerr));
         let opts = vault::client::TokenOptions::default()
             .display_name(display_name)
             .renewable(true)
@@ -192,9 +471,53 @@ impl GenerateToken for Vault {
         let auth = try!(client.create_token(&opts).chain_err(&mkerr));
         Ok(auth.client_token)
     }
+
+    fn approle_role_id(&self, role: &str) -> Result<String> {
+        try!(self.client()).approle_role_id(role)
+    }
+
+    fn generate_approle_secret_id(&self, role: &str, display_name: &str) -> Result<String> {
+        let mut metadata = BTreeMap::new();
+        metadata.insert("display_name".to_owned(), display_name.to_owned());
+        try!(self.client()).approle_generate_secret_id(role, &metadata)
+    }
+
+    fn read_secret(&self, path: &str, field: &str) -> Result<String> {
+        let secret = try!(try!(self.client()).read_secret(path));
+        match secret.find(field) {
+            Some(&Json::String(ref value)) => Ok(value.to_owned()),
+            // Allow numbers and booleans, too, because they're easy to
+            // write by accident.
+            Some(&Json::I64(value)) => Ok(value.to_string()),
+            Some(&Json::U64(value)) => Ok(value.to_string()),
+            Some(&Json::F64(value)) => Ok(value.to_string()),
+            Some(&Json::Boolean(value)) => Ok(value.to_string()),
+            Some(_) => Err(err!("field {} of Vault secret {} is not a string", field, path)),
+            None => Err(err!("Vault secret {} has no field {}", path, field)),
+        }
+    }
+
+    fn renew_token(&self, token: &str, ttl: u64) -> Result<u64> {
+        try!(self.client()).renew_token(token, ttl)
+    }
+
+    fn revoke_token(&self, token: &str) -> Result<()> {
+        try!(self.client()).revoke_token(token)
+    }
 }
 
-/// Issues `VAULT_TOKEN` values to services.
+/// Split a secret reference of the form `path:field` into a path and a
+/// field name.  If no field is specified, we use `value`, which is what
+/// the `vault write` command uses by default.
+fn parse_secret_ref(secret_ref: &str) -> (&str, &str) {
+    match secret_ref.rfind(':') {
+        Some(pos) => (&secret_ref[..pos], &secret_ref[pos + 1..]),
+        None => (secret_ref, "value"),
+    }
+}
+
+/// Issues `VAULT_TOKEN` or AppRole credentials to services, and reads
+/// secrets from Vault into their environments.
 #[derive(Debug)]
 pub struct Plugin {
     /// Our `config/vault.yml` YAML file, parsed and read into memory.
@@ -203,6 +526,11 @@ pub struct Plugin {
     config: Option<Config>,
     /// Our source of tokens.
     generator: Option<Box<GenerateToken>>,
+    /// Tokens we've already issued, which we'll try to reuse.  This is
+    /// only loaded if we have a config file.
+    tokens: Mutex<TokenCache>,
+    /// The path to which we'll save `tokens` when they change.
+    tokens_path: PathBuf,
 }
 
 impl Plugin {
@@ -216,20 +544,192 @@ impl Plugin {
         where G: GenerateToken + 'static
     {
         let path = Self::config_path(project);
-        let config = if path.exists() {
-            Some(try!(load_yaml(&path)))
+        let (config, tokens) = if path.exists() {
+            (Some(try!(load_yaml(&path))), try!(TokenCache::load(project)))
         } else {
-            None
+            (None, TokenCache::default())
         };
         Ok(Plugin {
             config: config,
             generator: generator.map(|gen: G| -> Box<GenerateToken> { Box::new(gen) }),
+            tokens: Mutex::new(tokens),
+            tokens_path: TokenCache::path_for(project),
         })
     }
+
+    /// The names of the environment variables which this plugin adds to
+    /// `service_name` in `pod_name` in the current target.  Exporters
+    /// should treat all of these as secrets.
+    pub fn secret_vars(project: &Project,
+                       pod_name: &str,
+                       service_name: &str)
+                       -> Result<BTreeSet<String>> {
+        let path = Self::config_path(project);
+        if project.config().plugins.is_disabled(Self::plugin_name()) || !path.exists() {
+            return Ok(BTreeSet::new());
+        }
+        let config: Config = try!(load_yaml(&path));
+        if !project.current_target().is_enabled_by(&config.enable_in_targets) {
+            return Ok(BTreeSet::new());
+        }
+
+        let mut vars = BTreeSet::new();
+        vars.insert("VAULT_ADDR".to_owned());
+        match config.auth_type_for(pod_name, service_name) {
+            AuthType::Token => {
+                vars.insert("VAULT_TOKEN".to_owned());
+            }
+            AuthType::AppRole => {
+                vars.insert("VAULT_ROLE_ID".to_owned());
+                vars.insert("VAULT_SECRET_ID".to_owned());
+            }
+        }
+        vars.extend(config.default_secrets.keys().cloned());
+        if let Some(service_config) = config.service_config(pod_name, service_name) {
+            vars.extend(service_config.secrets.keys().cloned());
+        }
+        vars.extend(config.extra_environment.keys().cloned());
+        Ok(vars)
+    }
+
+    /// Find all the policies that we might attach to tokens, in every
+    /// target where this plugin is enabled, and the services which use
+    /// each policy.  Services using AppRole authentication are skipped,
+    /// because their policies are attached to their roles by Vault.
+    pub fn policy_users(project: &Project) -> Result<BTreeMap<String, Vec<PolicyUser>>> {
+        let path = Self::config_path(project);
+        if !path.exists() {
+            return Err(err!("Cannot find {}", path.display()));
+        }
+        let config: Config = try!(load_yaml(&path));
+
+        let mut users = BTreeMap::new();
+        for target in project.targets() {
+            if !target.is_enabled_by(&config.enable_in_targets) {
+                continue;
+            }
+            for pod in project.pods().filter(|pod| pod.enabled_in(target)) {
+                for service in pod.service_names() {
+                    if config.auth_type_for(pod.name(), service) != AuthType::Token {
+                        continue;
+                    }
+                    let env = ConfigEnvironment {
+                        project: project,
+                        target: target,
+                        pod: pod.name(),
+                        service: service,
+                    };
+                    for policy in try!(config.policies_for(&env)) {
+                        users.entry(policy).or_insert_with(Vec::new).push(PolicyUser {
+                            target: target.name().to_owned(),
+                            pod: pod.name().to_owned(),
+                            service: service.to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(users)
+    }
+
+    /// The tokens we've issued to services in `project` and cached for
+    /// reuse, indexed by display name.
+    pub fn cached_tokens(project: &Project) -> Result<BTreeMap<String, CachedToken>> {
+        Ok(try!(TokenCache::load(project)).tokens)
+    }
+
+    /// Revoke all the tokens we've cached for `project`, and remove them
+    /// from our cache.  Returns the revoked tokens.
+    pub fn revoke_cached_tokens(project: &Project) -> Result<BTreeMap<String, CachedToken>> {
+        Self::revoke_cached_tokens_with(project, &try!(Vault::new()))
+    }
+
+    /// Revoke all the tokens we've cached for `project` using
+    /// `generator`.  Expired tokens are dropped without asking Vault, and
+    /// tokens which we can't revoke are left in the cache with a warning.
+    fn revoke_cached_tokens_with(project: &Project,
+                                 generator: &GenerateToken)
+                                 -> Result<BTreeMap<String, CachedToken>> {
+        let mut cache = try!(TokenCache::load(project));
+        let mut revoked = BTreeMap::new();
+        let names: Vec<String> = cache.tokens.keys().cloned().collect();
+        for name in names {
+            let cached = cache.tokens[&name].clone();
+            if cached.expires_in() == 0 {
+                debug!("Dropping expired token for '{}'", name);
+            } else if let Err(e) = generator.revoke_token(&cached.token) {
+                warn!("Could not revoke token for '{}': {}", name, e);
+                continue;
+            } else {
+                revoked.insert(name.clone(), cached);
+            }
+            // Save our cache after each revocation, so that we don't lose
+            // track of any tokens if we fail part way through.
+            cache.tokens.remove(&name);
+            try!(cache.save(&TokenCache::path_for(project)));
+        }
+        Ok(revoked)
+    }
+
+    /// Get a token with `display_name` and `policies`, reusing or
+    /// renewing a cached token if possible, and issuing a new one if
+    /// not.
+    fn token_for(&self,
+                 generator: &GenerateToken,
+                 display_name: &str,
+                 policies: Vec<String>,
+                 ttl: u64)
+                 -> Result<String> {
+        let mut cache = self.tokens.lock().expect("token cache lock poisoned");
+        let now = now_secs();
+
+        if let Some(cached) = cache.tokens.get(display_name).cloned() {
+            if cached.policies != policies {
+                // Our policies have changed, so clean up the old token.
+                debug!("Revoking token for '{}' because policies changed",
+                       display_name);
+                if let Err(e) = generator.revoke_token(&cached.token) {
+                    warn!("Could not revoke old token for '{}': {}", display_name, e);
+                }
+            } else if cached.is_reusable(ttl) {
+                debug!("Reusing cached token for '{}'", display_name);
+                return Ok(cached.token);
+            } else if cached.expires_in() > MIN_RENEWABLE_SECS {
+                debug!("Renewing cached token for '{}'", display_name);
+                match generator.renew_token(&cached.token, ttl) {
+                    Ok(lease) => {
+                        let token = cached.token.clone();
+                        cache.tokens.insert(display_name.to_owned(),
+                                            CachedToken { expires_at: now + lease, ..cached });
+                        try!(cache.save(&self.tokens_path));
+                        return Ok(token);
+                    }
+                    Err(e) => {
+                        warn!("Could not renew token for '{}': {}", display_name, e);
+                    }
+                }
+            }
+        }
+
+        debug!("Generating token for '{}' with policies {:?}",
+               display_name,
+               &policies);
+        let token = try!(generator.generate_token(display_name,
+                                                  policies.clone(),
+                                                  VaultDuration::seconds(ttl)));
+        cache.tokens.insert(display_name.to_owned(),
+                            CachedToken {
+                                token: token.clone(),
+                                policies: policies,
+                                expires_at: now + ttl,
+                            });
+        try!(cache.save(&self.tokens_path));
+        Ok(token)
+    }
 }
 
 impl plugins::Plugin for Plugin {
-    fn name(&self) -> &'static str {
+    fn name(&self) -> &str {
         Self::plugin_name()
     }
 }
@@ -247,7 +747,8 @@ impl PluginNew for Plugin {
     fn new(project: &Project) -> Result<Self> {
         // An annoying special case.  We may be called as a code generator,
         // in which case we don't want to try to create a `GenerateToken`
-        // instance.
+        // instance.  Even when we do, it won't talk to Vault until we need
+        // it to.
         let token_gen = if try!(Self::is_configured_for(project)) {
             Some(try!(Vault::new()))
         } else {
@@ -258,14 +759,20 @@ impl PluginNew for Plugin {
 }
 
 impl PluginGenerate for Plugin {
-    fn generator_description(&self) -> &'static str {
+    fn generator_description(&self) -> &str {
         "Get passwords & other secrets from a Vault server"
     }
 }
 
 impl PluginTransform for Plugin {
+    fn is_enabled_in(&self, target: &Target) -> bool {
+        self.config
+            .as_ref()
+            .map_or(false, |config| target.is_enabled_by(&config.enable_in_targets))
+    }
+
     fn transform(&self,
-                 _op: Operation,
+                 op: Operation,
                  ctx: &plugins::Context,
                  file: &mut dc::File)
                  -> Result<()> {
@@ -289,48 +796,94 @@ impl PluginTransform for Plugin {
             // Set up a ConfigEnvironment that we can use to perform
             // interpolations of values like `$SERVICE` in our config file.
             let env = ConfigEnvironment {
-                ctx: ctx,
+                project: ctx.project,
+                target: target,
+                pod: ctx.pod.name(),
                 service: name,
             };
 
             // Define a local helper function to interpolate
             // `RawOr<String>` values using `env`.
-            let interpolated = |raw_val: &dc::RawOr<String>| -> Result<String> {
-                let mut val = raw_val.to_owned();
-                Ok(try!(val.interpolate_env(&env)).to_owned())
-            };
+            let interpolated = |raw_val: &dc::RawOr<String>| env.interpolate(raw_val);
 
             // Insert our VAULT_ADDR value into the generated files.
             service.environment
                 .insert("VAULT_ADDR".to_owned(), generator.addr().to_owned());
 
-            // Get a list of policy "patterns" that apply to this service.
-            let mut raw_policies = config.default_policies.clone();
-            raw_policies.extend(config.pods
-                .get(ctx.pod.name())
-                .and_then(|pod| pod.get(name))
-                .map_or_else(|| vec![], |s| s.policies.clone()));
-
-            // Interpolate the variables found in our policy patterns.
-            let mut policies = vec![];
-            for result in raw_policies.iter().map(|p| interpolated(p)) {
-                // We'd like to use std::result::fold here but it's unstable.
-                policies.push(try!(result));
-            }
-            debug!("Generating token for '{}' with policies {:?}",
-                   name,
-                   &policies);
-
-            // Generate a VAULT_TOKEN.
+            // Look up any service-specific configuration.
+            let service_config = config.service_config(ctx.pod.name(), name);
             let display_name = format!("{}_{}_{}_{}",
                                        ctx.project.name(),
                                        ctx.project.current_target().name(),
                                        ctx.pod.name(),
                                        name);
-            let ttl = VaultDuration::seconds(config.default_ttl);
-            let token = try!(generator.generate_token(&display_name, policies, ttl)
-                .chain_err(|| format!("could not generate token for '{}'", name)));
-            service.environment.insert("VAULT_TOKEN".to_owned(), token);
+
+            match config.auth_type_for(ctx.pod.name(), name) {
+                AuthType::Token => {
+                    let policies = try!(config.policies_for(&env));
+
+                    // Get a VAULT_TOKEN.  Exported files run elsewhere, so
+                    // they always get new tokens, which we don't cache.
+                    // Otherwise, reuse an existing token if we can.
+                    let token = if op == Operation::Export {
+                        generator.generate_token(&display_name,
+                                                 policies,
+                                                 VaultDuration::seconds(config.default_ttl))
+                    } else {
+                        self.token_for(&**generator,
+                                       &display_name,
+                                       policies,
+                                       config.default_ttl)
+                    };
+                    let token = try!(token.chain_err(|| {
+                        format!("could not generate token for '{}'", name)
+                    }));
+                    service.environment.insert("VAULT_TOKEN".to_owned(), token);
+                }
+                AuthType::AppRole => {
+                    // Figure out which role to use.
+                    let raw_role = service_config.and_then(|s| s.approle_role.as_ref())
+                        .or_else(|| config.default_approle_role.as_ref());
+                    let role = match raw_role {
+                        Some(raw_role) => try!(interpolated(raw_role)),
+                        None => {
+                            format!("{}-{}-{}-{}",
+                                    ctx.project.name(),
+                                    ctx.project.current_target().name(),
+                                    ctx.pod.name(),
+                                    name)
+                        }
+                    };
+                    debug!("Generating AppRole credentials for '{}' with role {}",
+                           name,
+                           &role);
+
+                    // Generate VAULT_ROLE_ID and VAULT_SECRET_ID.
+                    let mkerr = || {
+                        format!("could not generate AppRole credentials for '{}'", name)
+                    };
+                    let role_id = try!(generator.approle_role_id(&role).chain_err(&mkerr));
+                    let secret_id =
+                        try!(generator.generate_approle_secret_id(&role, &display_name)
+                            .chain_err(&mkerr));
+                    service.environment.insert("VAULT_ROLE_ID".to_owned(), role_id);
+                    service.environment.insert("VAULT_SECRET_ID".to_owned(), secret_id);
+                }
+            }
+
+            // Read any secrets from Vault's KV store.
+            let mut secrets = config.default_secrets.clone();
+            if let Some(service_config) = service_config {
+                secrets.extend(service_config.secrets.clone());
+            }
+            for (var, raw_ref) in &secrets {
+                let secret_ref = try!(interpolated(raw_ref));
+                let (path, field) = parse_secret_ref(&secret_ref);
+                debug!("Reading {} for '{}' from {}:{}", var, name, path, field);
+                let value = try!(generator.read_secret(path, field)
+                    .chain_err(|| format!("could not read {} for '{}'", var, name)));
+                service.environment.insert(var.to_owned(), value);
+            }
 
             // Add in any extra environment variables.
             for (var, val) in &config.extra_environment {
@@ -377,6 +930,190 @@ fn interpolates_policies() {
                  "vault_integration-production-frontend-web".to_owned(),
                  "vault_integration-production-ssl".to_owned()]);
     assert_eq!(ttl, &VaultDuration::seconds(2592000));
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn reuses_and_renews_cached_tokens() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let display_name = "vault_integration_production_frontend_web";
+
+    // Generate a token, and make sure it gets reused.
+    let vault = MockVault::new();
+    let calls = vault.calls();
+    let token_calls = vault.token_calls();
+    let plugin = Plugin::new_with_generator(&proj, Some(vault)).unwrap();
+    for _ in 0..2 {
+        let mut file = frontend.merged_file(proj.current_target()).unwrap();
+        plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    }
+    assert_eq!(calls.read().unwrap().len(), 1);
+    let cached = Plugin::cached_tokens(&proj).unwrap();
+    assert_eq!(cached[display_name].token, "fake_token");
+
+    // When a token is close to expiring, renew it instead of issuing a
+    // new one.
+    plugin.tokens
+        .lock()
+        .unwrap()
+        .tokens
+        .get_mut(display_name)
+        .unwrap()
+        .expires_at = now_secs() + 3600;
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    assert_eq!(calls.read().unwrap().len(), 1);
+    assert_eq!(&token_calls.read().unwrap()[..], &["renew fake_token".to_owned()]);
+    let cached = Plugin::cached_tokens(&proj).unwrap();
+    assert!(cached[display_name].expires_at > now_secs() + 3600);
+
+    // Revoking our tokens should empty the cache, without bothering to
+    // revoke tokens which have already expired.
+    {
+        let mut cache = TokenCache::load(&proj).unwrap();
+        cache.tokens.insert("vault_integration_production_frontend_old".to_owned(),
+                            CachedToken {
+                                token: "expired_token".to_owned(),
+                                policies: vec![],
+                                expires_at: now_secs() - 60,
+                            });
+        cache.save(&TokenCache::path_for(&proj)).unwrap();
+    }
+    let vault = MockVault::new();
+    let token_calls = vault.token_calls();
+    let revoked = Plugin::revoke_cached_tokens_with(&proj, &vault).unwrap();
+    assert_eq!(revoked.len(), 1);
+    assert_eq!(&token_calls.read().unwrap()[..], &["revoke fake_token".to_owned()]);
+    assert!(Plugin::cached_tokens(&proj).unwrap().is_empty());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn never_caches_exported_tokens() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+
+    let vault = MockVault::new();
+    let calls = vault.calls();
+    let plugin = Plugin::new_with_generator(&proj, Some(vault)).unwrap();
+    for op in &[Operation::Output, Operation::Export, Operation::Export] {
+        let mut file = frontend.merged_file(proj.current_target()).unwrap();
+        plugin.transform(*op, &ctx, &mut file).unwrap();
+    }
+    assert_eq!(calls.read().unwrap().len(), 3);
+    assert_eq!(Plugin::cached_tokens(&proj).unwrap().len(), 1);
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn reads_secrets_into_environment() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    proj.set_current_target_name("production").unwrap();
+
+    let vault = MockVault::new();
+    let plugin = Plugin::new_with_generator(&proj, Some(vault)).unwrap();
+
+    let frontend = proj.pod("frontend").unwrap();
+    let ctx = plugins::Context::new(&proj, frontend);
+    let mut file = frontend.merged_file(proj.current_target()).unwrap();
+    plugin.transform(Operation::Export, &ctx, &mut file).unwrap();
+    let web = file.services.get("web").unwrap();
+    assert_eq!(web.environment.get("DATABASE_PASSWORD").expect("has DATABASE_PASSWORD"),
+               "fake_secret_from_secret/vault_integration/production/database:password");
+    assert_eq!(web.environment.get("SSL_KEY").expect("has SSL_KEY"),
+               "fake_secret_from_secret/vault_integration/production/frontend/ssl:value");
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn secret_refs_may_omit_field() {
+    assert_eq!(parse_secret_ref("secret/db:password"),
+               ("secret/db", "password"));
+    assert_eq!(parse_secret_ref("secret/db"), ("secret/db", "value"));
+}
+
+#[test]
+fn issues_approle_credentials_to_selected_services() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    proj.set_current_target_name("production").unwrap();
+
+    let vault = MockVault::new();
+    let calls = vault.calls();
+    let approle_calls = vault.approle_calls();
+    let plugin = Plugin::new_with_generator(&proj, Some(vault)).unwrap();
+
+    let rake = proj.pod("rake").unwrap();
+    let ctx = plugins::Context::new(&proj, rake);
+    let mut file = rake.merged_file(proj.current_target()).unwrap();
+    plugin.transform(Operation::Output, &ctx, &mut file).unwrap();
+    let service = file.services.get("rake").unwrap();
+    assert_eq!(service.environment.get("VAULT_ADDR").expect("has VAULT_ADDR"),
+               "http://example.com:8200/");
+    assert_eq!(service.environment.get("VAULT_TOKEN"), None);
+    assert_eq!(service.environment.get("VAULT_ROLE_ID").expect("has VAULT_ROLE_ID"),
+               "fake_role_id_for_vault_integration-production-migrations");
+    assert_eq!(service.environment.get("VAULT_SECRET_ID").expect("has VAULT_SECRET_ID"),
+               "fake_secret_id");
+    assert_eq!(service.environment.get("VAULT_ENV").expect("has VAULT_ENV"),
+               "production");
+
+    assert_eq!(calls.read().unwrap().len(), 0);
+    let approle_calls = approle_calls.read().unwrap();
+    assert_eq!(&approle_calls[..],
+               &[("vault_integration-production-migrations".to_owned(),
+                  "vault_integration_production_rake_rake".to_owned())]);
+}
+
+#[test]
+fn master_credentials_can_use_approle() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    server.respond("POST",
+                   "/v1/auth/approle/login",
+                   200,
+                   r#"{"auth":{"client_token":"ci_token"}}"#);
+    server.respond("POST", "/v1/auth/token/revoke-self", 204, "");
+    let creds = MasterCredentials::AppRole {
+        role_id: "ci_role_id".to_owned(),
+        secret_id: "ci_secret_id".to_owned(),
+    };
+    {
+        // We only log in once, and only when we need a token.
+        let vault = Vault::with_credentials(format!("{}/", server.url()), Some(creds));
+        assert_eq!(vault.addr(), server.url());
+        assert!(server.requests().is_empty());
+        assert_eq!(vault.token().unwrap(), "ci_token");
+        assert_eq!(vault.token().unwrap(), "ci_token");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    // We revoke our login token when we're done with it.
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].path, "/v1/auth/token/revoke-self");
+    assert_eq!(requests[1].headers.get("x-vault-token").unwrap(), "ci_token");
 }
 
 #[test]
@@ -398,3 +1135,22 @@ fn only_applied_in_specified_targets() {
     let web = file.services.get("web").unwrap();
     assert_eq!(web.environment.get("VAULT_ADDR"), None);
 }
+
+#[test]
+fn lists_secret_vars_for_services() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("vault_integration").unwrap();
+    assert!(Plugin::secret_vars(&proj, "frontend", "web").unwrap().is_empty());
+
+    proj.set_current_target_name("production").unwrap();
+    let web: Vec<String> =
+        Plugin::secret_vars(&proj, "frontend", "web").unwrap().into_iter().collect();
+    assert_eq!(web,
+               &["DATABASE_PASSWORD", "SSL_KEY", "VAULT_ADDR", "VAULT_ENV", "VAULT_TOKEN"]);
+    let rake = Plugin::secret_vars(&proj, "rake", "rake").unwrap();
+    assert!(rake.contains("VAULT_ROLE_ID"));
+    assert!(rake.contains("VAULT_SECRET_ID"));
+    assert!(!rake.contains("VAULT_TOKEN"));
+}
@@ -8,6 +8,11 @@ enum AuthType {
     /// appropriate policies on each token.
     #[serde(rename = "token")]
     Token,
+    /// Issue a `VAULT_ROLE_ID` and a freshly generated `VAULT_SECRET_ID`
+    /// to each service, which it can use to log in using Vault's AppRole
+    /// backend.
+    #[serde(rename = "approle")]
+    AppRole,
 }
 
 /// The policies associated with a specific pod.
@@ -17,6 +22,18 @@ struct ServiceConfig {
     /// Policies to apply to this service.
     #[serde(default)]
     policies: Vec<dc::RawOr<String>>,
+
+    /// Override the default `auth_type` for this service.
+    auth_type: Option<AuthType>,
+
+    /// The AppRole to use for this service, if we're using `approle`
+    /// authentication.  Overrides `default_approle_role`.
+    approle_role: Option<dc::RawOr<String>>,
+
+    /// Environment variables to read from Vault's KV store for this
+    /// service, in addition to `default_secrets`.
+    #[serde(default)]
+    secrets: BTreeMap<String, dc::RawOr<String>>,
 }
 
 /// Policies to apply to each service in a pod.
@@ -30,9 +47,14 @@ struct Config {
     /// field is omitted, we apply the plguin in all targets.
     enable_in_targets: Option<Vec<String>>,
 
-    /// The kind of authentication to use.
+    /// The kind of authentication to use, unless overridden for a
+    /// specific service.
     auth_type: AuthType,
 
+    /// The AppRole to use for services with `approle` authentication.
+    /// Defaults to `$PROJECT-$TARGET-$POD-$SERVICE`.
+    default_approle_role: Option<dc::RawOr<String>>,
+
     /// Extra environment variables to inject into each service.
     #[serde(default)]
     extra_environment: BTreeMap<String, dc::RawOr<String>>,
@@ -44,7 +66,13 @@ struct Config {
     #[serde(default)]
     default_policies: Vec<dc::RawOr<String>>,
 
-    /// More specific policies to apply to individual
+    /// Environment variables to read from Vault's KV store for every
+    /// service.  Values have the form `path:field`, where `:field` may be
+    /// omitted to use the `value` field.
+    #[serde(default)]
+    default_secrets: BTreeMap<String, dc::RawOr<String>>,
+
+    /// More specific policies and secrets to apply to individual services.
     #[serde(default)]
     pods: BTreeMap<String, PodConfig>,
 }
@@ -54,4 +82,6 @@ fn can_deserialize_config() {
     let path = Path::new("examples/vault_integration/config/vault.yml");
     let config: Config = load_yaml(&path).unwrap();
     assert_eq!(config.auth_type, AuthType::Token);
+    let rake = &config.pods["rake"]["rake"];
+    assert_eq!(rake.auth_type, Some(AuthType::AppRole));
 }
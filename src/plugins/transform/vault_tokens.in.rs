@@ -0,0 +1,25 @@
+// This is not a standalone Rust module.  It gets processed by serde to
+// generate serialization code and included directly into another module.
+
+/// A Vault token which we've issued to a service, and which we may reuse
+/// the next time we generate output.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedToken {
+    /// The token itself.
+    pub token: String,
+    /// The policies attached to this token.  We only reuse a token if
+    /// these match the policies we'd use for a new token.
+    pub policies: Vec<String>,
+    /// When this token expires, in seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// The contents of our token cache, stored in `.cage/vault_tokens.yml`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenCache {
+    /// Cached tokens, indexed by the display name we used to create them,
+    /// which includes the project, target, pod and service.
+    #[serde(default)]
+    tokens: BTreeMap<String, CachedToken>,
+}
@@ -190,6 +190,24 @@ impl Pod {
         self.config.pod_type.unwrap_or(PodType::Service)
     }
 
+    /// Should this pod be kept off the project-wide network which allows
+    /// services in different pods to reach each other?
+    pub fn is_isolated(&self) -> bool {
+        self.config.isolated
+    }
+
+    /// Has this pod asked us to assign (or not assign) free host ports to
+    /// its published ports?  `None` means we should decide based on the
+    /// target.
+    pub fn allocate_ports(&self) -> Option<bool> {
+        self.config.allocate_ports
+    }
+
+    /// How should this pod be deployed when exported to an orchestrator?
+    pub fn deploy(&self) -> Option<&DeployConfig> {
+        self.config.deploy.as_ref()
+    }
+
     /// Get the names of the services declared in this pod.
     pub fn service_names(&self) -> &BTreeSet<String> {
         &self.service_names
@@ -394,3 +412,26 @@ fn pod_type_returns_type_of_pod() {
     let rake = proj.pod("rake").unwrap();
     assert_eq!(rake.pod_type(), PodType::Task);
 }
+
+#[test]
+fn is_isolated_reads_pod_metadata() {
+    use env_logger;
+    let _ = env_logger::init();
+    let rails_hello: Project = Project::from_example("rails_hello").unwrap();
+    assert!(!rails_hello.pod("frontend").unwrap().is_isolated());
+    let node_hello: Project = Project::from_example("node_hello").unwrap();
+    assert!(node_hello.pod("frontend").unwrap().is_isolated());
+}
+
+#[test]
+fn deploy_reads_pod_metadata() {
+    use env_logger;
+    let _ = env_logger::init();
+    let rails_hello: Project = Project::from_example("rails_hello").unwrap();
+    assert!(rails_hello.pod("frontend").unwrap().deploy().is_none());
+    let node_hello: Project = Project::from_example("node_hello").unwrap();
+    let deploy = node_hello.pod("frontend").unwrap().deploy().unwrap();
+    assert_eq!(deploy.replicas, Some(2));
+    assert_eq!(deploy.resources.limits.memory, Some("256M".to_owned()));
+    assert!(deploy.resources.reservations.is_empty());
+}
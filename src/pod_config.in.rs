@@ -31,6 +31,50 @@ impl fmt::Display for PodType {
     }
 }
 
+/// How to deploy a pod's services when exporting to an orchestrator.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeployConfig {
+    /// How many copies of each service should we run?
+    pub replicas: Option<u32>,
+
+    /// The resources available to each service.
+    #[serde(default)]
+    pub resources: Resources,
+}
+
+/// Resource limits and reservations for a service.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Resources {
+    /// The most resources a service may use.
+    #[serde(default)]
+    pub limits: ResourceValues,
+
+    /// The resources we should set aside for a service.
+    #[serde(default)]
+    pub reservations: ResourceValues,
+}
+
+/// Amounts of CPU and memory, written as strings using `docker-compose`
+/// syntax.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResourceValues {
+    /// A number of CPUs, such as `"0.5"`.
+    pub cpus: Option<String>,
+
+    /// An amount of memory, such as `"512M"`.
+    pub memory: Option<String>,
+}
+
+impl ResourceValues {
+    /// Are any values specified?
+    pub fn is_empty(&self) -> bool {
+        self.cpus.is_none() && self.memory.is_none()
+    }
+}
+
 /// Configuration information about a pod.
 #[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -41,4 +85,16 @@ struct Config {
 
     /// What kind of pod is this?
     pod_type: Option<PodType>,
+
+    /// Should this pod be kept off the project-wide shared network?
+    #[serde(default)]
+    isolated: bool,
+
+    /// Should cage assign free host ports to the ports published by this
+    /// pod?  If omitted, we use the `ports` plugin settings for the
+    /// current target.
+    allocate_ports: Option<bool>,
+
+    /// How to deploy this pod when exporting it to an orchestrator.
+    deploy: Option<DeployConfig>,
 }
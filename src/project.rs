@@ -1,11 +1,10 @@
 //! A cage project.
 
-#[cfg(test)]
 use compose_yml::v2 as dc;
 use semver;
-use serde_yaml;
 use std::collections::BTreeMap;
 use std::env;
+use std::ffi::OsStr;
 use std::fs;
 use std::io;
 use std::io::Read;
@@ -19,16 +18,21 @@ use std::str;
 use default_tags::DefaultTags;
 use dir;
 use errors::*;
+use export::{self, ExportFormat, ExportedPod};
+use fingerprint;
 use hook::HookManager;
+use image_lock::{DigestSource, ImageLock};
 use target::Target;
 use plugins::{self, Operation};
 use pod::{Pod, PodType};
 use sources::Sources;
 use rayon::prelude::*;
 use rustc_serialize::json::{Json, ToJson};
-use serde_helpers::deserialize_parsable_opt;
+use serde::Deserialize;
+use serde_helpers::{deserialize_parsable_opt, YamlValue};
 use service_locations::ServiceLocations;
-use util::{ConductorPathExt, ToStrOrErr};
+use util::{self, ConductorPathExt, ToStrOrErr};
+use vars::Vars;
 use version;
 
 // Include some source code containing data structures we need to run
@@ -89,6 +93,9 @@ pub struct Project {
     /// The main configuration for this project.
     config: ProjectConfig,
 
+    /// Variables to interpolate into our pod files.
+    vars: Vars,
+
     /// Docker image tags to use for images that don't have them.
     /// Typically used to lock down versions supplied by a CI system.
     default_tags: Option<DefaultTags>,
@@ -96,6 +103,14 @@ pub struct Project {
     /// The plugins associated with this project.  Guaranteed to never be
     /// `None` after returning from `from_dirs`.
     plugins: Option<plugins::Manager>,
+
+    /// Should `output` regenerate our output even if our inputs haven't
+    /// changed?
+    force_output: bool,
+
+    /// Should `export` fail if any images are untagged?  This can also be
+    /// turned on in our config.
+    strict_tags: bool,
 }
 
 impl Project {
@@ -132,8 +147,11 @@ impl Project {
             sources: sources,
             hooks: try!(HookManager::new(root_dir.join("config").join("hooks"))),
             config: config,
+            vars: try!(Vars::new(root_dir)),
             default_tags: None,
             plugins: None,
+            force_output: false,
+            strict_tags: false,
         };
         let plugins = try!(plugins::Manager::new(&proj));
         proj.plugins = Some(plugins);
@@ -188,6 +206,14 @@ impl Project {
         Project::from_dirs(&root_dir, &test_output.join("src"), &test_output)
     }
 
+    /// (Tests only.) Create a `Project` from `root_dir`, which is usually
+    /// a project generated by the test itself, with the same layout as
+    /// `from_current_dir`.
+    #[cfg(test)]
+    pub fn from_test_dir(root_dir: &Path) -> Result<Project> {
+        Project::from_dirs(root_dir, &root_dir.join("src"), &root_dir.join(".cage"))
+    }
+
     /// (Tests only.) Remove our output directory after a test.
     #[cfg(test)]
     pub fn remove_test_output(&self) -> Result<()> {
@@ -367,6 +393,20 @@ impl Project {
         &self.hooks
     }
 
+    /// The main configuration for this project, as loaded from
+    /// `config/project.yml`.
+    pub fn config(&self) -> &ProjectConfig {
+        &self.config
+    }
+
+    /// (Tests only.) Modify the configuration for this project.  Note
+    /// that this doesn't update any plugins which have already been
+    /// loaded.
+    #[cfg(test)]
+    pub fn config_mut(&mut self) -> &mut ProjectConfig {
+        &mut self.config
+    }
+
     /// Get the default tags associated with this project, if any.
     pub fn default_tags(&self) -> Option<&DefaultTags> {
         self.default_tags.as_ref()
@@ -378,6 +418,66 @@ impl Project {
         self
     }
 
+    /// Should `export` fail if any images are untagged or tagged
+    /// `latest`?  This is true if either `set_strict_tags` or our config
+    /// file turned it on.
+    pub fn strict_tags(&self) -> bool {
+        self.strict_tags || self.config.strict_tags
+    }
+
+    /// Make `export` fail if any images are untagged or tagged `latest`.
+    pub fn set_strict_tags(&mut self, strict: bool) -> &mut Project {
+        self.strict_tags = strict;
+        self
+    }
+
+    /// If we're in strict mode, fail if any service in our processed
+    /// `pods` has an image with no tag or the `latest` tag, listing each
+    /// one as `pod/service: image`.  Images pinned to a digest are fine.
+    fn check_strict_tags(&self, pods: &[ExportedPod]) -> Result<()> {
+        if !self.strict_tags() {
+            return Ok(());
+        }
+        let mut untagged = vec![];
+        for exported in pods {
+            for (name, service) in &exported.file.services {
+                let image = match service.image {
+                    Some(ref image) => try!(image.value()),
+                    None => continue,
+                };
+                if image.to_string().contains('@') {
+                    continue;
+                }
+                match image.tag.as_ref().map(|t| &t[..]) {
+                    None | Some("latest") => {
+                        untagged.push(format!("{}/{}: {}", exported.pod.name(), name, image))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        if untagged.is_empty() {
+            Ok(())
+        } else {
+            Err(err!("Refusing to export images without specific tags (try \
+                      --default-tags):\n  {}",
+                     untagged.join("\n  ")))
+        }
+    }
+
+    /// Look up the digest of every image used by our processed `pods`.
+    fn lock_images(pods: &[ExportedPod], source: &DigestSource) -> Result<ImageLock> {
+        let mut images = vec![];
+        for exported in pods {
+            for service in exported.file.services.values() {
+                if let Some(ref image) = service.image {
+                    images.push(try!(image.value()).to_string());
+                }
+            }
+        }
+        ImageLock::resolve(images.iter().map(|i| &i[..]), source)
+    }
+
     /// Our plugin manager.
     pub fn plugins(&self) -> &plugins::Manager {
         self.plugins
@@ -385,78 +485,296 @@ impl Project {
             .expect("plugins should always be set at Project init")
     }
 
+    /// (Tests only.) Our plugin manager, for tests which need to
+    /// register extra plugins.
+    #[cfg(test)]
+    pub fn plugins_mut(&mut self) -> &mut plugins::Manager {
+        self.plugins
+            .as_mut()
+            .expect("plugins should always be set at Project init")
+    }
+
     /// Save persistent project settings to disk.
     pub fn save_settings(&mut self) -> Result<()> {
         self.sources.save_settings(&self.output_dir)
     }
 
-    /// Process our pods, flattening and transforming them using our
-    /// plugins, and output them to the specified directory.
-    fn output_helper(&self, op: Operation, export_dir: &Path) -> Result<()> {
-        // Output each pod.  This isn't especially slow (except maybe the
-        // Vault plugin), but parallelizing things is easy.
-        self.pods.par_iter()
+    /// Write our processed `pods` to the specified directory.  We only
+    /// rewrite files whose contents have changed, and we return the names
+    /// of the pods we wrote, in sorted order.
+    fn write_pods(op: Operation,
+                  pods: &[ExportedPod],
+                  export_dir: &Path)
+                  -> Result<Vec<String>> {
+        let mut changed = vec![];
+        for exported in pods {
+            // Figure out where to put our pod.
+            let out_path = export_dir.join(Self::output_rel_path(op, exported.pod));
+
+            // Render our pod in memory, and only write it if it's
+            // different from what's already there.
+            let mut rendered = vec![];
+            try!(exported.file.write(&mut rendered));
+            if try!(util::write_if_changed(&out_path, &rendered)) {
+                debug!("Outputting {}", out_path.display());
+                changed.push(exported.pod.name().to_owned());
+            } else {
+                debug!("{} is unchanged", out_path.display());
+            }
+        }
+        changed.sort();
+        Ok(changed)
+    }
+
+    /// Where should we output `pod`, relative to our output directory?
+    fn output_rel_path(op: Operation, pod: &Pod) -> PathBuf {
+        let file_name = format!("{}.yml", pod.name());
+        match (op, pod.pod_type()) {
+            (Operation::Export, PodType::Task) => Path::new("tasks").join(file_name),
+            _ => Path::new(&file_name).to_owned(),
+        }
+    }
+
+    /// Combine targets for `pod` and interpolate our `config/vars.yml`
+    /// variables, but don't run any plugins.  Commands which just want to
+    /// look at the services in a pod should use this instead of
+    /// `Pod::merged_file`, which leaves variables like `${PORT}` in place.
+    pub fn interpolated_file(&self, pod: &Pod) -> Result<dc::File> {
+        let mut file = try!(pod.merged_file(&self.current_target));
+        try!(self.vars.interpolate(self, pod, &mut file)
+            .chain_err(|| format!("could not interpolate variables into {}", pod.name())));
+        Ok(file)
+    }
+
+    /// Look up the service `name` in `interpolated_file(pod)`, returning an
+    /// error if it can't be found.
+    pub fn interpolated_service_or_err(&self, pod: &Pod, name: &str) -> Result<dc::Service> {
+        try!(self.interpolated_file(pod))
+            .services
+            .remove(name)
+            .ok_or_else(|| ErrorKind::UnknownService(name.to_owned()).into())
+    }
+
+    /// Look up the service `name` in the file we generated for `pod` in
+    /// `.cage/pods`, which has our variables and plugins applied.  Returns
+    /// `None` if `pod` isn't enabled in the current target or we haven't
+    /// generated it yet.
+    pub fn output_service(&self, pod: &Pod, name: &str) -> Result<Option<dc::Service>> {
+        let path = self.output_pods_dir().join(format!("{}.yml", pod.name()));
+        if !pod.enabled_in(&self.current_target) || !path.exists() {
+            return Ok(None);
+        }
+        let mut file = try!(dc::File::read_from_path(&path));
+        Ok(file.services.remove(name))
+    }
+
+    /// Combine targets for `pod`, make it standalone, and tweak it as
+    /// needed using our plugins.
+    fn processed_pod<'a>(&self, op: Operation, pod: &'a Pod) -> Result<ExportedPod<'a>> {
+        let mut file = try!(self.interpolated_file(pod));
+        try!(file.make_standalone(&self.pods_dir()));
+
+        // Keep track of our labels before the `labels` plugin strips any
+        // of them from exported files.
+        let mut original_labels = BTreeMap::new();
+        if op == Operation::Export {
+            for (name, service) in &file.services {
+                original_labels.insert(name.to_owned(), try!(export::service_labels(service)));
+            }
+        }
+
+        let ctx = plugins::Context::new(self, pod);
+        try!(self.plugins().transform(op, &ctx, &mut file));
+        Ok(ExportedPod {
+            pod: pod,
+            file: file,
+            original_labels: original_labels,
+        })
+    }
+
+    /// Process all our enabled pods for `op`, without writing them
+    /// anywhere.  Each pod is processed exactly once, so plugins with side
+    /// effects (like the Vault plugin, which creates tokens) should only
+    /// call this once per command.
+    pub fn processed_pods(&self, op: Operation) -> Result<Vec<ExportedPod>> {
+        // This isn't especially slow (except maybe the Vault plugin), but
+        // parallelizing things is easy.
+        self.pods
+            .par_iter()
             // Don't export pods which aren't enabled.
             //
             // TODO MED: Should we exclude these at load time instead?
             .filter(|pod| pod.enabled_in(&self.current_target))
             // Process each pod in parallel.
-            .map(|pod| -> Result<()> {
-                // Figure out where to put our pod.
-                let file_name = format!("{}.yml", pod.name());
-                let rel_path = match (op, pod.pod_type()) {
-                    (Operation::Export, PodType::Task) => {
-                        Path::new("tasks").join(file_name)
-                    }
-                    _ => Path::new(&file_name).to_owned(),
-                };
-                let out_path = try!(export_dir.join(&rel_path)
-                    .with_guaranteed_parent());
-                debug!("Outputting {}", out_path.display());
-
-                // Combine targets, make it standalone, tweak as needed, and
-                // output.
-                let mut file = try!(pod.merged_file(&self.current_target));
-                try!(file.make_standalone(&self.pods_dir()));
-                let ctx = plugins::Context::new(self, pod);
-                try!(self.plugins().transform(op, &ctx, &mut file));
-                try!(file.write_to_path(out_path));
-                Ok(())
+            .map(|pod| -> Result<Vec<ExportedPod>> {
+                Ok(vec![try!(self.processed_pod(op, pod))])
             })
             // If more than one parallel branch fails, just return one error.
-            .reduce_with(|result1, result2| result1.and(result2).and(Ok(())))
-            .unwrap_or(Ok(()))
+            .reduce_with(|result1, result2| {
+                let mut pods = try!(result1);
+                pods.extend(try!(result2));
+                Ok(pods)
+            })
+            .unwrap_or_else(|| Ok(vec![]))
+    }
+
+    /// Should `output` regenerate our output even if none of its inputs
+    /// have changed?  Changes made to this `Project` in memory, like
+    /// `config_mut`, aren't included in our fingerprint of its inputs.
+    pub fn set_force_output(&mut self, force: bool) {
+        self.force_output = force;
     }
 
-    /// Delete our existing output and replace it with a processed and
-    /// expanded version of our pod definitions.
-    pub fn output(&self) -> Result<()> {
-        // Get a path to our output pods directory (and delete it if it
-        // exists).
+    /// Update our output with a processed and expanded version of our pod
+    /// definitions, deleting the output for any pods which are no longer
+    /// enabled.  Returns the names of the pods whose output was written or
+    /// deleted, in sorted order, so that callers can tell which pods need
+    /// to be restarted.
+    ///
+    /// If none of our inputs have changed since our last output, we do
+    /// nothing at all (unless `set_force_output` was called, or the
+    /// `vault` plugin is enabled, since we can't tell when the secrets
+    /// and credentials it fetches have changed or expired).
+    pub fn output(&self) -> Result<Vec<String>> {
         let out_pods = self.output_pods_dir();
-        if out_pods.exists() {
-            try!(fs::remove_dir_all(&out_pods)
-                .map_err(|e| err!("Cannot delete {}: {}", out_pods.display(), e)));
+
+        // Skip everything if nothing has changed since our last output.
+        let fingerprint = try!(fingerprint::output_inputs(self));
+        if !self.force_output && !self.uses_vault() && out_pods.exists() &&
+           try!(fingerprint::recorded(self)).as_ref() == Some(&fingerprint) {
+            debug!("Inputs are unchanged, so not regenerating {}",
+                   out_pods.display());
+            return Ok(vec![]);
         }
 
-        self.output_helper(Operation::Output, &out_pods)
+        let pods = try!(self.processed_pods(Operation::Output));
+        let mut changed = try!(Self::write_pods(Operation::Output, &pods, &out_pods));
+        changed.extend(try!(self.remove_stale_output(&out_pods)));
+        changed.sort();
+
+        // If requested, also merge all our pods into a single file.
+        if let Some(ref path) = self.config.combined_output {
+            try!(export::combined::write(self, &pods, &self.root_dir.join(path)));
+        }
+
+        try!(fingerprint::record(self, &fingerprint));
+        Ok(changed)
+    }
+
+    /// Is the `vault` plugin enabled for our current target?
+    fn uses_vault(&self) -> bool {
+        let target = self.current_target();
+        self.plugins()
+            .transforms()
+            .iter()
+            .any(|t| t.name() == "vault" && t.is_enabled_in(target))
+    }
+
+    /// Delete any files in `out_pods` which don't belong to an enabled
+    /// pod, and return the names of the pods they belonged to.
+    fn remove_stale_output(&self, out_pods: &Path) -> Result<Vec<String>> {
+        let mut removed = vec![];
+        if !out_pods.exists() {
+            return Ok(removed);
+        }
+        for entry in try!(fs::read_dir(out_pods)) {
+            let path = try!(entry).path();
+            if path.extension() != Some(OsStr::new("yml")) {
+                continue;
+            }
+            let stem = try!(path.file_stem()
+                .ok_or_else(|| err!("can't get pod name from {}", path.display())));
+            let name = try!(stem.to_str_or_err()).to_owned();
+            let is_current = self.pod(&name)
+                .map_or(false, |pod| pod.enabled_in(&self.current_target));
+            if !is_current {
+                debug!("Removing stale output {}", path.display());
+                try!(fs::remove_file(&path)
+                    .map_err(|e| err!("Cannot delete {}: {}", path.display(), e)));
+                removed.push(name);
+            }
+        }
+        Ok(removed)
     }
 
     /// Export this project (with the specified target applied) as a set
     /// of standalone `*.yml` files with no environment variable
     /// interpolations and no external dependencies.
     pub fn export(&self, export_dir: &Path) -> Result<()> {
+        self.export_as(ExportFormat::Compose, export_dir)
+    }
+
+    /// Export this project (with the specified target applied) in
+    /// `format`.  `ExportFormat::Compose` works just like `export`.
+    pub fn export_as(&self, format: ExportFormat, export_dir: &Path) -> Result<()> {
+        let pods = try!(self.processed_pods_for_export(export_dir));
+        self.export_pods(format, &pods, export_dir)
+    }
+
+    /// Export this project like `export_as`, and write a lock file to
+    /// `lock_path` recording the digest of each exported image, as
+    /// reported by `source`.  If `pin` is true, the exported files refer
+    /// to each image by digest, as `name@sha256:...`.  We only write the
+    /// lock file once the export succeeds, and we refuse to overwrite an
+    /// existing one.
+    pub fn export_locked_as(&self,
+                            format: ExportFormat,
+                            export_dir: &Path,
+                            lock_path: &Path,
+                            source: &DigestSource,
+                            pin: bool)
+                            -> Result<()> {
+        if lock_path.exists() {
+            return Err(err!("The lock file {} already exists", lock_path.display()));
+        }
+        let mut pods = try!(self.processed_pods_for_export(export_dir));
+        let lock = try!(Self::lock_images(&pods, source));
+        if pin {
+            for exported in &mut pods {
+                try!(lock.pin_file(&mut exported.file));
+            }
+        }
+        try!(self.export_pods(format, &pods, export_dir));
+        lock.write_to_path(lock_path)
+    }
+
+    /// Check that we can export to `export_dir`, and process all our pods
+    /// for export.
+    fn processed_pods_for_export(&self, export_dir: &Path) -> Result<Vec<ExportedPod>> {
         // Don't clobber an existing directory.
         if export_dir.exists() {
             return Err(err!("The directory {} already exists", export_dir.display()));
         }
 
         // You should really supply default tags if you're going to export.
-        if self.default_tags().is_none() {
+        let pods = try!(self.processed_pods(Operation::Export));
+        if self.strict_tags() {
+            try!(self.check_strict_tags(&pods));
+        } else if self.default_tags().is_none() {
             warn!("Exporting project without --default-tags");
         }
+        Ok(pods)
+    }
 
-        self.output_helper(Operation::Export, export_dir)
+    /// Write our processed `pods` to `export_dir` in `format`.
+    fn export_pods(&self,
+                   format: ExportFormat,
+                   pods: &[ExportedPod],
+                   export_dir: &Path)
+                   -> Result<()> {
+        match format {
+            ExportFormat::Compose => {
+                try!(Self::write_pods(Operation::Export, pods, export_dir));
+                Ok(())
+            }
+            ExportFormat::Combined => {
+                export::combined::write(self, pods, &export_dir.join("docker-compose.yml"))
+            }
+            ExportFormat::Kubernetes => export::kubernetes::export(self, pods, export_dir),
+            ExportFormat::Nomad => export::nomad::export(self, pods, export_dir),
+            ExportFormat::Stack => export::stack::export(pods, export_dir),
+            ExportFormat::Systemd => export::systemd::export(self, pods, export_dir),
+        }
     }
 }
 
@@ -711,3 +1029,176 @@ fn export_applies_expected_transforms() {
     assert_eq!(web.labels.get("io.fdy.cage.pod"),
                Some(&"frontend".to_owned()));
 }
+
+#[test]
+fn export_can_require_specific_tags() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    proj.config_mut().strict_tags = true;
+    let export_dir = proj.output_dir.join("strict_export");
+
+    // Every untagged image should be listed.
+    let err = proj.export(&export_dir).unwrap_err().to_string();
+    assert!(err.contains("db/db: postgres"));
+    assert!(err.contains("frontend/web: faraday/rails_hello"));
+    assert!(err.contains("rake/rake: faraday/rails_hello"));
+    assert!(!export_dir.exists());
+
+    // `latest` doesn't count as a specific tag.
+    let cursor = io::Cursor::new("faraday/rails_hello:latest\npostgres:9.6\n");
+    proj.set_default_tags(DefaultTags::read(cursor).unwrap());
+    let err = proj.export(&export_dir).unwrap_err().to_string();
+    assert!(!err.contains("postgres"));
+    assert!(err.contains("frontend/web: faraday/rails_hello:latest"));
+
+    let cursor = io::Cursor::new("faraday/rails_hello:1.0\npostgres:9.6\n");
+    proj.set_default_tags(DefaultTags::read(cursor).unwrap());
+    proj.export(&export_dir).unwrap();
+    assert!(export_dir.join("frontend.yml").exists());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn export_can_pin_images_to_digests() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let cursor = io::Cursor::new("faraday/rails_hello:1.0\npostgres:9.6\n");
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    proj.set_default_tags(DefaultTags::read(cursor).unwrap());
+
+    // Images are locked after applying our default tags.
+    let mut digests = BTreeMap::new();
+    digests.insert("faraday/rails_hello:1.0".to_owned(), "sha256:111".to_owned());
+    digests.insert("postgres:9.6".to_owned(), "sha256:222".to_owned());
+    let export_dir = proj.output_dir.join("pinned_export");
+    let lock_path = proj.output_dir.join("images.lock");
+    proj.export_locked_as(ExportFormat::Compose,
+                          &export_dir,
+                          &lock_path,
+                          &digests,
+                          true)
+        .unwrap();
+    let lock = ImageLock::read_from_path(&lock_path).unwrap();
+    assert_eq!(lock.digest("postgres:9.6"), Some("sha256:222"));
+
+    // We won't overwrite an existing lock file.
+    let another_dir = proj.output_dir.join("another_export");
+    let err = proj.export_locked_as(ExportFormat::Compose,
+                                    &another_dir,
+                                    &lock_path,
+                                    &digests,
+                                    true)
+        .unwrap_err();
+    assert!(err.to_string().contains("already exists"));
+    let mut yaml = String::new();
+    fs::File::open(export_dir.join("frontend.yml"))
+        .unwrap()
+        .read_to_string(&mut yaml)
+        .unwrap();
+    assert!(yaml.contains("faraday/rails_hello@sha256:111"));
+
+    // Output for local use is unaffected.
+    proj.output().unwrap();
+    let file = dc::File::read_from_path(proj.output_dir.join("pods").join("frontend.yml"))
+        .unwrap();
+    assert_eq!(file.services["web"].image.as_ref().unwrap().value().unwrap().to_string(),
+               "faraday/rails_hello:1.0");
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn output_can_write_combined_file() {
+    use env_logger;
+    use serde_yaml;
+    let _ = env_logger::init();
+
+    // `combined_output` is relative to the project root unless it's
+    // absolute, so use an absolute path to keep it out of our example.
+    let mut proj = Project::from_example("hello").unwrap();
+    let combined_path = env::current_dir().unwrap().join(&proj.output_dir).join("combined.yml");
+    proj.config_mut().combined_output = Some(combined_path.clone());
+    proj.output().unwrap();
+
+    // Our shared network should be created by `docker-compose` itself.
+    let file = dc::File::read_from_path(combined_path).unwrap();
+    assert!(file.services.contains_key("web"));
+    let yaml = serde_yaml::to_value(&file);
+    assert!(yaml["networks"]["hello_shared"]["external"].is_badvalue());
+    assert!(!yaml["networks"]["hello_shared"].is_badvalue());
+    assert!(!yaml["services"]["web"]["networks"]["hello_shared"].is_badvalue());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn output_keeps_secrets_out_of_combined_file() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    // `rails_hello` has a `config/secrets.yml` file.
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    let combined_path = env::current_dir().unwrap().join(&proj.output_dir).join("combined.yml");
+    proj.config_mut().combined_output = Some(combined_path.clone());
+    proj.output().unwrap();
+    let mut combined = String::new();
+    fs::File::open(&combined_path).unwrap().read_to_string(&mut combined).unwrap();
+    assert!(combined.contains("${GLOBAL_PASSWORD}"));
+    assert!(!combined.contains("magic"));
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn output_only_rewrites_changed_pods() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    proj.set_force_output(true);
+    let out_pods = proj.output_pods_dir();
+    assert_eq!(proj.output().unwrap(), &["db", "frontend", "rake"]);
+    let mtime = || fs::metadata(out_pods.join("frontend.yml")).unwrap().modified().unwrap();
+    let first_mtime = mtime();
+
+    // Nothing has changed, so we shouldn't write anything.
+    assert!(proj.output().unwrap().is_empty());
+    assert_eq!(mtime(), first_mtime);
+
+    // Files for stale or disabled pods get removed.
+    fs::File::create(out_pods.join("old.yml")).unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let changed = proj.output().unwrap();
+    assert!(changed.contains(&"db".to_owned()));
+    assert!(changed.contains(&"old".to_owned()));
+    assert!(!out_pods.join("db.yml").exists());
+    assert!(!out_pods.join("old.yml").exists());
+
+    proj.remove_test_output().unwrap();
+}
+
+#[test]
+fn output_skips_work_when_inputs_are_unchanged() {
+    use env_logger;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    let frontend_path = proj.output_pods_dir().join("frontend.yml");
+    proj.output().unwrap();
+
+    // If our inputs haven't changed, we don't even notice damaged output.
+    fs::File::create(&frontend_path).unwrap();
+    assert!(proj.output().unwrap().is_empty());
+    assert_eq!(fs::metadata(&frontend_path).unwrap().len(), 0);
+
+    // But we can always force regeneration.
+    proj.set_force_output(true);
+    assert_eq!(proj.output().unwrap(), &["frontend"]);
+    assert!(fs::metadata(&frontend_path).unwrap().len() > 0);
+
+    proj.remove_test_output().unwrap();
+}
@@ -18,12 +18,89 @@ pub struct ProjectConfig {
     #[serde(default, deserialize_with = "deserialize_parsable_opt")]
     pub cage_version: Option<semver::VersionReq>,
 
+    /// Which plugins should we run, and how should they be configured?
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+
+    /// If present, `cage` will also merge all the service pods enabled in
+    /// the current target into a single `docker-compose.yml` file at this
+    /// path.  Relative paths are relative to the project root.  Task pods
+    /// are left out, and secrets are replaced by `${VAR}` references.
+    #[serde(default)]
+    pub combined_output: Option<PathBuf>,
+
+    /// If true, `cage export` fails if any image would be exported
+    /// without a tag, or with the `latest` tag.
+    #[serde(default)]
+    pub strict_tags: bool,
+
     /// Ensure that this struct has at least one private field so we
     /// can extend it in the future.
     #[serde(default, skip_deserializing)]
     _phantom: PhantomData<()>,
 }
 
+/// Where should an external plugin be inserted into our list of
+/// transforms?
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExternalPluginConfig {
+    /// The name of this plugin, for use in error messages and in the
+    /// `settings` section.
+    pub name: String,
+
+    /// The command to run, with any arguments.  Relative paths are
+    /// interpreted relative to the project root.
+    pub command: Vec<String>,
+
+    /// Run this plugin just before the named plugin.
+    pub before: Option<String>,
+
+    /// Run this plugin just after the named plugin.  If neither `before`
+    /// nor `after` is specified, run this plugin after all the others.
+    pub after: Option<String>,
+}
+
+/// Plugin-related configuration, read from the `plugins` section of
+/// `PROJECT_CONFIG_PATH`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Built-in plugins which should not be run.
+    #[serde(default)]
+    pub disable: Vec<String>,
+
+    /// External transform plugins to insert into our pipeline.
+    #[serde(default)]
+    pub external: Vec<ExternalPluginConfig>,
+
+    /// Per-plugin settings, indexed by plugin name.  The format of these
+    /// settings is up to each individual plugin.
+    #[serde(default)]
+    pub settings: BTreeMap<String, YamlValue>,
+}
+
+impl PluginsConfig {
+    /// Has the specified plugin been disabled?
+    pub fn is_disabled(&self, name: &str) -> bool {
+        self.disable.iter().any(|d| d == name)
+    }
+
+    /// Get the settings for the named plugin, converted to the
+    /// appropriate type, or a default value if no settings were supplied.
+    pub fn settings_for<T>(&self, name: &str) -> Result<T>
+        where T: Deserialize + Default
+    {
+        match self.settings.get(name) {
+            Some(settings) => {
+                settings.to_typed()
+                    .chain_err(|| format!("invalid settings for plugin '{}'", name))
+            }
+            None => Ok(Default::default()),
+        }
+    }
+}
+
 impl ProjectConfig {
     /// Load a config file from the specified path.
     pub fn new(path: &Path) -> Result<Self> {
@@ -110,3 +187,35 @@ fn check_config_version() {
         ref e => panic!("Unexpected error type {}", e),
     }
 }
+
+#[test]
+fn plugins_config_can_be_deserialized() {
+    /// Settings for an imaginary plugin.
+    #[derive(Debug, Default, Deserialize)]
+    struct FakeSettings {
+        /// A fake setting.
+        color: String,
+    }
+
+    let yaml = r#"---
+plugins:
+  disable:
+    - "labels"
+  external:
+    - name: "sidecar"
+      command: ["bin/add-sidecar", "--verbose"]
+      after: "sources"
+  settings:
+    sidecar:
+      color: "blue"
+"#;
+    let config: ProjectConfig = serde_yaml::from_str(yaml).unwrap();
+    assert!(config.plugins.is_disabled("labels"));
+    assert!(!config.plugins.is_disabled("sources"));
+    assert_eq!(config.plugins.external.len(), 1);
+    assert_eq!(config.plugins.external[0].after, Some("sources".to_owned()));
+    let settings: FakeSettings = config.plugins.settings_for("sidecar").unwrap();
+    assert_eq!(settings.color, "blue");
+    let missing: FakeSettings = config.plugins.settings_for("other").unwrap();
+    assert_eq!(missing.color, "");
+}
@@ -1,8 +1,9 @@
 //! Helper functions for use with `serde`.
 
 use serde::{self, Deserialize, Deserializer, Serialize};
-use serde::de::Visitor;
+use serde::de::{MapVisitor, SeqVisitor, Visitor};
 use serde_yaml;
+use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::fs;
 use std::io;
@@ -12,6 +13,7 @@ use std::str::FromStr;
 
 use errors::{self, ChainErr, ErrorKind};
 use util::ConductorPathExt;
+use yaml_rust::YamlEmitter;
 
 /// Load a YAML file using `serde`, and generate the best error we can if
 /// it fails.
@@ -33,6 +35,17 @@ pub fn dump_yaml<T>(path: &Path, data: &T) -> Result<(), errors::Error>
     serde_yaml::to_writer(&mut io::BufWriter::new(f), data).chain_err(&mkerr)
 }
 
+/// Convert a raw YAML value to a string.  We need this because
+/// `serde_yaml::Value` doesn't implement `Serialize`.
+pub fn yaml_value_to_string(value: &serde_yaml::Value) -> Result<String, errors::Error> {
+    let mut out = String::new();
+    try!(YamlEmitter::new(&mut out)
+        .dump(value)
+        .map_err(|e| errors::Error::from(format!("could not serialize YAML: {:?}", e))));
+    out.push('\n');
+    Ok(out)
+}
+
 /// Deserialize a type that we can parse using `FromStr`.
 pub fn deserialize_parsable<D, T>(deserializer: &mut D) -> Result<T, D::Error>
     where D: Deserializer,
@@ -98,3 +111,121 @@ pub fn deserialize_parsable_opt<D, T>(deserializer: &mut D)
 
     Wrap::deserialize(deserializer).map(|wrap| wrap.0)
 }
+
+/// An arbitrary chunk of YAML, which we can deserialize as part of a
+/// larger structure and interpret later, once we know what type it should
+/// have.  We need this wrapper because `serde_yaml::Value` doesn't
+/// implement `Deserialize` itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YamlValue(pub serde_yaml::Value);
+
+impl YamlValue {
+    /// Convert this YAML into a value of type `T`.
+    pub fn to_typed<T>(&self) -> Result<T, errors::Error>
+        where T: Deserialize
+    {
+        Ok(try!(serde_yaml::from_value(self.0.clone())))
+    }
+}
+
+impl Deserialize for YamlValue {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Self, D::Error>
+        where D: Deserializer
+    {
+        /// Declare an internal visitor type to handle our input.
+        struct YamlVisitor;
+
+        impl Visitor for YamlVisitor {
+            type Value = YamlValue;
+
+            fn visit_bool<E>(&mut self, v: bool) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::Boolean(v)))
+            }
+
+            fn visit_i64<E>(&mut self, v: i64) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::Integer(v)))
+            }
+
+            fn visit_u64<E>(&mut self, v: u64) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                if v > i64::max_value() as u64 {
+                    Err(E::custom(format!("integer {} is too large", v)))
+                } else {
+                    Ok(YamlValue(serde_yaml::Value::Integer(v as i64)))
+                }
+            }
+
+            fn visit_f64<E>(&mut self, v: f64) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::Real(format!("{}", v))))
+            }
+
+            fn visit_str<E>(&mut self, v: &str) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::String(v.to_owned())))
+            }
+
+            fn visit_unit<E>(&mut self) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::Null))
+            }
+
+            fn visit_none<E>(&mut self) -> Result<Self::Value, E>
+                where E: serde::Error
+            {
+                Ok(YamlValue(serde_yaml::Value::Null))
+            }
+
+            fn visit_some<D>(&mut self,
+                             deserializer: &mut D)
+                             -> Result<Self::Value, D::Error>
+                where D: Deserializer
+            {
+                YamlValue::deserialize(deserializer)
+            }
+
+            fn visit_seq<V>(&mut self, mut visitor: V) -> Result<Self::Value, V::Error>
+                where V: SeqVisitor
+            {
+                let mut items = vec![];
+                while let Some(YamlValue(item)) = try!(visitor.visit::<YamlValue>()) {
+                    items.push(item);
+                }
+                try!(visitor.end());
+                Ok(YamlValue(serde_yaml::Value::Array(items)))
+            }
+
+            fn visit_map<V>(&mut self, mut visitor: V) -> Result<Self::Value, V::Error>
+                where V: MapVisitor
+            {
+                let mut items = BTreeMap::new();
+                while let Some((YamlValue(k), YamlValue(v))) =
+                    try!(visitor.visit::<YamlValue, YamlValue>()) {
+                    items.insert(k, v);
+                }
+                try!(visitor.end());
+                Ok(YamlValue(serde_yaml::Value::Hash(items)))
+            }
+        }
+
+        deserializer.deserialize(YamlVisitor)
+    }
+}
+
+#[test]
+fn yaml_value_can_be_deserialized_and_converted() {
+    let yaml = "---\na: 1\nb: [\"x\", true]\n";
+    let value: YamlValue = serde_yaml::from_str(yaml).unwrap();
+    let map: BTreeMap<String, YamlValue> = value.to_typed().unwrap();
+    assert_eq!(map.get("a").unwrap().0, serde_yaml::Value::Integer(1));
+    let b: (String, bool) = map.get("b").unwrap().to_typed().unwrap();
+    assert_eq!(b, ("x".to_owned(), true));
+}
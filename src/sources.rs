@@ -4,9 +4,11 @@
 use compose_yml::v2 as dc;
 use std::collections::BTreeMap;
 use std::collections::btree_map;
+use std::ffi::OsString;
 #[cfg(test)]
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::process;
 
 use command_runner::{Command, CommandRunner};
 #[cfg(test)]
@@ -261,6 +263,26 @@ impl Source {
         }
     }
 
+    /// The abbreviated git commit ID currently checked out in our local
+    /// copy of this source tree.
+    pub fn git_revision(&self, project: &Project) -> Result<String> {
+        let args = ["rev-parse", "--short=12", "HEAD"];
+        let mkerr = || {
+            let mut command = vec![OsString::from("git")];
+            command.extend(args.iter().map(OsString::from));
+            ErrorKind::CommandFailed(command)
+        };
+        let output = try!(process::Command::new("git")
+            .args(&args)
+            .current_dir(self.path(project))
+            .output()
+            .chain_err(&mkerr));
+        if !output.status.success() {
+            return Err(mkerr().into());
+        }
+        Ok(try!(String::from_utf8(output.stdout)).trim().to_owned())
+    }
+
     /// (Test mode only.) Pretend to clone the source code for this
     /// repository by creating an empty directory in the right place.
     #[cfg(test)]
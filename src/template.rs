@@ -7,11 +7,12 @@ use std::collections::BTreeMap;
 use std::fmt;
 use std::fs;
 use std::io;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use data;
 use errors::*;
-use util::ConductorPathExt;
+use util::{ConductorPathExt, ToStrOrErr};
 
 /// Escape double quotes and backslashes in a string that we're rendering,
 /// which should work well more-or-less well enough for all the formats
@@ -50,8 +51,7 @@ impl Template {
             // Does this file belong to our template?
             if key.starts_with(&prefix) {
                 let rel: &str = &key[prefix.len()..];
-                // Make sure it doesn't belong to a child template.
-                if !rel.starts_with('_') && !rel.contains("/_") {
+                if is_template_file(rel) {
                     // Load this file and add it to our list.
                     let raw_data = try!(data::DATA.get(key)).into_owned();
                     let data = try!(String::from_utf8(raw_data));
@@ -59,16 +59,49 @@ impl Template {
                 }
             }
         }
+        Ok(Template::from_files(name, files))
+    }
+
+    /// Is there a template named `name` built into this library?
+    pub fn is_builtin(name: &str) -> bool {
+        let prefix = format!("data/templates/{}/", name);
+        data::DATA.files.keys().any(|key| key.starts_with(&prefix))
+    }
+
+    /// Create a new template, loading it from the files under `dir` at
+    /// runtime.  This allows projects and users to supply their own
+    /// templates.
+    pub fn from_dir<S: Into<String>>(name: S, dir: &Path) -> Result<Template> {
+        let mut files = BTreeMap::new();
+        for path in try!(dir.glob("**/*")) {
+            let path = try!(path);
+            if !path.is_file() {
+                continue;
+            }
+            let rel = try!(path.strip_prefix(dir)
+                .map_err(|_| err!("{} is not in {}", path.display(), dir.display())));
+            if is_template_file(try!(rel.to_str_or_err())) {
+                let mkerr = || ErrorKind::CouldNotReadFile(path.clone());
+                let mut data = String::new();
+                let mut f = try!(fs::File::open(&path).chain_err(&mkerr));
+                try!(f.read_to_string(&mut data).chain_err(&mkerr));
+                files.insert(rel.to_owned(), data);
+            }
+        }
+        Ok(Template::from_files(name, files))
+    }
 
+    /// Create a new template from a list of files.
+    fn from_files<S: Into<String>>(name: S, files: BTreeMap<PathBuf, String>) -> Template {
         // Create our Handlebars template engine.
         let mut hb = hb::Handlebars::new();
         hb.register_escape_fn(escape_double_quotes);
 
-        Ok(Template {
-            name: name,
+        Template {
+            name: name.into(),
             files: files,
             handlebars: hb,
-        })
+        }
     }
 
     /// Generate this template into `target_dir`, passing `data` to the
@@ -85,7 +118,11 @@ impl Template {
         for (rel_path, tmpl) in &self.files {
             let path = target_dir.join(rel_path);
             debug!("Output {}", path.display());
-            try!(writeln!(out, "Generating: {}", rel_path.display()));
+            if path.exists() {
+                try!(writeln!(out, "Overwriting: {}", rel_path.display()));
+            } else {
+                try!(writeln!(out, "Generating: {}", rel_path.display()));
+            }
             let mkerr = || ErrorKind::CouldNotWriteFile(path.clone());
 
             // Make sure our parent directory exists.
@@ -105,6 +142,13 @@ impl Template {
     }
 }
 
+/// Should the file at `rel` (relative to the top of a template) be
+/// generated?  Files and directories starting with `_` belong to child
+/// templates or contain metadata about the template.
+fn is_template_file(rel: &str) -> bool {
+    !rel.starts_with('_') && !rel.contains("/_")
+}
+
 #[test]
 fn loads_correct_files_for_template() {
     let tmpl = Template::new("test_tmpl").unwrap();
@@ -112,3 +156,10 @@ fn loads_correct_files_for_template() {
     assert!(keys.contains(&Path::new("test.txt").to_owned()));
     assert!(!keys.contains(&Path::new("_child_tmpl/child.txt").to_owned()));
 }
+
+#[test]
+fn loads_template_from_dir() {
+    let tmpl = Template::from_dir("test_tmpl", Path::new("data/templates/test_tmpl")).unwrap();
+    let keys: Vec<_> = tmpl.files.keys().cloned().collect();
+    assert_eq!(keys, &[Path::new("test.txt").to_owned()]);
+}
@@ -6,7 +6,9 @@ use std::env;
 use std::error;
 use std::ffi::OsStr;
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
 
 use errors::*;
@@ -28,6 +30,82 @@ pub fn err(msg: &str) -> Error {
     msg.into()
 }
 
+/// Write `data` to a file at `path` which can only be read by the current
+/// user (at least on Unix), replacing any existing file.
+pub fn write_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    try!(path.with_guaranteed_parent().chain_err(&mkerr));
+    if fs::symlink_metadata(path).is_ok() {
+        // Remove the existing file (or symlink) so that we don't inherit
+        // its permissions or write through it.
+        try!(fs::remove_file(path).chain_err(&mkerr));
+    }
+    write_new_private_file(path, data)
+}
+
+/// Like `write_private_file`, but fail if anything already exists at
+/// `path`, including a symlink planted there by another user.  Use this
+/// in shared directories like `env::temp_dir()`.
+pub fn write_new_private_file(path: &Path, data: &[u8]) -> Result<()> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    make_private(&mut opts);
+    let mut f = try!(opts.open(path).chain_err(&mkerr));
+    try!(f.write_all(data).chain_err(&mkerr));
+    Ok(())
+}
+
+/// Write `data` to `path` unless the file already contains exactly
+/// `data`, so that we don't change its modification time needlessly.
+/// Returns `true` if we wrote the file.
+pub fn write_if_changed(path: &Path, data: &[u8]) -> Result<bool> {
+    let mkerr = || ErrorKind::CouldNotWriteFile(path.to_owned());
+    if path.exists() {
+        let mut existing = vec![];
+        let mut f = try!(fs::File::open(path).chain_err(&mkerr));
+        try!(f.read_to_end(&mut existing).chain_err(&mkerr));
+        if existing == data {
+            return Ok(false);
+        }
+    }
+    try!(path.with_guaranteed_parent().chain_err(&mkerr));
+    let mut f = try!(fs::File::create(path).chain_err(&mkerr));
+    try!(f.write_all(data).chain_err(&mkerr));
+    Ok(true)
+}
+
+/// Create the directory `path` and any missing parents, allowing only the
+/// current user to access any directories we create (at least on Unix).
+pub fn create_private_dir_all(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    make_private_dir(&mut builder);
+    builder.create(path).chain_err(|| ErrorKind::CouldNotWriteFile(path.to_owned()))
+}
+
+/// Only allow the current user to access directories created using
+/// `builder`.
+#[cfg(unix)]
+fn make_private_dir(builder: &mut fs::DirBuilder) {
+    builder.mode(0o700);
+}
+
+/// Pretend to restrict access to directories created using `builder`.
+#[cfg(not(unix))]
+fn make_private_dir(_builder: &mut fs::DirBuilder) {}
+
+/// Only allow the current user to access files created using `opts`.
+#[cfg(unix)]
+fn make_private(opts: &mut fs::OpenOptions) {
+    opts.mode(0o600);
+}
+
+/// Pretend to restrict access to files created using `opts`.  We don't
+/// know how to do this on non-Unix platforms yet.
+#[cfg(not(unix))]
+fn make_private(_opts: &mut fs::OpenOptions) {}
+
 /// Trait for things which we really hope are actually UTF-8 strings, and not
 /// something weird like [WTF-8](https://simonsapin.github.io/wtf-8/).
 pub trait ToStrOrErr {
@@ -136,3 +214,29 @@ fn path_glob_uses_path_as_base() {
         .collect();
     assert_eq!(paths, vec![Path::new("test/common.env")]);
 }
+
+#[test]
+#[cfg(unix)]
+fn private_files_and_dirs_are_only_accessible_by_owner() {
+    use rand::random;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = env::current_dir()
+        .unwrap()
+        .join("target")
+        .join("test_output")
+        .join(format!("private-{}", random::<u16>()));
+    let file = dir.join("secret");
+    create_private_dir_all(&dir).unwrap();
+    write_private_file(&file, b"magic").unwrap();
+
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&dir), 0o700);
+    assert_eq!(mode(&file), 0o600);
+
+    // We never write through a file (or symlink) that already exists.
+    assert!(write_new_private_file(&file, b"more magic").is_err());
+    write_private_file(&file, b"more magic").unwrap();
+    assert_eq!(mode(&file), 0o600);
+    fs::remove_dir_all(&dir).unwrap();
+}
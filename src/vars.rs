@@ -0,0 +1,257 @@
+//! Variables which can be interpolated into pod files, loaded from
+//! `config/vars.yml`.
+
+use compose_yml::v2 as dc;
+use serde_yaml;
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use yaml_rust::Yaml;
+
+use errors::*;
+use pod::Pod;
+use project::Project;
+use serde_helpers::load_yaml;
+
+#[cfg(feature = "serde_derive")]
+include!(concat!("vars_config.in.rs"));
+#[cfg(feature = "serde_codegen")]
+include!(concat!(env!("OUT_DIR"), "/vars_config.rs"));
+
+/// Variables which we always define, and which may not be redefined in
+/// `config/vars.yml`.  `SERVICE` is only available inside a service
+/// definition.
+const BUILT_IN_VARS: &'static [&'static str] = &["PROJECT", "TARGET", "POD", "SERVICE"];
+
+/// Variables loaded from `config/vars.yml`.  When this file is present,
+/// we interpolate these variables (plus the ones in `BUILT_IN_VARS`)
+/// into our pod files when generating output, using the same `$VAR`,
+/// `${VAR}` and `${VAR:-default}` syntax as `docker-compose`.  Any
+/// variable we can't resolve is an error, so a literal `$` must be
+/// written as `$$`.
+#[derive(Debug, Default)]
+pub struct Vars {
+    /// Our parsed config file, or `None` if this project doesn't have
+    /// one, in which case we leave pod files unchanged and let
+    /// `docker-compose` interpolate them from the shell environment.
+    config: Option<VarsConfig>,
+}
+
+impl Vars {
+    /// The path to our config file.
+    fn config_path(root_dir: &Path) -> PathBuf {
+        root_dir.join("config").join("vars.yml")
+    }
+
+    /// Load the variables for the project in `root_dir`, if it defines
+    /// any.
+    pub fn new(root_dir: &Path) -> Result<Vars> {
+        let path = Self::config_path(root_dir);
+        if !path.exists() {
+            return Ok(Vars { config: None });
+        }
+        let config: VarsConfig = try!(load_yaml(&path));
+        let all_names = config.common
+            .keys()
+            .chain(config.targets.values().flat_map(|defs| defs.keys()));
+        for name in all_names {
+            if BUILT_IN_VARS.contains(&name.as_str()) {
+                return Err(err!("{} may not redefine the built-in variable ${}",
+                                path.display(),
+                                name));
+            }
+        }
+        Ok(Vars { config: Some(config) })
+    }
+
+    /// Interpolate our variables into `file`, which was loaded from
+    /// `pod`, using the project's current target.
+    pub fn interpolate(&self,
+                       project: &Project,
+                       pod: &Pod,
+                       file: &mut dc::File)
+                       -> Result<()> {
+        let config = match self.config {
+            Some(ref config) => config,
+            None => return Ok(()),
+        };
+
+        // Figure out what variables to use for this pod.
+        let target = project.current_target().name();
+        let mut vars = config.common.clone();
+        if let Some(overrides) = config.targets.get(target) {
+            vars.extend(overrides.clone());
+        }
+        vars.insert("PROJECT".to_owned(), project.name().to_owned());
+        vars.insert("TARGET".to_owned(), target.to_owned());
+        vars.insert("POD".to_owned(), pod.name().to_owned());
+
+        // `dc::File` has a great many `RawOr` fields, so rather than
+        // visiting each one, we convert the whole file to YAML, update
+        // all the strings, and parse it again.  This also parses any
+        // fields which are now free of variables.
+        let mut yaml = serde_yaml::to_value(file);
+        if let Yaml::Hash(ref mut top) = yaml {
+            for (key, value) in top.iter_mut() {
+                if key.as_str() != Some("services") {
+                    try!(interpolate_yaml(value, &vars));
+                } else if let Yaml::Hash(ref mut services) = *value {
+                    for (name, service) in services.iter_mut() {
+                        let mut service_vars = vars.clone();
+                        if let Some(name) = name.as_str() {
+                            service_vars.insert("SERVICE".to_owned(), name.to_owned());
+                        }
+                        try!(interpolate_yaml(service, &service_vars));
+                    }
+                }
+            }
+        }
+        *file = try!(serde_yaml::from_value(yaml));
+        Ok(())
+    }
+}
+
+/// Interpolate `vars` into all the strings in `value`, but not into any
+/// hash keys.
+fn interpolate_yaml(value: &mut Yaml, vars: &BTreeMap<String, String>) -> Result<()> {
+    match *value {
+        Yaml::String(ref mut s) => {
+            let interpolated = try!(interpolate_str(s, vars));
+            *s = interpolated;
+        }
+        Yaml::Array(ref mut items) => {
+            for item in items {
+                try!(interpolate_yaml(item, vars));
+            }
+        }
+        Yaml::Hash(ref mut hash) => {
+            for (_, item) in hash.iter_mut() {
+                try!(interpolate_yaml(item, vars));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Is `c` allowed in a variable name?
+fn is_var_char(c: char) -> bool {
+    c.is_digit(36) || c == '_'
+}
+
+/// Interpolate `vars` into `input`.  Any `$` characters in the output
+/// are escaped as `$$`, so that `docker-compose` will treat them
+/// literally.  Exporters for other formats undo this escape when they
+/// read services using `export::ServiceInfo`.
+fn interpolate_str(input: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mkerr = || ErrorKind::InvalidInterpolation(input.to_owned());
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(idx) = rest.find('$') {
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        // Parse our variable reference.
+        let (name, default) = if rest.starts_with('$') {
+            result.push_str("$$");
+            rest = &rest[1..];
+            continue;
+        } else if rest.starts_with('{') {
+            let close = try!(rest.find('}').ok_or_else(&mkerr));
+            let body = &rest[1..close];
+            rest = &rest[close + 1..];
+            match body.find(":-") {
+                Some(sep) => (&body[..sep], Some(&body[sep + 2..])),
+                None => (body, None),
+            }
+        } else {
+            let len = rest.find(|c| !is_var_char(c)).unwrap_or(rest.len());
+            let name = &rest[..len];
+            rest = &rest[len..];
+            (name, None)
+        };
+        if name.is_empty() || !name.chars().all(is_var_char) ||
+           name.starts_with(|c: char| c.is_digit(10)) {
+            return Err(mkerr().into());
+        }
+
+        // Look up our value, falling back to the default if the variable
+        // is either undefined or empty.
+        let value = match (vars.get(name).map(|v| v.as_str()), default) {
+            (Some(value), Some(default)) if value.is_empty() => default,
+            (Some(value), _) => value,
+            (None, Some(default)) => default,
+            (None, None) => {
+                return Err(ErrorKind::UndefinedVariable(name.to_owned()).into())
+            }
+        };
+        result.push_str(&value.replace("$", "$$"));
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[test]
+fn interpolate_str_handles_docker_compose_syntax() {
+    let mut vars = BTreeMap::new();
+    vars.insert("NAME".to_owned(), "world".to_owned());
+    vars.insert("EMPTY".to_owned(), "".to_owned());
+    vars.insert("PRICE".to_owned(), "$5".to_owned());
+
+    let interp = |s| interpolate_str(s, &vars).unwrap();
+    assert_eq!(interp("hello"), "hello");
+    assert_eq!(interp("hello $NAME!"), "hello world!");
+    assert_eq!(interp("hello ${NAME}s"), "hello worlds");
+    assert_eq!(interp("${MISSING:-default}"), "default");
+    assert_eq!(interp("${EMPTY:-default}"), "default");
+    assert_eq!(interp("${NAME:-default}"), "world");
+    assert_eq!(interp("$$NAME"), "$$NAME");
+    assert_eq!(interp("$PRICE"), "$$5");
+
+    assert!(interpolate_str("$MISSING", &vars).is_err());
+    assert!(interpolate_str("${NAME", &vars).is_err());
+    assert!(interpolate_str("${}", &vars).is_err());
+    assert!(interpolate_str("$ alone", &vars).is_err());
+}
+
+#[test]
+fn interpolates_vars_into_pod_files() {
+    use env_logger;
+    use ext::port_mapping::PortMappingExt;
+    let _ = env_logger::init();
+
+    let mut proj = Project::from_example("rails_hello").unwrap();
+    proj.set_current_target_name("production").unwrap();
+    let vars = Vars::new(proj.root_dir()).unwrap();
+    let pod = proj.pod("frontend").unwrap();
+
+    let mut file = dc::File::from_str(r#"---
+version: "2"
+services:
+  web:
+    image: "${PROJECT}/$SERVICE"
+    ports:
+    - "${RAILS_PORT:-3000}:3000"
+    labels:
+      pod: "$POD in $TARGET"
+"#)
+        .unwrap();
+    vars.interpolate(&proj, pod, &mut file).unwrap();
+
+    let web = file.services.get("web").unwrap();
+    assert_eq!(web.image.as_ref().unwrap().value().unwrap(),
+               &dc::Image::new("rails_hello/web").unwrap());
+    let port = web.ports[0].value().unwrap();
+    assert_eq!(port.host_string().unwrap(), "80");
+    assert_eq!(web.labels.get("pod").unwrap(), "frontend in production");
+
+    let mut bad = dc::File::from_str(r#"---
+version: "2"
+services:
+  web:
+    image: "$NO_SUCH_VAR"
+"#)
+        .unwrap();
+    assert!(vars.interpolate(&proj, pod, &mut bad).is_err());
+}
@@ -0,0 +1,17 @@
+// This is not a standalone Rust module.  It gets processed by serde to
+// generate serialization code and included directly into another module.
+
+/// A set of variable definitions.
+type VarDefs = BTreeMap<String, String>;
+
+/// The deserialized form of `config/vars.yml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VarsConfig {
+    /// Variables shared by all targets.
+    #[serde(default)]
+    common: VarDefs,
+    /// Per-target variables, which override those in `common`.
+    #[serde(default)]
+    targets: BTreeMap<String, VarDefs>,
+}
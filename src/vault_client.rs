@@ -0,0 +1,268 @@
+//! A minimal client for the parts of Vault's HTTP API which aren't
+//! supported by the `hashicorp_vault` crate.
+
+use hyper;
+use hyper::header::{ContentType, Headers};
+use hyper::method::Method;
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use errors::*;
+use util::err;
+
+/// A client for a Vault server.
+#[derive(Debug)]
+pub struct Client {
+    /// The address of our Vault server, without a trailing `/`.
+    addr: String,
+    /// The token we use to authenticate our requests, if any.
+    token: Option<String>,
+}
+
+impl Client {
+    /// Create a new client for the server at `addr`, which will use
+    /// `token` to authenticate (if present).
+    pub fn new<S: Into<String>>(addr: S, token: Option<String>) -> Client {
+        let mut addr = addr.into();
+        while addr.ends_with('/') {
+            addr.pop();
+        }
+        Client {
+            addr: addr,
+            token: token,
+        }
+    }
+
+    /// Log in using AppRole credentials, and return a client which uses
+    /// the resulting token.
+    pub fn approle_login(addr: &str, role_id: &str, secret_id: &str) -> Result<Client> {
+        let anonymous = Client::new(addr, None);
+        let mut body = BTreeMap::new();
+        body.insert("role_id".to_owned(), Json::String(role_id.to_owned()));
+        body.insert("secret_id".to_owned(), Json::String(secret_id.to_owned()));
+        let response = try!(anonymous.request(Method::Post,
+                                              "auth/approle/login",
+                                              Some(Json::Object(body))));
+        let token = try!(string_at(&response, &["auth", "client_token"]));
+        Ok(Client::new(anonymous.addr, Some(token)))
+    }
+
+    /// The token used by this client, if any.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_ref().map(|t| t.as_str())
+    }
+
+    /// Look up the `role_id` associated with an AppRole.
+    pub fn approle_role_id(&self, role: &str) -> Result<String> {
+        let path = try!(approle_path(role, "role-id"));
+        let response = try!(self.request(Method::Get, &path, None));
+        string_at(&response, &["data", "role_id"])
+    }
+
+    /// Generate a new `secret_id` for an AppRole, attaching the specified
+    /// metadata.
+    pub fn approle_generate_secret_id(&self,
+                                      role: &str,
+                                      metadata: &BTreeMap<String, String>)
+                                      -> Result<String> {
+        // Vault expects metadata to be a JSON-encoded string.
+        let metadata = metadata.iter()
+            .map(|(k, v)| (k.to_owned(), Json::String(v.to_owned())))
+            .collect();
+        let mut body = BTreeMap::new();
+        body.insert("metadata".to_owned(),
+                    Json::String(Json::Object(metadata).to_string()));
+        let path = try!(approle_path(role, "secret-id"));
+        let response = try!(self.request(Method::Post, &path, Some(Json::Object(body))));
+        string_at(&response, &["data", "secret_id"])
+    }
+
+    /// Renew `token` for `increment` seconds, returning the new lease
+    /// duration in seconds.
+    pub fn renew_token(&self, token: &str, increment: u64) -> Result<u64> {
+        let mut body = BTreeMap::new();
+        body.insert("token".to_owned(), Json::String(token.to_owned()));
+        body.insert("increment".to_owned(), Json::U64(increment));
+        let response = try!(self.request(Method::Post,
+                                         "auth/token/renew",
+                                         Some(Json::Object(body))));
+        response.find_path(&["auth", "lease_duration"])
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| err("expected auth.lease_duration in Vault response"))
+    }
+
+    /// Revoke `token` and all of its children.
+    pub fn revoke_token(&self, token: &str) -> Result<()> {
+        let mut body = BTreeMap::new();
+        body.insert("token".to_owned(), Json::String(token.to_owned()));
+        try!(self.request(Method::Post, "auth/token/revoke", Some(Json::Object(body))));
+        Ok(())
+    }
+
+    /// Revoke the token used by this client.
+    pub fn revoke_self(&self) -> Result<()> {
+        try!(self.request(Method::Post, "auth/token/revoke-self", None));
+        Ok(())
+    }
+
+    /// Read the secret stored at `path`, returning the fields of the
+    /// secret as JSON.
+    pub fn read_secret(&self, path: &str) -> Result<Json> {
+        let path = path.trim_matches('/');
+        let response = try!(self.request(Method::Get, path, None));
+        response.find("data")
+            .cloned()
+            .ok_or_else(|| err!("expected data in Vault response for {}", path))
+    }
+
+    /// Make a request to `/v1/<path>` and parse the JSON response.
+    fn request(&self, method: Method, path: &str, body: Option<Json>) -> Result<Json> {
+        let mkerr = || ErrorKind::VaultError(self.addr.clone());
+        let url = format!("{}/v1/{}", self.addr, path);
+        debug!("Vault request: {} {}", method, url);
+
+        let mut headers = Headers::new();
+        headers.set(ContentType::json());
+        if let Some(ref token) = self.token {
+            headers.set_raw("X-Vault-Token", vec![token.as_bytes().to_owned()]);
+        }
+        let body = body.map_or_else(|| "".to_owned(), |b| b.to_string());
+
+        let client = hyper::Client::new();
+        let mut res = try!(client.request(method, &url)
+            .headers(headers)
+            .body(&body[..])
+            .send()
+            .chain_err(&mkerr));
+        let mut text = String::new();
+        try!(res.read_to_string(&mut text).chain_err(&mkerr));
+        if !res.status.is_success() {
+            return Err(err!("{} returned {}: {}", url, res.status, text.trim()))
+                .chain_err(&mkerr);
+        }
+        if text.trim().is_empty() {
+            // Some endpoints return `204 No Content`.
+            return Ok(Json::Null);
+        }
+        Json::from_str(&text)
+            .map_err(|e| err!("could not parse response from {}: {}", url, e))
+            .chain_err(&mkerr)
+    }
+}
+
+/// Look up the string at `path` in `json`.
+fn string_at(json: &Json, path: &[&str]) -> Result<String> {
+    json.find_path(path)
+        .and_then(|v| v.as_string())
+        .map(|s| s.to_owned())
+        .ok_or_else(|| err!("expected {} in Vault response", path.join(".")))
+}
+
+/// Build the path to `endpoint` for the AppRole named `role`.  We insert
+/// `role` directly into a URL path, so we only allow the names which Vault
+/// itself allows: word characters, plus `-` and `.` in the middle.
+fn approle_path(role: &str, endpoint: &str) -> Result<String> {
+    let is_word = |c: char| c.is_digit(36) || c == '_';
+    let valid = role.starts_with(&is_word) && role.ends_with(&is_word) &&
+                role.chars().all(|c| is_word(c) || c == '-' || c == '.');
+    if !valid {
+        return Err(err!("invalid Vault AppRole name: {:?}", role));
+    }
+    Ok(format!("auth/approle/role/{}/{}", role, endpoint))
+}
+
+#[test]
+fn rejects_invalid_approle_names() {
+    assert_eq!(approle_path("my.app-production_web", "role-id").unwrap(),
+               "auth/approle/role/my.app-production_web/role-id");
+    for role in &["", "..", "-a", "a/b", "a?b", "a b", "caf\u{e9}"] {
+        assert!(approle_path(role, "role-id").is_err());
+    }
+}
+
+#[test]
+fn approle_requests_are_sent_to_vault() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    server.respond("POST",
+                   "/v1/auth/approle/login",
+                   200,
+                   r#"{"auth":{"client_token":"master"}}"#);
+    server.respond("GET",
+                   "/v1/auth/approle/role/myrole/role-id",
+                   200,
+                   r#"{"data":{"role_id":"role-123"}}"#);
+    server.respond("POST",
+                   "/v1/auth/approle/role/myrole/secret-id",
+                   200,
+                   r#"{"data":{"secret_id":"secret-456"}}"#);
+
+    let client = Client::approle_login(server.url(), "ci-role", "ci-secret").unwrap();
+    assert_eq!(client.token(), Some("master"));
+    assert_eq!(client.approle_role_id("myrole").unwrap(), "role-123");
+    let mut metadata = BTreeMap::new();
+    metadata.insert("display_name".to_owned(), "web".to_owned());
+    assert_eq!(client.approle_generate_secret_id("myrole", &metadata).unwrap(),
+               "secret-456");
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 3);
+    let login = Json::from_str(&requests[0].body).unwrap();
+    assert_eq!(string_at(&login, &["role_id"]).unwrap(), "ci-role");
+    assert_eq!(string_at(&login, &["secret_id"]).unwrap(), "ci-secret");
+    assert_eq!(requests[0].headers.get("x-vault-token"), None);
+    assert_eq!(requests[1].headers.get("x-vault-token").unwrap(), "master");
+    assert_eq!(requests[2].headers.get("x-vault-token").unwrap(), "master");
+    let secret_id_req = Json::from_str(&requests[2].body).unwrap();
+    assert_eq!(string_at(&secret_id_req, &["metadata"]).unwrap(),
+               r#"{"display_name":"web"}"#);
+
+    // Errors should be reported.
+    assert!(client.approle_role_id("missing").is_err());
+}
+
+#[test]
+fn secrets_can_be_read() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    server.respond("GET",
+                   "/v1/secret/myapp/db",
+                   200,
+                   r#"{"lease_duration":2592000,"data":{"password":"pw"}}"#);
+
+    let client = Client::new(format!("{}/", server.url()), Some("token".to_owned()));
+    let secret = client.read_secret("/secret/myapp/db").unwrap();
+    assert_eq!(string_at(&secret, &["password"]).unwrap(), "pw");
+    assert_eq!(server.requests()[0].headers.get("x-vault-token").unwrap(),
+               "token");
+    assert!(client.read_secret("secret/missing").is_err());
+}
+
+#[test]
+fn tokens_can_be_renewed_and_revoked() {
+    use mock_http::MockServer;
+
+    let server = MockServer::new();
+    server.respond("POST",
+                   "/v1/auth/token/renew",
+                   200,
+                   r#"{"auth":{"client_token":"child","lease_duration":3600}}"#);
+    server.respond("POST", "/v1/auth/token/revoke", 204, "");
+    server.respond("POST", "/v1/auth/token/revoke-self", 204, "");
+
+    let client = Client::new(server.url(), Some("master".to_owned()));
+    assert_eq!(client.renew_token("child", 3600).unwrap(), 3600);
+    client.revoke_token("child").unwrap();
+    client.revoke_self().unwrap();
+
+    let requests = server.requests();
+    let renew = Json::from_str(&requests[0].body).unwrap();
+    assert_eq!(string_at(&renew, &["token"]).unwrap(), "child");
+    assert_eq!(renew.find("increment").and_then(|v| v.as_u64()), Some(3600));
+    assert_eq!(requests[1].path, "/v1/auth/token/revoke");
+    assert_eq!(requests[2].path, "/v1/auth/token/revoke-self");
+    assert_eq!(requests[2].headers.get("x-vault-token").unwrap(), "master");
+}